@@ -0,0 +1,401 @@
+//! Omron 2SMPB-02B digital barometric pressure sensor.
+//!
+//! The sensor provides raw 24 bit temperature and pressure values that are compensated with
+//! coefficients from the sensor's OTP memory.
+
+use embedded_hal_async::delay::DelayNs;
+use embedded_hal_async::i2c::I2c;
+use embedded_hal_async::spi::Operation;
+use embedded_hal_async::spi::SpiDevice;
+
+/// I2C address with SDO connected to GND.
+pub const I2C_ADDRESS_SDO_LOW: u8 = 0x70;
+/// I2C address with SDO connected to VDD.
+pub const I2C_ADDRESS_SDO_HIGH: u8 = 0x56;
+
+const CHIP_ID: u8 = 0x5c;
+const RESET_COMMAND: u8 = 0xe6;
+const OTP_LEN: usize = 25;
+
+/// Standard sea level pressure [Pa].
+pub const SEA_LEVEL_PRESSURE: f32 = 101_325.0;
+
+mod register {
+    pub const COE_B00_1: u8 = 0xa0;
+    pub const CHIP_ID: u8 = 0xd1;
+    pub const RESET: u8 = 0xe0;
+    pub const IIR_CNT: u8 = 0xf1;
+    pub const CTRL_MEAS: u8 = 0xf4;
+    pub const IO_SETUP: u8 = 0xf5;
+    pub const PRESS_TXD2: u8 = 0xf7;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    Bus(E),
+    /// Unexpected chip id, i.e., there is no 2SMPB-02B at that address.
+    ChipId(u8),
+}
+
+/// Register access over I2C or SPI.
+#[allow(async_fn_in_trait)]
+pub trait Interface {
+    type Error;
+
+    async fn read(&mut self, register: u8, buf: &mut [u8]) -> Result<(), Self::Error>;
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), Self::Error>;
+}
+
+pub struct I2cInterface<I> {
+    i2c: I,
+    address: u8,
+}
+
+impl<I: I2c> I2cInterface<I> {
+    pub fn new(i2c: I, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<I: I2c> Interface for I2cInterface<I> {
+    type Error = I::Error;
+
+    async fn read(&mut self, register: u8, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.i2c.write_read(self.address, &[register], buf).await
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+        self.i2c.write(self.address, &[register, value]).await
+    }
+}
+
+/// 4-wire SPI interface, the MSB of the register address selects reading.
+pub struct SpiInterface<S> {
+    spi: S,
+}
+
+impl<S: SpiDevice> SpiInterface<S> {
+    pub fn new(spi: S) -> Self {
+        Self { spi }
+    }
+}
+
+impl<S: SpiDevice> Interface for SpiInterface<S> {
+    type Error = S::Error;
+
+    async fn read(&mut self, register: u8, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.spi
+            .transaction(&mut [Operation::Write(&[register | 0x80]), Operation::Read(buf)])
+            .await
+    }
+
+    async fn write(&mut self, register: u8, value: u8) -> Result<(), Self::Error> {
+        self.spi.write(&[register & 0x7f, value]).await
+    }
+}
+
+/// Number of averaged samples per measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Averaging {
+    Skip = 0b000,
+    X1 = 0b001,
+    X2 = 0b010,
+    X4 = 0b011,
+    X8 = 0b100,
+    X16 = 0b101,
+    X32 = 0b110,
+}
+
+/// Coefficient of the internal IIR filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Off = 0b000,
+    N2 = 0b001,
+    N4 = 0b010,
+    N8 = 0b011,
+    N16 = 0b100,
+    N32 = 0b101,
+}
+
+/// Standby time between measurements in normal mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Standby {
+    Ms1 = 0b000,
+    Ms5 = 0b001,
+    Ms50 = 0b010,
+    Ms250 = 0b011,
+    Ms500 = 0b100,
+    Ms1000 = 0b101,
+    Ms2000 = 0b110,
+    Ms4000 = 0b111,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub temperature_averaging: Averaging,
+    pub pressure_averaging: Averaging,
+    pub filter: Filter,
+    pub standby: Standby,
+}
+
+impl Default for Config {
+    /// Continuous measurements at roughly 50 Hz.
+    fn default() -> Self {
+        Self {
+            temperature_averaging: Averaging::X1,
+            pressure_averaging: Averaging::X8,
+            filter: Filter::N4,
+            standby: Standby::Ms1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Measurement {
+    pub temperature: f32, // [degC]
+    pub pressure: f32,    // [Pa]
+}
+
+/// Compensation coefficients from the OTP memory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    a0: f64,
+    a1: f64,
+    a2: f64,
+    b00: f64,
+    bt1: f64,
+    bt2: f64,
+    bp1: f64,
+    b11: f64,
+    bp2: f64,
+    b12: f64,
+    b21: f64,
+    bp3: f64,
+}
+
+impl Coefficients {
+    /// Converts the 25 bytes of OTP memory starting at `COE_b00_1` (0xa0).
+    pub fn from_otp(otp: &[u8; OTP_LEN]) -> Self {
+        let ex = otp[24];
+        // K = A + S * OTP / 32767 with the conversion factors of the datasheet.
+        let k = |a: f64, s: f64, offset: usize| {
+            let raw = i16::from_be_bytes([otp[offset], otp[offset + 1]]);
+            a + s * raw as f64 / 32767.0
+        };
+        // 20 bit two's complement with 4 fractional bits.
+        let q4 = |offset: usize, low: u8| {
+            let raw = ((otp[offset] as u32) << 12) | ((otp[offset + 1] as u32) << 4) | low as u32;
+            ((raw << 12) as i32 >> 12) as f64 / 16.0
+        };
+        Self {
+            b00: q4(0, ex >> 4),
+            bt1: k(1.0e-01, 9.1e-02, 2),
+            bt2: k(1.2e-08, 1.2e-06, 4),
+            bp1: k(3.3e-02, 1.9e-02, 6),
+            b11: k(2.1e-07, 1.4e-07, 8),
+            bp2: k(-6.3e-10, 3.5e-10, 10),
+            b12: k(2.9e-13, 7.6e-13, 12),
+            b21: k(2.1e-15, 1.2e-14, 14),
+            bp3: k(1.3e-16, 7.9e-17, 16),
+            a0: q4(18, ex & 0x0f),
+            a1: k(-6.3e-03, 4.3e-04, 20),
+            a2: k(-1.9e-11, 1.2e-10, 22),
+        }
+    }
+
+    /// Compensates raw values (as returned by `raw_value`).
+    pub fn compensate(&self, raw_temperature: i32, raw_pressure: i32) -> Measurement {
+        let dt = raw_temperature as f64;
+        let dp = raw_pressure as f64;
+        // Temperature in 1/256 degC.
+        let tr = self.a0 + self.a1 * dt + self.a2 * dt * dt;
+        let pr = self.b00
+            + self.bt1 * tr
+            + self.bp1 * dp
+            + self.b11 * dp * tr
+            + self.bt2 * tr * tr
+            + self.bp2 * dp * dp
+            + self.b12 * dp * tr * tr
+            + self.b21 * dp * dp * tr
+            + self.bp3 * dp * dp * dp;
+        Measurement {
+            temperature: (tr / 256.0) as f32,
+            pressure: pr as f32,
+        }
+    }
+}
+
+/// Converts a 24 bit `TXD2..TXD0` register triple to a signed raw value.
+pub fn raw_value(txd: [u8; 3]) -> i32 {
+    (((txd[0] as i32) << 16) | ((txd[1] as i32) << 8) | txd[2] as i32) - (1 << 23)
+}
+
+/// Altitude [m] above the level at which the pressure is `reference_pressure` [Pa],
+/// using the international standard atmosphere.
+pub fn pressure_altitude(pressure: f32, reference_pressure: f32) -> f32 {
+    44_330.77 * (1.0 - libm::powf(pressure / reference_pressure, 0.190_263))
+}
+
+pub struct Baro2smpb<I> {
+    interface: I,
+    coefficients: Coefficients,
+}
+
+impl<I: Interface> Baro2smpb<I> {
+    /// Resets the sensor, reads its coefficients and starts continuous measurements.
+    pub async fn new(
+        mut interface: I,
+        config: Config,
+        delay: &mut impl DelayNs,
+    ) -> Result<Self, Error<I::Error>> {
+        let mut chip_id = [0u8];
+        interface
+            .read(register::CHIP_ID, &mut chip_id)
+            .await
+            .map_err(Error::Bus)?;
+        if chip_id[0] != CHIP_ID {
+            return Err(Error::ChipId(chip_id[0]));
+        }
+        interface
+            .write(register::RESET, RESET_COMMAND)
+            .await
+            .map_err(Error::Bus)?;
+        delay.delay_ms(10).await;
+
+        let mut otp = [0u8; OTP_LEN];
+        interface
+            .read(register::COE_B00_1, &mut otp)
+            .await
+            .map_err(Error::Bus)?;
+        let coefficients = Coefficients::from_otp(&otp);
+
+        interface
+            .write(register::IIR_CNT, config.filter as u8)
+            .await
+            .map_err(Error::Bus)?;
+        interface
+            .write(register::IO_SETUP, (config.standby as u8) << 5)
+            .await
+            .map_err(Error::Bus)?;
+        // Normal mode (continuous measurements).
+        let ctrl_meas = ((config.temperature_averaging as u8) << 5)
+            | ((config.pressure_averaging as u8) << 2)
+            | 0b11;
+        interface
+            .write(register::CTRL_MEAS, ctrl_meas)
+            .await
+            .map_err(Error::Bus)?;
+
+        Ok(Self {
+            interface,
+            coefficients,
+        })
+    }
+
+    pub fn coefficients(&self) -> &Coefficients {
+        &self.coefficients
+    }
+
+    /// Reads the latest temperature compensated measurement.
+    pub async fn read(&mut self) -> Result<Measurement, Error<I::Error>> {
+        let mut data = [0u8; 6];
+        self.interface
+            .read(register::PRESS_TXD2, &mut data)
+            .await
+            .map_err(Error::Bus)?;
+        let raw_pressure = raw_value([data[0], data[1], data[2]]);
+        let raw_temperature = raw_value([data[3], data[4], data[5]]);
+        Ok(self.coefficients.compensate(raw_temperature, raw_pressure))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_values() {
+        assert_eq!(raw_value([0x80, 0x00, 0x00]), 0);
+        assert_eq!(raw_value([0xff, 0xff, 0xff]), (1 << 23) - 1);
+        assert_eq!(raw_value([0x00, 0x00, 0x00]), -(1 << 23));
+    }
+
+    #[test]
+    fn coefficient_conversion() {
+        let mut otp = [0u8; OTP_LEN];
+        // b00 = -1/16 (all 20 bits set), a0 = 0x12345 / 16.
+        otp[0] = 0xff;
+        otp[1] = 0xff;
+        otp[18] = 0x12;
+        otp[19] = 0x34;
+        otp[24] = 0xf5;
+        // bt1 at the upper end of its range, a1 at the lower end.
+        otp[2] = 0x7f;
+        otp[3] = 0xff;
+        otp[20] = 0x80;
+        otp[21] = 0x00;
+
+        let c = Coefficients::from_otp(&otp);
+        assert_eq!(c.b00, -1.0 / 16.0);
+        assert_eq!(c.a0, 0x12345 as f64 / 16.0);
+        assert!((c.bt1 - (1.0e-01 + 9.1e-02)).abs() < 1e-12);
+        assert!((c.a1 - (-6.3e-03 - 4.3e-04 * 32768.0 / 32767.0)).abs() < 1e-12);
+        // Zero OTP values yield the offsets of the conversion table.
+        assert_eq!(c.bt2, 1.2e-08);
+        assert_eq!(c.bp3, 1.3e-16);
+        assert_eq!(c.a2, -1.9e-11);
+    }
+
+    #[test]
+    fn compensation() {
+        let c = Coefficients {
+            a0: 25.0 * 256.0,
+            a1: -6.3e-03,
+            a2: 1.0e-11,
+            b00: 100_000.0,
+            bt1: 0.1,
+            bt2: 0.0,
+            bp1: 0.04,
+            b11: 0.0,
+            bp2: 0.0,
+            b12: 0.0,
+            b21: 0.0,
+            bp3: 0.0,
+        };
+        let m = c.compensate(0, 0);
+        assert!((m.temperature - 25.0).abs() < 1e-4);
+        assert!((m.pressure - 100_640.0).abs() < 1e-2);
+
+        // Tr = 6400 - 630 + 0.1
+        let m = c.compensate(100_000, 10_000);
+        assert!((m.temperature - 5770.1 / 256.0).abs() < 1e-4);
+        assert!((m.pressure - (100_000.0 + 577.01 + 400.0)).abs() < 1e-2);
+    }
+
+    #[test]
+    fn otp_to_measurement() {
+        // Every coefficient nonzero, the reference values are evaluated separately from the
+        // conversion table and the compensation formulas of the datasheet.
+        let otp = [
+            0x5a, 0x3c, 0xd5, 0x08, 0xfe, 0xd4, 0x11, 0x94, 0xf6, 0x3c, 0x03, 0x84, 0xff, 0x38,
+            0x00, 0x96, 0xfd, 0xa8, 0x16, 0xb2, 0xfb, 0x50, 0x0b, 0xb8, 0x7e,
+        ];
+        let c = Coefficients::from_otp(&otp);
+        assert_eq!(c.b00, 23_100.437_5);
+        assert_eq!(c.a0, 5_810.875);
+
+        let raw_temperature = raw_value([0x7d, 0xb6, 0x10]);
+        let raw_pressure = raw_value([0xa0, 0xce, 0x70]);
+        assert_eq!((raw_temperature, raw_pressure), (-150_000, 2_150_000));
+        let m = c.compensate(raw_temperature, raw_pressure);
+        assert!((m.temperature - 26.398_66).abs() < 1e-4);
+        assert!((m.pressure - 101_531.18).abs() < 0.05);
+    }
+
+    #[test]
+    fn standard_atmosphere_altitude() {
+        assert!(pressure_altitude(SEA_LEVEL_PRESSURE, SEA_LEVEL_PRESSURE).abs() < 1e-3);
+        assert!((pressure_altitude(89_874.6, SEA_LEVEL_PRESSURE) - 1000.0).abs() < 0.5);
+        assert!((pressure_altitude(79_495.2, SEA_LEVEL_PRESSURE) - 2000.0).abs() < 0.5);
+    }
+}
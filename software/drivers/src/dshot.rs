@@ -0,0 +1,284 @@
+//! DShot digital ESC protocol.
+//!
+//! A frame consists of an 11 bit value (0: stop, 1..=47: special commands, 48..=2047:
+//! throttle), a telemetry request bit and a 4 bit checksum, sent MSB first. Each bit is a pulse
+//! whose width encodes its value.
+//!
+//! With bidirectional DShot, the signal is inverted (idle high) and the checksum is inverted.
+//! The ESC then answers each frame on the same pin with its eRPM: 16 bits GCR-encoded to 21
+//! bit levels (including a low start bit), sent at 5/4 of the frame bit rate.
+
+pub const MIN_THROTTLE: u16 = 48;
+pub const MAX_THROTTLE: u16 = 2047;
+
+/// Number of duty cycle slots per frame: 16 bits and two low slots to end the frame.
+pub const FRAME_SLOTS: usize = 18;
+
+/// Special commands, only accepted by the ESC while the motors are stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    MotorStop = 0,
+    Beep1 = 1,
+    Beep2 = 2,
+    Beep3 = 3,
+    Beep4 = 4,
+    Beep5 = 5,
+    EscInfo = 6,
+    SpinDirection1 = 7,
+    SpinDirection2 = 8,
+    Mode3dOff = 9,
+    Mode3dOn = 10,
+    SettingsRequest = 11,
+    SaveSettings = 12,
+    ExtendedTelemetryEnable = 13,
+    ExtendedTelemetryDisable = 14,
+    SpinDirectionNormal = 20,
+    SpinDirectionReversed = 21,
+}
+
+impl Command {
+    /// How often the command needs to be sent in a row to be accepted.
+    pub fn repeat(self) -> u32 {
+        match self {
+            Command::SpinDirection1
+            | Command::SpinDirection2
+            | Command::Mode3dOff
+            | Command::Mode3dOn
+            | Command::SaveSettings
+            | Command::ExtendedTelemetryEnable
+            | Command::ExtendedTelemetryDisable
+            | Command::SpinDirectionNormal
+            | Command::SpinDirectionReversed => 6,
+            _ => 1,
+        }
+    }
+
+    /// Time the ESC needs after the command before accepting new frames [ms].
+    pub fn delay_ms(self) -> u64 {
+        match self {
+            Command::Beep1 | Command::Beep2 | Command::Beep3 | Command::Beep4 | Command::Beep5 => {
+                260
+            }
+            Command::SaveSettings => 35,
+            _ => 1,
+        }
+    }
+}
+
+/// Maps thrust `[0.0 .. 1.0]` to a DShot value, zero (or less) stops the motor.
+pub fn throttle_value(thrust: f32) -> u16 {
+    if thrust <= 0.0 {
+        return 0;
+    }
+    let range = (MAX_THROTTLE - MIN_THROTTLE) as f32;
+    MIN_THROTTLE + (thrust.min(1.0) * range + 0.5) as u16
+}
+
+/// Builds the 16 bit frame for an 11 bit value.
+pub fn encode_frame(value: u16, telemetry: bool) -> u16 {
+    let packet = ((value & 0x07ff) << 1) | telemetry as u16;
+    let crc = (packet ^ (packet >> 4) ^ (packet >> 8)) & 0x0f;
+    (packet << 4) | crc
+}
+
+/// Like `encode_frame`, but with the inverted checksum that requests an eRPM response.
+pub fn encode_bidirectional_frame(value: u16, telemetry: bool) -> u16 {
+    encode_frame(value, telemetry) ^ 0x0f
+}
+
+/// Compare values for a zero and a one bit, for a timer period of `max_duty` per bit.
+pub fn bit_duty(max_duty: u16) -> (u16, u16) {
+    let max_duty = max_duty as u32;
+    ((max_duty * 3 / 8) as u16, (max_duty * 3 / 4) as u16)
+}
+
+/// Converts a frame to one compare value per slot.
+pub fn frame_duty(frame: u16, (zero, one): (u16, u16)) -> [u16; FRAME_SLOTS] {
+    let mut duty = [0; FRAME_SLOTS];
+    for (i, slot) in duty.iter_mut().take(16).enumerate() {
+        *slot = if frame & (0x8000 >> i) != 0 {
+            one
+        } else {
+            zero
+        };
+    }
+    duty
+}
+
+/// Number of bit levels of an eRPM response, including the start bit.
+pub const RESPONSE_BITS: u32 = 21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryError {
+    NoResponse,
+    Gcr,
+    Checksum,
+}
+
+const GCR_ENCODE: [u8; 16] = [
+    0x19, 0x1b, 0x12, 0x13, 0x1d, 0x15, 0x16, 0x17, 0x1a, 0x09, 0x0a, 0x0b, 0x1e, 0x0d, 0x0e, 0x0f,
+];
+
+/// Maps the 5 bit symbols back to nibbles, `0xff` marks invalid symbols.
+const GCR_DECODE: [u8; 32] = {
+    let mut table = [0xff; 32];
+    let mut i = 0;
+    while i < GCR_ENCODE.len() {
+        table[GCR_ENCODE[i] as usize] = i as u8;
+        i += 1;
+    }
+    table
+};
+
+/// Recovers the bit levels of a response from samples of the motor pin, taken at
+/// `samples_per_bit` samples per response bit. The response starts at the first low sample.
+pub fn response_levels(
+    samples: impl IntoIterator<Item = bool>,
+    samples_per_bit: f32,
+) -> Result<u32, TelemetryError> {
+    let run_bits = |run: u32| ((run as f32 / samples_per_bit + 0.5) as u32).clamp(1, RESPONSE_BITS);
+    let mut levels = 0u64;
+    let mut bits = 0;
+    let mut push = |level: bool, n: u32| {
+        levels = (levels << n) | if level { (1 << n) - 1 } else { 0 };
+        bits += n;
+        bits >= RESPONSE_BITS
+    };
+    let mut level = false;
+    let mut run = 0;
+    let mut complete = false;
+    for sample in samples.into_iter().skip_while(|sample| *sample) {
+        if sample == level {
+            run += 1;
+            continue;
+        }
+        complete = push(level, run_bits(run));
+        if complete {
+            break;
+        }
+        level = sample;
+        run = 1;
+    }
+    if run == 0 {
+        return Err(TelemetryError::NoResponse);
+    }
+    // The line returns to idle (high) after the response.
+    if !complete && !push(level, run_bits(run)) {
+        push(true, RESPONSE_BITS);
+    }
+    Ok((levels >> (bits - RESPONSE_BITS)) as u32)
+}
+
+/// Decodes the 21 response bit levels to the 12 bit eRPM value.
+pub fn decode_response(levels: u32) -> Result<u16, TelemetryError> {
+    // A transition between two levels encodes a one.
+    let gcr = (levels ^ (levels >> 1)) & 0x000f_ffff;
+    let mut frame = 0u16;
+    for i in (0..4).rev() {
+        let nibble = GCR_DECODE[((gcr >> (5 * i)) & 0x1f) as usize];
+        if nibble == 0xff {
+            return Err(TelemetryError::Gcr);
+        }
+        frame = (frame << 4) | nibble as u16;
+    }
+    let checksum = frame ^ (frame >> 4) ^ (frame >> 8) ^ (frame >> 12);
+    if checksum & 0x0f != 0x0f {
+        return Err(TelemetryError::Checksum);
+    }
+    Ok(frame >> 4)
+}
+
+/// Electrical RPM of a response value (3 bit shift, 9 bit period in us), zero if stopped.
+pub fn erpm(value: u16) -> u32 {
+    let period = ((value & 0x01ff) as u32) << (value >> 9);
+    if period == 0 || value == 0x0fff {
+        0
+    } else {
+        60_000_000 / period
+    }
+}
+
+/// Mechanical RPM for a motor with `motor_poles` magnet poles.
+pub fn rpm(erpm: u32, motor_poles: u8) -> u32 {
+    erpm * 2 / (motor_poles.max(2) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_checksum() {
+        // Example from the DShot protocol description: throttle 1046 without telemetry.
+        assert_eq!(encode_frame(1046, false), 0b1000_0010_1100_0110);
+        assert_eq!(encode_frame(0, false), 0);
+        assert_eq!(encode_frame(0, true) & 0x0f, 1);
+    }
+
+    #[test]
+    fn throttle_mapping() {
+        assert_eq!(throttle_value(0.0), 0);
+        assert_eq!(throttle_value(-0.2), 0);
+        assert_eq!(throttle_value(1e-6), MIN_THROTTLE);
+        assert_eq!(throttle_value(1.0), MAX_THROTTLE);
+        assert_eq!(throttle_value(2.0), MAX_THROTTLE);
+    }
+
+    #[test]
+    fn duty_cycles() {
+        let bits = bit_duty(140);
+        assert_eq!(bits, (52, 105));
+        let duty = frame_duty(0b1000_0000_0000_0001, bits);
+        assert_eq!(duty[0], 105);
+        assert_eq!(duty[1..15], [52; 14]);
+        assert_eq!(duty[15], 105);
+        assert_eq!(duty[16..], [0, 0]);
+    }
+
+    /// Response to eRPM 60000 (period 1000 us: value 0x3f4, frame 0x3f47).
+    const RESPONSE: u32 = 0b0_1110_1101_0101_0010_0101;
+
+    fn samples(s: &str) -> impl Iterator<Item = bool> + '_ {
+        s.chars().map(|c| c == '1')
+    }
+
+    #[test]
+    fn bidirectional_checksum() {
+        assert_eq!(
+            encode_bidirectional_frame(1046, false),
+            0b1000_0010_1100_1001
+        );
+    }
+
+    #[test]
+    fn decode_erpm() {
+        assert_eq!(decode_response(RESPONSE), Ok(0x3f4));
+        assert_eq!(erpm(0x3f4), 60_000);
+        assert_eq!(rpm(60_000, 14), 8571);
+        assert_eq!(erpm(0x0fff), 0);
+        assert_eq!(decode_response(RESPONSE ^ 0b100), Err(TelemetryError::Gcr));
+    }
+
+    #[test]
+    fn checksum_error() {
+        // Valid symbols, but the nibble 0x7 replaced by 0x6.
+        assert_eq!(
+            decode_response(0b0_1110_1101_0101_0010_0110),
+            Err(TelemetryError::Checksum)
+        );
+    }
+
+    #[test]
+    fn levels_from_samples() {
+        // Sampled at three samples per bit, with some jitter on the edges.
+        let captured =
+            "1111000111111111100111111000011100111000111100000111000000111100011111111111111";
+        assert_eq!(response_levels(samples(captured), 3.0), Ok(RESPONSE));
+        // Response truncated after the last low bit, the idle level completes it.
+        assert_eq!(response_levels(samples(&captured[..67]), 3.0), Ok(RESPONSE));
+        assert_eq!(
+            response_levels(samples("11111111"), 3.0),
+            Err(TelemetryError::NoResponse)
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! GNSS receivers on a UART: u-blox receivers are configured for the UBX protocol, other
+//! receivers are read via NMEA.
+
+use crate::nmea;
+use crate::ubx;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FixType {
+    #[default]
+    NoFix,
+    DeadReckoning,
+    Fix2d,
+    Fix3d,
+    /// Surveyed position, only the time is solved for.
+    TimeOnly,
+}
+
+/// Navigation solution of the receiver.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Solution {
+    pub fix: FixType,
+    pub latitude: i32,  // [1e-7 deg]
+    pub longitude: i32, // [1e-7 deg]
+    pub altitude: f32,  // [m], above mean sea level
+    /// North and east velocity [m/s].
+    pub velocity: [f32; 2],
+    /// Vertical velocity [m/s, up], not reported via NMEA.
+    pub vertical_velocity: Option<f32>,
+    /// Estimated standard deviations [m]. Via NMEA, they are derived from the dilution of
+    /// precision.
+    pub horizontal_accuracy: f32,
+    pub vertical_accuracy: f32,
+    /// Estimated standard deviation of the velocity [m/s], not reported via NMEA.
+    pub speed_accuracy: Option<f32>,
+    pub satellites: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Ubx(ubx::Message),
+    Nmea(Solution),
+}
+
+impl Event {
+    pub fn solution(&self) -> Option<Solution> {
+        match self {
+            Event::Ubx(ubx::Message::NavPvt(solution)) | Event::Nmea(solution) => Some(*solution),
+            Event::Ubx(_) => None,
+        }
+    }
+}
+
+/// Decodes a byte stream with UBX frames and NMEA sentences in any order.
+#[derive(Default)]
+pub struct Parser {
+    ubx: ubx::Parser,
+    nmea: nmea::Parser,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Event> {
+        let ubx = self.ubx.push(byte).map(Event::Ubx);
+        let nmea = self.nmea.push(byte).map(Event::Nmea);
+        ubx.or(nmea)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_stream() {
+        let mut parser = Parser::new();
+        let stream = b"$GNTXT,01,01,02,u-blox AG - www.u-blox.com*4E\r\n\
+            \xb5\x62\x05\x01\x02\x00\x06\x01\x0f\x38\
+            $GPGGA,123519.00,3345.12000,S,15112.34000,W,1,06,1.50,20.0,M,30.0,M,,*6E\r\n";
+        let mut events = stream.iter().filter_map(|byte| parser.push(*byte));
+        assert_eq!(
+            events.next(),
+            Some(Event::Ubx(ubx::Message::Ack {
+                class: ubx::CLASS_CFG,
+                id: ubx::CFG_MSG
+            }))
+        );
+        let solution = events.next().and_then(|event| event.solution()).unwrap();
+        assert_eq!(solution.fix, FixType::Fix3d);
+        assert_eq!(solution.satellites, 6);
+        assert_eq!(events.next(), None);
+    }
+}
@@ -0,0 +1,12 @@
+//! Hardware independent device drivers.
+//!
+//! Everything in here only depends on the `embedded-hal` traits, so that the protocol and
+//! conversion logic can be tested on the host.
+#![no_std]
+
+pub mod baro;
+pub mod dshot;
+pub mod gps;
+pub mod nmea;
+pub mod pwm;
+pub mod ubx;
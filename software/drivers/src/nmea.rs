@@ -0,0 +1,233 @@
+//! NMEA 0183 sentences of GNSS receivers without UBX support.
+//!
+//! A solution is reported per GGA sentence, combined with the velocity of the last RMC and the fix
+//! dimension of the last GSA sentence. Sentences of all talkers (GP, GN, ...) are accepted.
+
+use crate::gps::FixType;
+use crate::gps::Solution;
+
+/// Maximum length of a sentence without the start delimiter and the line ending. Longer ones
+/// are dropped.
+const MAX_SENTENCE: usize = 82;
+const MAX_FIELDS: usize = 20;
+/// User equivalent range error [m], to estimate the accuracy from the dilution of precision.
+const UERE: f32 = 4.0;
+const KNOT: f32 = 0.514_444; // [m/s]
+
+pub struct Parser {
+    buf: [u8; MAX_SENTENCE],
+    len: usize,
+    receiving: bool,
+    /// North and east velocity [m/s] of the last RMC sentence.
+    velocity: [f32; 2],
+    /// Fix dimension and vertical dilution of precision of the last GSA sentence.
+    dimension: Option<u8>,
+    vdop: Option<f32>,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_SENTENCE],
+            len: 0,
+            receiving: false,
+            velocity: [0.0; 2],
+            dimension: None,
+            vdop: None,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Solution> {
+        match byte {
+            b'$' => {
+                self.len = 0;
+                self.receiving = true;
+            }
+            b'\r' | b'\n' if self.receiving => {
+                self.receiving = false;
+                return self.sentence();
+            }
+            _ if self.receiving => {
+                if self.len < self.buf.len() {
+                    self.buf[self.len] = byte;
+                    self.len += 1;
+                } else {
+                    self.receiving = false;
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn sentence(&mut self) -> Option<Solution> {
+        // Copied, as the fields borrow from it while the parser state is updated.
+        let buf = self.buf;
+        let sentence = core::str::from_utf8(&buf[..self.len]).ok()?;
+        let (body, checksum) = sentence.rsplit_once('*')?;
+        let expected = body.bytes().fold(0, |checksum, byte| checksum ^ byte);
+        if u8::from_str_radix(checksum, 16).ok()? != expected {
+            return None;
+        }
+        let mut fields = [""; MAX_FIELDS];
+        let mut parts = body.split(',');
+        let kind = parts.next()?;
+        for (field, part) in fields.iter_mut().zip(parts) {
+            *field = part;
+        }
+        match kind.get(2..)? {
+            "GGA" => return self.gga(&fields),
+            "RMC" => self.rmc(&fields),
+            "GSA" => self.gsa(&fields),
+            _ => {}
+        }
+        None
+    }
+
+    fn gga(&self, fields: &[&str; MAX_FIELDS]) -> Option<Solution> {
+        let quality: u8 = fields[5].parse().ok()?;
+        let satellites = fields[6].parse().unwrap_or(0);
+        let position = coordinate(fields[1], fields[2]).zip(coordinate(fields[3], fields[4]));
+        let (Some((latitude, longitude)), 1..=5) = (position, quality) else {
+            return Some(Solution {
+                fix: if quality == 6 {
+                    FixType::DeadReckoning
+                } else {
+                    FixType::NoFix
+                },
+                satellites,
+                ..Default::default()
+            });
+        };
+        let hdop: f32 = fields[7].parse().ok()?;
+        Some(Solution {
+            fix: match self.dimension {
+                Some(2) => FixType::Fix2d,
+                _ => FixType::Fix3d,
+            },
+            latitude,
+            longitude,
+            altitude: fields[8].parse().ok()?,
+            velocity: self.velocity,
+            vertical_velocity: None,
+            horizontal_accuracy: hdop * UERE,
+            // The vertical error is typically about 1.5 times the horizontal one.
+            vertical_accuracy: self.vdop.unwrap_or(1.5 * hdop) * UERE,
+            speed_accuracy: None,
+            satellites,
+        })
+    }
+
+    fn rmc(&mut self, fields: &[&str; MAX_FIELDS]) {
+        let speed = fields[6].parse::<f32>().ok().filter(|_| fields[1] == "A");
+        self.velocity = match speed {
+            Some(speed) => {
+                // The course is empty while not moving.
+                let course = fields[7].parse::<f32>().unwrap_or(0.0).to_radians();
+                let speed = speed * KNOT;
+                [speed * libm::cosf(course), speed * libm::sinf(course)]
+            }
+            None => [0.0; 2],
+        };
+    }
+
+    fn gsa(&mut self, fields: &[&str; MAX_FIELDS]) {
+        self.dimension = fields[1].parse().ok();
+        self.vdop = fields[16].parse().ok();
+    }
+}
+
+/// Converts a `(d)ddmm.mmmm` coordinate with its hemisphere to 1e-7 degrees.
+fn coordinate(value: &str, hemisphere: &str) -> Option<i32> {
+    let dot = value.find('.').unwrap_or(value.len());
+    let degrees: f64 = value.get(..dot.checked_sub(2)?)?.parse().ok()?;
+    let minutes: f64 = value.get(dot - 2..)?.parse().ok()?;
+    let sign = match hemisphere {
+        "N" | "E" => 1.0,
+        "S" | "W" => -1.0,
+        _ => return None,
+    };
+    Some(libm::round(sign * (degrees + minutes / 60.0) * 1e7) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of a u-blox M8 with the UBX output disabled.
+    const RECORDING: &[u8] = b"\
+$GNRMC,083559.00,A,4717.11437,N,00833.91522,E,0.004,77.52,091202,,,A,V*33\r\n\
+$GNGGA,083559.00,4717.11437,N,00833.91522,E,1,08,1.01,499.6,M,48.0,M,,*46\r\n\
+$GNGSA,A,3,23,29,07,08,09,18,26,28,,,,,1.94,1.01,1.66,1*07\r\n\
+$GNTXT,01,01,02,u-blox AG - www.u-blox.com*4E\r\n\
+$GNRMC,083600.00,A,4717.11399,N,00833.91590,E,9.720,135.0,091202,,,A,V*3E\r\n\
+$GNGGA,083600.00,4717.11399,N,00833.91590,E,1,09,0.95,501.2,M,48.0,M,,*4A\r\n\
+$GNGGA,083601.00,,,,,0,00,99.99,,,,,,*74\r\n";
+
+    /// Returns the number of solutions and the first four of them.
+    fn parse(parser: &mut Parser, bytes: &[u8]) -> (usize, [Solution; 4]) {
+        let mut solutions = [Solution::default(); 4];
+        let mut count = 0;
+        for solution in bytes.iter().filter_map(|byte| parser.push(*byte)) {
+            if let Some(slot) = solutions.get_mut(count) {
+                *slot = solution;
+            }
+            count += 1;
+        }
+        (count, solutions)
+    }
+
+    #[test]
+    fn recorded_sentences() {
+        let mut parser = Parser::new();
+        let (count, solutions) = parse(&mut parser, RECORDING);
+        assert_eq!(count, 3);
+
+        let first = solutions[0];
+        assert_eq!(first.fix, FixType::Fix3d);
+        assert_eq!(first.latitude, 472_852_395);
+        assert_eq!(first.longitude, 85_652_537);
+        assert_eq!(first.altitude, 499.6);
+        assert!(first.velocity.iter().all(|v| v.abs() < 0.01));
+        assert_eq!(first.satellites, 8);
+        assert!((first.horizontal_accuracy - 1.01 * UERE).abs() < 1e-4);
+        // No GSA sentence yet.
+        assert!((first.vertical_accuracy - 1.515 * UERE).abs() < 1e-4);
+        assert_eq!(first.vertical_velocity, None);
+
+        let second = solutions[1];
+        assert!((second.velocity[0] + 3.5358).abs() < 1e-3);
+        assert!((second.velocity[1] - 3.5358).abs() < 1e-3);
+        assert!((second.vertical_accuracy - 1.66 * UERE).abs() < 1e-4);
+
+        assert_eq!(solutions[2].fix, FixType::NoFix);
+        assert_eq!(solutions[2].satellites, 0);
+    }
+
+    #[test]
+    fn southern_and_western_hemisphere() {
+        let mut parser = Parser::new();
+        let (_, solutions) = parse(
+            &mut parser,
+            b"$GPGGA,123519.00,3345.12000,S,15112.34000,W,1,06,1.50,20.0,M,30.0,M,,*6E\r\n",
+        );
+        assert_eq!(solutions[0].latitude, -337_520_000);
+        assert_eq!(solutions[0].longitude, -1_512_056_667);
+    }
+
+    #[test]
+    fn rejects_corrupted_sentences() {
+        let mut parser = Parser::new();
+        let corrupted =
+            b"$GNGGA,083559.00,4717.11437,N,00833.91522,E,1,08,1.01,499.6,M,48.0,M,,*47\r\n";
+        assert_eq!(parse(&mut parser, corrupted).0, 0);
+        let truncated = b"$GNGGA,083559.00,4717.11437,N,00833.91522,E,1,08\r\n";
+        assert_eq!(parse(&mut parser, truncated).0, 0);
+    }
+}
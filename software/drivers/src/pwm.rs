@@ -0,0 +1,81 @@
+//! Analog ESC protocols, which encode the thrust in the width of a repeated pulse.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// Servo PWM, 1000 .. 2000 us.
+    Standard,
+    /// 125 .. 250 us.
+    OneShot125,
+    /// 42 .. 84 us.
+    OneShot42,
+    /// 5 .. 25 us.
+    Multishot,
+}
+
+impl Protocol {
+    /// Pulse width for zero and full thrust [ns].
+    pub fn pulse_range(self) -> (u32, u32) {
+        match self {
+            Protocol::Standard => (1_000_000, 2_000_000),
+            Protocol::OneShot125 => (125_000, 250_000),
+            Protocol::OneShot42 => (42_000, 84_000),
+            Protocol::Multishot => (5_000, 25_000),
+        }
+    }
+
+    /// Highest output rate, leaving a gap of a quarter of the longest pulse [Hz].
+    pub fn max_rate(self) -> u32 {
+        let (_, max) = self.pulse_range();
+        1_000_000_000 / (max + max / 4)
+    }
+
+    /// Output rate if none is configured [Hz].
+    pub fn default_rate(self) -> u32 {
+        match self {
+            // Also works for ESCs (and servos) which only accept 50 Hz.
+            Protocol::Standard => 50,
+            Protocol::OneShot125 | Protocol::OneShot42 | Protocol::Multishot => 1000,
+        }
+    }
+
+    /// The configured output rate, limited to what the protocol allows [Hz].
+    pub fn rate(self, requested: Option<u32>) -> u32 {
+        requested
+            .unwrap_or(self.default_rate())
+            .clamp(1, self.max_rate())
+    }
+
+    /// Compare value for thrust `[0.0 .. 1.0]`, for a timer running at `rate` with a period of
+    /// `max_duty`.
+    pub fn duty(self, thrust: f32, rate: u32, max_duty: u16) -> u16 {
+        let (min, max) = self.pulse_range();
+        let pulse = min as u64 + (thrust.clamp(0.0, 1.0) * (max - min) as f32) as u64;
+        (pulse * rate as u64 * max_duty as u64 / 1_000_000_000) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rates() {
+        assert_eq!(Protocol::Standard.max_rate(), 400);
+        assert_eq!(Protocol::Standard.rate(None), 50);
+        assert_eq!(Protocol::Standard.rate(Some(1000)), 400);
+        assert_eq!(Protocol::OneShot125.max_rate(), 3200);
+        assert_eq!(Protocol::Multishot.rate(Some(8000)), 8000);
+    }
+
+    #[test]
+    fn pulse_widths() {
+        // 50 Hz: 5% .. 10% duty cycle.
+        assert_eq!(Protocol::Standard.duty(0.0, 50, 20000), 1000);
+        assert_eq!(Protocol::Standard.duty(1.0, 50, 20000), 2000);
+        assert_eq!(Protocol::Standard.duty(1.5, 50, 20000), 2000);
+        assert_eq!(Protocol::Standard.duty(-1.0, 400, 20000), 8000);
+        // 1 kHz: 125 .. 250 us of 1000 us.
+        assert_eq!(Protocol::OneShot125.duty(0.5, 1000, 40000), 7500);
+        assert_eq!(Protocol::Multishot.duty(1.0, 1000, 40000), 1000);
+    }
+}
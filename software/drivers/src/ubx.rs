@@ -0,0 +1,366 @@
+//! u-blox UBX binary protocol: frame encoding and decoding, the configuration messages and the
+//! navigation solution (NAV-PVT).
+//!
+//! The configuration uses the legacy `CFG-*` messages, which are supported up to the M9
+//! generation.
+
+use crate::gps::FixType;
+use crate::gps::Solution;
+
+const SYNC: [u8; 2] = [0xb5, 0x62];
+/// Largest payload of the decoded messages (NAV-PVT).
+const MAX_PAYLOAD: usize = 92;
+/// Largest encoded configuration frame (CFG-NAV5).
+const MAX_FRAME: usize = 36 + 8;
+
+pub const CLASS_NAV: u8 = 0x01;
+pub const CLASS_ACK: u8 = 0x05;
+pub const CLASS_CFG: u8 = 0x06;
+pub const NAV_PVT: u8 = 0x07;
+pub const ACK_NAK: u8 = 0x00;
+pub const ACK_ACK: u8 = 0x01;
+pub const CFG_PRT: u8 = 0x00;
+pub const CFG_MSG: u8 = 0x01;
+pub const CFG_RATE: u8 = 0x08;
+pub const CFG_NAV5: u8 = 0x24;
+
+/// Dynamic platform model "airborne with <2g acceleration".
+const DYNAMIC_MODEL_AIRBORNE: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Message {
+    NavPvt(Solution),
+    /// The receiver accepted the configuration message with the given class and id.
+    Ack {
+        class: u8,
+        id: u8,
+    },
+    /// The receiver rejected the configuration message with the given class and id.
+    Nak {
+        class: u8,
+        id: u8,
+    },
+}
+
+/// An encoded UBX frame.
+pub struct Frame {
+    buf: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl Frame {
+    pub fn new(class: u8, id: u8, payload: &[u8]) -> Self {
+        let mut buf = [0; MAX_FRAME];
+        let len = payload.len() + 8;
+        buf[0..2].copy_from_slice(&SYNC);
+        buf[2] = class;
+        buf[3] = id;
+        buf[4..6].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        buf[6..len - 2].copy_from_slice(payload);
+        let checksum = checksum(&buf[2..len - 2]);
+        buf[len - 2..len].copy_from_slice(&checksum);
+        Self { buf, len }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn class(&self) -> u8 {
+        self.buf[2]
+    }
+
+    pub fn id(&self) -> u8 {
+        self.buf[3]
+    }
+}
+
+/// 8 bit Fletcher checksum over class, id, length and payload.
+fn checksum<'a>(data: impl IntoIterator<Item = &'a u8>) -> [u8; 2] {
+    let mut a = 0u8;
+    let mut b = 0u8;
+    for byte in data {
+        a = a.wrapping_add(*byte);
+        b = b.wrapping_add(a);
+    }
+    [a, b]
+}
+
+/// Sets the baud rate of UART1 and switches its output to UBX only.
+pub fn cfg_prt(baudrate: u32) -> Frame {
+    let mut payload = [0u8; 20];
+    payload[0] = 1; // UART1
+    payload[4..8].copy_from_slice(&0x0000_08d0u32.to_le_bytes()); // 8N1
+    payload[8..12].copy_from_slice(&baudrate.to_le_bytes());
+    payload[12..14].copy_from_slice(&0x0003u16.to_le_bytes()); // UBX and NMEA in
+    payload[14..16].copy_from_slice(&0x0001u16.to_le_bytes()); // UBX out
+    Frame::new(CLASS_CFG, CFG_PRT, &payload)
+}
+
+/// Sets the measurement period [ms], one navigation solution per measurement.
+pub fn cfg_rate(period: u16) -> Frame {
+    let mut payload = [0u8; 6];
+    payload[0..2].copy_from_slice(&period.to_le_bytes());
+    payload[2..4].copy_from_slice(&1u16.to_le_bytes());
+    payload[4..6].copy_from_slice(&1u16.to_le_bytes()); // GPS time
+    Frame::new(CLASS_CFG, CFG_RATE, &payload)
+}
+
+/// Selects the airborne dynamic model, which allows for the vertical dynamics of the vehicle.
+pub fn cfg_nav5() -> Frame {
+    let mut payload = [0u8; 36];
+    payload[0..2].copy_from_slice(&0x0001u16.to_le_bytes()); // only apply the dynamic model
+    payload[2] = DYNAMIC_MODEL_AIRBORNE;
+    Frame::new(CLASS_CFG, CFG_NAV5, &payload)
+}
+
+/// Sends the message with the given class and id on every `rate`-th solution on the current port.
+pub fn cfg_msg(class: u8, id: u8, rate: u8) -> Frame {
+    Frame::new(CLASS_CFG, CFG_MSG, &[class, id, rate])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Sync1,
+    Sync2,
+    Header { received: usize },
+    Payload,
+    Checksum { received: usize },
+}
+
+/// Decodes UBX frames from a byte stream, unknown and oversized messages are skipped.
+pub struct Parser {
+    state: State,
+    header: [u8; 4],
+    payload: [u8; MAX_PAYLOAD],
+    received: usize,
+    checksum: [u8; 2],
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Sync1,
+            header: [0; 4],
+            payload: [0; MAX_PAYLOAD],
+            received: 0,
+            checksum: [0; 2],
+        }
+    }
+
+    fn len(&self) -> usize {
+        u16::from_le_bytes([self.header[2], self.header[3]]) as usize
+    }
+
+    pub fn push(&mut self, byte: u8) -> Option<Message> {
+        match self.state {
+            State::Sync1 => {
+                if byte == SYNC[0] {
+                    self.state = State::Sync2;
+                }
+            }
+            State::Sync2 => {
+                self.state = match byte {
+                    _ if byte == SYNC[1] => State::Header { received: 0 },
+                    _ if byte == SYNC[0] => State::Sync2,
+                    _ => State::Sync1,
+                }
+            }
+            State::Header { received } => {
+                self.header[received] = byte;
+                self.state = if received + 1 < self.header.len() {
+                    State::Header {
+                        received: received + 1,
+                    }
+                } else if self.len() > MAX_PAYLOAD {
+                    State::Sync1
+                } else {
+                    self.received = 0;
+                    if self.len() == 0 {
+                        State::Checksum { received: 0 }
+                    } else {
+                        State::Payload
+                    }
+                };
+            }
+            State::Payload => {
+                self.payload[self.received] = byte;
+                self.received += 1;
+                if self.received == self.len() {
+                    self.state = State::Checksum { received: 0 };
+                }
+            }
+            State::Checksum { received: 0 } => {
+                self.checksum[0] = byte;
+                self.state = State::Checksum { received: 1 };
+            }
+            State::Checksum { .. } => {
+                self.checksum[1] = byte;
+                self.state = State::Sync1;
+                return self.decode();
+            }
+        }
+        None
+    }
+
+    fn decode(&self) -> Option<Message> {
+        let len = self.len();
+        if checksum(self.header.iter().chain(&self.payload[..len])) != self.checksum {
+            return None;
+        }
+        let payload = &self.payload[..len];
+        match (self.header[0], self.header[1], len) {
+            (CLASS_NAV, NAV_PVT, 92) => Some(Message::NavPvt(nav_pvt(payload))),
+            (CLASS_ACK, ACK_ACK, 2) => Some(Message::Ack {
+                class: payload[0],
+                id: payload[1],
+            }),
+            (CLASS_ACK, ACK_NAK, 2) => Some(Message::Nak {
+                class: payload[0],
+                id: payload[1],
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn nav_pvt(payload: &[u8]) -> Solution {
+    let u32_at = |offset: usize| {
+        u32::from_le_bytes([
+            payload[offset],
+            payload[offset + 1],
+            payload[offset + 2],
+            payload[offset + 3],
+        ])
+    };
+    let i32_at = |offset: usize| u32_at(offset) as i32;
+    let fix_ok = payload[21] & 0x01 != 0;
+    let fix = match payload[20] {
+        _ if !fix_ok => FixType::NoFix,
+        1 => FixType::DeadReckoning,
+        2 => FixType::Fix2d,
+        3 | 4 => FixType::Fix3d,
+        5 => FixType::TimeOnly,
+        _ => FixType::NoFix,
+    };
+    Solution {
+        fix,
+        latitude: i32_at(28),
+        longitude: i32_at(24),
+        altitude: i32_at(36) as f32 / 1000.0,
+        velocity: [i32_at(48) as f32 / 1000.0, i32_at(52) as f32 / 1000.0],
+        vertical_velocity: Some(-(i32_at(56) as f32) / 1000.0),
+        horizontal_accuracy: u32_at(40) as f32 / 1000.0,
+        vertical_accuracy: u32_at(44) as f32 / 1000.0,
+        speed_accuracy: Some(u32_at(68) as f32 / 1000.0),
+        satellites: payload[23],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(parser: &mut Parser, bytes: &[u8]) -> Option<Message> {
+        bytes.iter().filter_map(|byte| parser.push(*byte)).last()
+    }
+
+    #[test]
+    fn encode_configuration() {
+        assert_eq!(
+            cfg_msg(CLASS_NAV, NAV_PVT, 1).as_bytes(),
+            [
+                0xb5, 0x62, 0x06, 0x01, 0x03, 0x00, 0x01, 0x07, 0x01, 0x13, 0x51
+            ]
+        );
+        assert_eq!(
+            cfg_rate(200).as_bytes(),
+            [
+                0xb5, 0x62, 0x06, 0x08, 0x06, 0x00, 0xc8, 0x00, 0x01, 0x00, 0x01, 0x00, 0xde, 0x6a
+            ]
+        );
+        assert_eq!(cfg_prt(115_200).as_bytes().len(), 28);
+        assert_eq!(cfg_nav5().as_bytes().len(), 44);
+    }
+
+    #[test]
+    fn decode_ack() {
+        let mut parser = Parser::new();
+        // Garbage and a NMEA sentence before the frame.
+        let stream = b"\xb5\x00$GPTXT,01*00\r\n\xb5\x62\x05\x01\x02\x00\x06\x01\x0f\x38";
+        assert_eq!(
+            parse(&mut parser, stream),
+            Some(Message::Ack {
+                class: CLASS_CFG,
+                id: CFG_MSG
+            })
+        );
+
+        // Corrupted checksum.
+        let stream = [0xb5, 0x62, 0x05, 0x00, 0x02, 0x00, 0x06, 0x01, 0x0e, 0x36];
+        assert_eq!(parse(&mut parser, &stream), None);
+        let stream = [0xb5, 0x62, 0x05, 0x00, 0x02, 0x00, 0x06, 0x01, 0x0e, 0x33];
+        assert_eq!(
+            parse(&mut parser, &stream),
+            Some(Message::Nak {
+                class: CLASS_CFG,
+                id: CFG_MSG
+            })
+        );
+    }
+
+    #[test]
+    fn decode_nav_pvt() {
+        let mut payload = [0u8; 92];
+        payload[20] = 3;
+        payload[21] = 0x01;
+        payload[23] = 14;
+        payload[24..28].copy_from_slice(&87_654_321i32.to_le_bytes());
+        payload[28..32].copy_from_slice(&481_234_567i32.to_le_bytes());
+        payload[36..40].copy_from_slice(&512_300i32.to_le_bytes());
+        payload[40..44].copy_from_slice(&1_200u32.to_le_bytes());
+        payload[44..48].copy_from_slice(&2_500u32.to_le_bytes());
+        payload[48..52].copy_from_slice(&(-1_500i32).to_le_bytes());
+        payload[52..56].copy_from_slice(&3_000i32.to_le_bytes());
+        payload[56..60].copy_from_slice(&(-200i32).to_le_bytes());
+        payload[68..72].copy_from_slice(&300u32.to_le_bytes());
+
+        // Built by hand, as `Frame` only holds configuration messages.
+        let mut frame = [0u8; 100];
+        frame[0..6].copy_from_slice(&[0xb5, 0x62, CLASS_NAV, NAV_PVT, 92, 0]);
+        frame[6..98].copy_from_slice(&payload);
+        let ck = checksum(&frame[2..98]);
+        frame[98..100].copy_from_slice(&ck);
+
+        let mut parser = Parser::new();
+        let Some(Message::NavPvt(solution)) = parse(&mut parser, &frame) else {
+            panic!("no solution");
+        };
+        assert_eq!(solution.fix, FixType::Fix3d);
+        assert_eq!(solution.latitude, 481_234_567);
+        assert_eq!(solution.longitude, 87_654_321);
+        assert_eq!(solution.altitude, 512.3);
+        assert_eq!(solution.velocity, [-1.5, 3.0]);
+        assert_eq!(solution.vertical_velocity, Some(0.2));
+        assert_eq!(solution.horizontal_accuracy, 1.2);
+        assert_eq!(solution.vertical_accuracy, 2.5);
+        assert_eq!(solution.speed_accuracy, Some(0.3));
+        assert_eq!(solution.satellites, 14);
+
+        // Without the fix ok flag, the fix is not valid.
+        payload[21] = 0;
+        frame[6..98].copy_from_slice(&payload);
+        let ck = checksum(&frame[2..98]);
+        frame[98..100].copy_from_slice(&ck);
+        let Some(Message::NavPvt(solution)) = parse(&mut parser, &frame) else {
+            panic!("no solution");
+        };
+        assert_eq!(solution.fix, FixType::NoFix);
+    }
+}
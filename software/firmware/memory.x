@@ -0,0 +1,8 @@
+/* STM32F405RG */
+MEMORY
+{
+  /* The last two 128 KiB sectors (10 and 11, 0x080C0000 .. 0x080FFFFF) hold the mission and the
+     settings, see storage.rs. */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 768K
+  RAM : ORIGIN = 0x20000000, LENGTH = 128K
+}
@@ -0,0 +1,47 @@
+use defmt::info;
+use defmt::warn;
+use drivers::baro;
+use drivers::baro::Baro2smpb;
+use drivers::baro::I2cInterface;
+use drivers::baro::Measurement;
+use embassy_time::Delay;
+
+pub use crate::board::BaroI2c;
+
+pub struct Baro {
+    driver: Baro2smpb<I2cInterface<BaroI2c>>,
+}
+
+impl Baro {
+    /// Returns `None` if there is no working barometer.
+    pub async fn init(i2c: BaroI2c) -> Option<Self> {
+        let interface = I2cInterface::new(i2c, baro::I2C_ADDRESS_SDO_LOW);
+        match Baro2smpb::new(interface, baro::Config::default(), &mut Delay).await {
+            Ok(driver) => {
+                info!("Barometer initialized");
+                Some(Self { driver })
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to initialize barometer: {}",
+                    defmt::Debug2Format(&e)
+                );
+                None
+            }
+        }
+    }
+
+    /// Returns the measurement and the pressure altitude [m].
+    pub async fn read(&mut self) -> Option<(Measurement, f32)> {
+        match self.driver.read().await {
+            Ok(measurement) => Some((
+                measurement,
+                baro::pressure_altitude(measurement.pressure, baro::SEA_LEVEL_PRESSURE),
+            )),
+            Err(e) => {
+                warn!("Failed to read barometer: {}", defmt::Debug2Format(&e));
+                None
+            }
+        }
+    }
+}
@@ -1,17 +1,34 @@
 use super::EscDriver;
+use super::ServoDriver;
+use crate::watchdog;
 
+use cortex_m::peripheral::DWT;
+use drivers::dshot;
+use drivers::pwm;
 use embassy_stm32::Peri;
+use embassy_stm32::flash::Blocking;
+use embassy_stm32::flash::Flash;
 use embassy_stm32::gpio::Level;
 use embassy_stm32::gpio::Output;
 use embassy_stm32::gpio::OutputType;
 use embassy_stm32::gpio::Speed;
 use embassy_stm32::mode::Async;
+use embassy_stm32::pac;
+use embassy_stm32::pac::gpio::Gpio;
+use embassy_stm32::pac::gpio::vals::Moder;
+use embassy_stm32::pac::gpio::vals::Pupdr;
 use embassy_stm32::peripherals::USB_OTG_FS;
-use embassy_stm32::peripherals::{PA5, PA6, PA7, PA9, PA10, TIM3, TIM5};
+use embassy_stm32::peripherals::{
+    DMA1_CH0, DMA1_CH2, IWDG, PA0, PA2, PA5, PA6, PA7, PA9, PA10, PB0, PB1, PB10, PB11, TIM2, TIM3,
+    TIM5, TIM8,
+};
 use embassy_stm32::spi::Config as SpiConfig;
 use embassy_stm32::spi::Spi;
 use embassy_stm32::time::Hertz;
 use embassy_stm32::time::hz;
+use embassy_stm32::time::khz;
+use embassy_stm32::timer::Channel;
+use embassy_stm32::timer::low_level::OutputPolarity;
 use embassy_stm32::timer::simple_pwm::PwmPin;
 use embassy_stm32::timer::simple_pwm::SimplePwm;
 use embassy_stm32::usart;
@@ -21,35 +38,59 @@ use embassy_stm32::usart::Parity;
 use embassy_stm32::usart::StopBits;
 use embassy_stm32::usart::Uart;
 use embassy_stm32::usb;
+use embassy_stm32::wdg::IndependentWatchdog;
 use embassy_stm32::{bind_interrupts, i2c, peripherals};
+use embassy_time::Timer;
 use embassy_usb::Builder;
 use embassy_usb::class::cdc_acm::CdcAcmClass;
 use embassy_usb::class::cdc_acm::State;
+use protocol::DShotSpeed;
+use protocol::EscCommand;
+use protocol::EscProtocol;
+use protocol::ResetReason;
 use static_cell::StaticCell;
 
 // see https://github.com/betaflight/unified-targets/blob/master/configs/default/OPEN-REVO.config for pin map
 // or  https://www.getfpv.com/lumenier-skitzo-flight-controller-powered-by-flightone.html for some general information
 type RadioRx = Peri<'static, PA10>;
 type RadioTx = Peri<'static, PA9>;
+type GpsTx = Peri<'static, PB10>;
+type GpsRx = Peri<'static, PB11>;
 type ImuSck = Peri<'static, PA5>;
 type ImuMiso = Peri<'static, PA6>;
 type ImuMosi = Peri<'static, PA7>;
 
 pub type ImuSpi = Spi<'static, Async>; // SPI1
 pub type ImuCs = Output<'static>; // PA4
+pub type BaroI2c = i2c::I2c<'static, Async>; // I2C1
 pub type RadioUart = Uart<'static, Async>; // USART1
+pub type GpsUart = Uart<'static, Async>; // USART3
 pub type UsbClass = CdcAcmClass<'static, usb::Driver<'static, USB_OTG_FS>>;
 pub type UsbDevice = embassy_usb::UsbDevice<'static, usb::Driver<'static, USB_OTG_FS>>;
 pub type UsbReceiver =
     embassy_usb::class::cdc_acm::Receiver<'static, usb::Driver<'static, USB_OTG_FS>>;
 pub type UsbSender = embassy_usb::class::cdc_acm::Sender<'static, usb::Driver<'static, USB_OTG_FS>>;
-pub type EscDriverType = BlackpillEscDriver;
+pub type EscDriverType = BlackpillEsc;
+pub type ServoDriverType = BlackpillServoDriver;
+pub type SettingsFlash = Flash<'static, Blocking>;
+pub type HardwareWatchdog = IndependentWatchdog<'static, IWDG>;
+
+pub const SERVO_COUNT: usize = 6;
+
+const SYSCLK: u32 = 168_000_000;
+/// Standard servo pulse rate.
+const SERVO_RATE: u32 = 50;
+/// Samples per bit of the bidirectional DShot response.
+const RESPONSE_OVERSAMPLING: u32 = 3;
+/// Covers the turnaround time of about 30 us and the 21 bit response.
+const RESPONSE_SAMPLES: usize = 256;
 
 bind_interrupts!(struct Irqs {
     I2C1_EV => i2c::EventInterruptHandler<peripherals::I2C1>;
     I2C1_ER => i2c::ErrorInterruptHandler<peripherals::I2C1>;
     OTG_FS => usb::InterruptHandler<peripherals::USB_OTG_FS>;
     USART1 => usart::InterruptHandler<peripherals::USART1>;
+    USART3 => usart::InterruptHandler<peripherals::USART3>;
 });
 
 static EP_OUT_BUFFER: StaticCell<[u8; 256]> = StaticCell::new();
@@ -60,11 +101,17 @@ static STATE: StaticCell<State> = StaticCell::new();
 
 pub struct Board {
     pub radio_uart: RadioUart,
+    pub gps_uart: GpsUart,
     pub imu_spi: ImuSpi,
     pub imu_cs: ImuCs,
+    pub baro_i2c: BaroI2c,
     pub usb_class: UsbClass,
     pub usb_device: UsbDevice,
-    pub esc_driver: EscDriverType,
+    pub esc: EscPeripherals,
+    pub servos: ServoDriverType,
+    pub flash: SettingsFlash,
+    pub watchdog: HardwareWatchdog,
+    pub reset_reason: ResetReason,
 }
 
 impl Board {
@@ -91,6 +138,7 @@ impl Board {
             config.rcc.mux.clk48sel = mux::Clk48sel::PLL1_Q;
         }
         let p = embassy_stm32::init(config);
+        let reset_reason = reset_reason();
 
         // init usb connection
         let ep_out_buffer = EP_OUT_BUFFER.init([0; 256]);
@@ -138,6 +186,22 @@ impl Board {
         )
         .unwrap();
 
+        // init gps (UART3 header), the baud rate is changed during the receiver detection
+        let gps_tx: GpsTx = p.PB10;
+        let gps_rx: GpsRx = p.PB11;
+        let mut gps_uart_config = UsartConfig::default();
+        gps_uart_config.baudrate = 9600;
+        let gps_uart = Uart::new(
+            p.USART3,
+            gps_rx,
+            gps_tx,
+            Irqs,
+            p.DMA1_CH3,
+            p.DMA1_CH1,
+            gps_uart_config,
+        )
+        .unwrap();
+
         // init imu
         let imu_cs = Output::new(p.PA4, Level::High, Speed::VeryHigh);
         let imu_sck: ImuSck = p.PA5;
@@ -155,43 +219,217 @@ impl Board {
             imu_spi_config,
         );
 
-        // init esc driver
-        let pwm_tim5 = SimplePwm::new(
-            p.TIM5,
-            Some(PwmPin::new(p.PA0, OutputType::PushPull)),
-            None,
-            Some(PwmPin::new(p.PA2, OutputType::PushPull)),
-            None,
-            hz(50),
+        // init barometer (SCL: PB8, SDA: PB9)
+        let mut baro_i2c_config = i2c::Config::default();
+        baro_i2c_config.frequency = hz(400_000);
+        let baro_i2c = i2c::I2c::new(
+            p.I2C1,
+            p.PB8,
+            p.PB9,
+            Irqs,
+            p.DMA1_CH7,
+            p.DMA1_CH5,
+            baro_i2c_config,
+        );
+
+        // the esc driver is created later, once the configured protocol is known
+        let esc = EscPeripherals {
+            tim5: p.TIM5,
+            tim3: p.TIM3,
+            m1: p.PB0,
+            m2: p.PB1,
+            m3: p.PA0,
+            m4: p.PA2,
+            dma_tim5: p.DMA1_CH0,
+            dma_tim3: p.DMA1_CH2,
+        };
+
+        // init servos (the PWM input header and the unused motor outputs)
+        let pwm_tim8 = SimplePwm::new(
+            p.TIM8,
+            Some(PwmPin::new(p.PC6, OutputType::PushPull)),
+            Some(PwmPin::new(p.PC7, OutputType::PushPull)),
+            Some(PwmPin::new(p.PC8, OutputType::PushPull)),
+            Some(PwmPin::new(p.PC9, OutputType::PushPull)),
+            hz(SERVO_RATE),
             Default::default(),
         );
-        let pwm_tim3 = SimplePwm::new(
-            p.TIM3,
+        let pwm_tim2 = SimplePwm::new(
+            p.TIM2,
             None,
+            Some(PwmPin::new(p.PA1, OutputType::PushPull)),
             None,
-            Some(PwmPin::new(p.PB0, OutputType::PushPull)),
-            Some(PwmPin::new(p.PB1, OutputType::PushPull)),
-            hz(50),
+            Some(PwmPin::new(p.PA3, OutputType::PushPull)),
+            hz(SERVO_RATE),
             Default::default(),
         );
-        let esc_driver = BlackpillEscDriver::init(pwm_tim5, pwm_tim3);
+        let servos = BlackpillServoDriver::init(pwm_tim8, pwm_tim2);
+
+        // init settings storage
+        let flash = Flash::new_blocking(p.FLASH);
+
+        // the hardware watchdog is only started once it is fed
+        let watchdog = IndependentWatchdog::new(p.IWDG, watchdog::HARDWARE_TIMEOUT_US);
 
         Board {
             radio_uart,
+            gps_uart,
             imu_spi,
             imu_cs,
+            baro_i2c,
             usb_class,
             usb_device,
-            esc_driver,
+            esc,
+            servos,
+            flash,
+            watchdog,
+            reset_reason,
+        }
+    }
+}
+
+/// Timers, pins and DMA channels of the motor outputs.
+pub struct EscPeripherals {
+    tim5: Peri<'static, TIM5>,
+    tim3: Peri<'static, TIM3>,
+    m1: Peri<'static, PB0>,
+    m2: Peri<'static, PB1>,
+    m3: Peri<'static, PA0>,
+    m4: Peri<'static, PA2>,
+    dma_tim5: Peri<'static, DMA1_CH0>, // TIM5_UP
+    dma_tim3: Peri<'static, DMA1_CH2>, // TIM3_UP
+}
+
+impl EscPeripherals {
+    /// `rate` is the output rate of the analog protocols, `None` for the protocol default.
+    pub fn init(self, protocol: EscProtocol, rate: Option<u16>) -> EscDriverType {
+        let analog = match protocol {
+            EscProtocol::Pwm => Some(pwm::Protocol::Standard),
+            EscProtocol::OneShot125 => Some(pwm::Protocol::OneShot125),
+            EscProtocol::OneShot42 => Some(pwm::Protocol::OneShot42),
+            EscProtocol::Multishot => Some(pwm::Protocol::Multishot),
+            EscProtocol::DShot { .. } => None,
+        };
+        // For DShot, one timer period is one bit.
+        let frequency = match (protocol, analog) {
+            (EscProtocol::DShot { speed, .. }, _) => match speed {
+                DShotSpeed::DShot150 => khz(150),
+                DShotSpeed::DShot300 => khz(300),
+                DShotSpeed::DShot600 => khz(600),
+            },
+            (_, analog) => hz(analog
+                .unwrap_or(pwm::Protocol::Standard)
+                .rate(rate.map(u32::from))),
+        };
+        let pwm_tim5 = SimplePwm::new(
+            self.tim5,
+            Some(PwmPin::new(self.m3, OutputType::PushPull)),
+            None,
+            Some(PwmPin::new(self.m4, OutputType::PushPull)),
+            None,
+            frequency,
+            Default::default(),
+        );
+        let pwm_tim3 = SimplePwm::new(
+            self.tim3,
+            None,
+            None,
+            Some(PwmPin::new(self.m1, OutputType::PushPull)),
+            Some(PwmPin::new(self.m2, OutputType::PushPull)),
+            frequency,
+            Default::default(),
+        );
+        match analog {
+            Some(analog) => BlackpillEsc::Pwm(BlackpillEscDriver::init(
+                pwm_tim5, pwm_tim3, analog, frequency,
+            )),
+            None => BlackpillEsc::DShot(DShotEscDriver::init(
+                pwm_tim5,
+                pwm_tim3,
+                self.dma_tim5,
+                self.dma_tim3,
+                frequency,
+                matches!(
+                    protocol,
+                    EscProtocol::DShot {
+                        bidirectional: true,
+                        ..
+                    }
+                ),
+            )),
+        }
+    }
+}
+
+pub enum BlackpillEsc {
+    Pwm(BlackpillEscDriver),
+    DShot(DShotEscDriver),
+}
+
+impl EscDriver for BlackpillEsc {
+    async fn update(&mut self, thrust: [f32; 4]) {
+        match self {
+            BlackpillEsc::Pwm(driver) => driver.update(thrust).await,
+            BlackpillEsc::DShot(driver) => driver.update(thrust).await,
+        }
+    }
+
+    async fn command(&mut self, motor: Option<usize>, command: EscCommand) -> bool {
+        match self {
+            BlackpillEsc::Pwm(driver) => driver.command(motor, command).await,
+            BlackpillEsc::DShot(driver) => driver.command(motor, command).await,
+        }
+    }
+
+    fn erpm(&self) -> [Option<u32>; 4] {
+        match self {
+            BlackpillEsc::Pwm(driver) => driver.erpm(),
+            BlackpillEsc::DShot(driver) => driver.erpm(),
         }
     }
 }
 
+/// Stops the pulses on all motor outputs, so that the ESCs see a signal loss and stop.
+///
+/// Only writes the compare registers, so it can be called from any context (e.g. the output
+/// watchdog) while the ESC driver is owned by the control loop.
+pub fn stop_esc_outputs() {
+    pac::TIM3.ccr(2).write(|w| w.set_ccr(0));
+    pac::TIM3.ccr(3).write(|w| w.set_ccr(0));
+    pac::TIM5.ccr(0).write(|w| w.set_ccr(0));
+    pac::TIM5.ccr(2).write(|w| w.set_ccr(0));
+}
+
+/// Reads and clears the reset flags.
+fn reset_reason() -> ResetReason {
+    let csr = pac::RCC.csr().read();
+    let reason = if csr.iwdgrstf() {
+        ResetReason::IndependentWatchdog
+    } else if csr.wwdgrstf() {
+        ResetReason::WindowWatchdog
+    } else if csr.lpwrrstf() {
+        ResetReason::LowPower
+    } else if csr.sftrstf() {
+        ResetReason::Software
+    } else if csr.porrstf() {
+        // Power-on also sets the brownout and pin flags.
+        ResetReason::PowerOn
+    } else if csr.borrstf() {
+        ResetReason::Brownout
+    } else if csr.pinrstf() {
+        ResetReason::Pin
+    } else {
+        ResetReason::Unknown
+    };
+    pac::RCC.csr().modify(|w| w.set_rmvf(true));
+    reason
+}
+
 pub struct BlackpillEscDriver {
     pwm_tim5: SimplePwm<'static, TIM5>,
     pwm_tim3: SimplePwm<'static, TIM3>,
-    offset_tim5: u16,
-    offset_tim3: u16,
+    protocol: pwm::Protocol,
+    rate: u32,
 }
 
 impl BlackpillEscDriver {
@@ -201,9 +439,13 @@ impl BlackpillEscDriver {
     /// M2: PB1
     /// M3: PA0 (TX4, M3 seems to be broken on flightcontroller board)
     /// M4: PA2
+    ///
+    /// The timers need to run at `rate`, the pulse width depends on the `protocol`.
     pub fn init(
         mut pwm_tim5: SimplePwm<'static, TIM5>,
         mut pwm_tim3: SimplePwm<'static, TIM3>,
+        protocol: pwm::Protocol,
+        rate: Hertz,
     ) -> Self {
         {
             pwm_tim5.ch1().enable();
@@ -213,39 +455,284 @@ impl BlackpillEscDriver {
             pwm_tim3.ch3().enable();
             pwm_tim3.ch4().enable();
         }
-        let offset_tim5 = (pwm_tim5.max_duty_cycle() as f32 * 0.05) as u16;
-        let offset_tim3 = (pwm_tim3.max_duty_cycle() as f32 * 0.05) as u16;
         Self {
             pwm_tim5,
             pwm_tim3,
-            offset_tim5,
-            offset_tim3,
+            protocol,
+            rate: rate.0,
         }
     }
 }
 
 impl EscDriver for BlackpillEscDriver {
-    fn update(&mut self, thrust: [f32; 4]) {
+    async fn update(&mut self, thrust: [f32; 4]) {
+        let max_duty_tim3 = self.pwm_tim3.max_duty_cycle();
+        let max_duty_tim5 = self.pwm_tim5.max_duty_cycle();
+        let duty = |thrust, max_duty| self.protocol.duty(thrust, self.rate, max_duty);
+        let duty = [
+            duty(thrust[0], max_duty_tim3),
+            duty(thrust[1], max_duty_tim3),
+            duty(thrust[2], max_duty_tim5),
+            duty(thrust[3], max_duty_tim5),
+        ];
         // M1 (PB0 @ TIM3)
-        self.pwm_tim3
-            .ch3()
-            .set_duty_cycle(calc(thrust[0], self.offset_tim3));
+        self.pwm_tim3.ch3().set_duty_cycle(duty[0]);
         // M2 (PB1 @ TIM3)
-        self.pwm_tim3
-            .ch4()
-            .set_duty_cycle(calc(thrust[1], self.offset_tim3));
+        self.pwm_tim3.ch4().set_duty_cycle(duty[1]);
         // M3 (PA0 @ TIM5)
-        self.pwm_tim5
-            .ch1()
-            .set_duty_cycle(calc(thrust[2], self.offset_tim5));
+        self.pwm_tim5.ch1().set_duty_cycle(duty[2]);
         // M4 (PA2 @ TIM5)
+        self.pwm_tim5.ch3().set_duty_cycle(duty[3]);
+    }
+}
+
+pub struct BlackpillServoDriver {
+    pwm_tim8: SimplePwm<'static, TIM8>,
+    pwm_tim2: SimplePwm<'static, TIM2>,
+}
+
+impl BlackpillServoDriver {
+    /// Create the servo PWM driver.
+    ///
+    /// S1: PC6
+    /// S2: PC7
+    /// S3: PC8
+    /// S4: PC9
+    /// S5: PA1
+    /// S6: PA3
+    ///
+    /// Disabled outputs stay low, so that the servos do not move.
+    pub fn init(
+        mut pwm_tim8: SimplePwm<'static, TIM8>,
+        mut pwm_tim2: SimplePwm<'static, TIM2>,
+    ) -> Self {
+        pwm_tim8.ch1().enable();
+        pwm_tim8.ch2().enable();
+        pwm_tim8.ch3().enable();
+        pwm_tim8.ch4().enable();
+        pwm_tim2.ch2().enable();
+        pwm_tim2.ch4().enable();
+        Self { pwm_tim8, pwm_tim2 }
+    }
+}
+
+impl ServoDriver for BlackpillServoDriver {
+    fn update(&mut self, positions: &[Option<f32>]) {
+        let max_duty_tim8 = self.pwm_tim8.max_duty_cycle();
+        let max_duty_tim2 = self.pwm_tim2.max_duty_cycle();
+        let duty = |i: usize, max_duty| match positions.get(i).copied().flatten() {
+            Some(position) => {
+                pwm::Protocol::Standard.duty((position + 1.0) / 2.0, SERVO_RATE, max_duty)
+            }
+            None => 0,
+        };
+        self.pwm_tim8.ch1().set_duty_cycle(duty(0, max_duty_tim8));
+        self.pwm_tim8.ch2().set_duty_cycle(duty(1, max_duty_tim8));
+        self.pwm_tim8.ch3().set_duty_cycle(duty(2, max_duty_tim8));
+        self.pwm_tim8.ch4().set_duty_cycle(duty(3, max_duty_tim8));
+        self.pwm_tim2.ch2().set_duty_cycle(duty(4, max_duty_tim2));
+        self.pwm_tim2.ch4().set_duty_cycle(duty(5, max_duty_tim2));
+    }
+}
+
+pub struct DShotEscDriver {
+    pwm_tim5: SimplePwm<'static, TIM5>,
+    pwm_tim3: SimplePwm<'static, TIM3>,
+    dma_tim5: Peri<'static, DMA1_CH0>,
+    dma_tim3: Peri<'static, DMA1_CH2>,
+    bits_tim5: (u16, u16),
+    bits_tim3: (u16, u16),
+    bidirectional: bool,
+    sample_period: u32, // [1/16 cycles]
+    erpm: [Option<u32>; 4],
+}
+
+impl DShotEscDriver {
+    /// Create the DShot driver, same motor mapping as `BlackpillEscDriver`.
+    ///
+    /// The timers need to run at the DShot bit rate. Each frame is written to the compare
+    /// registers by an update DMA burst.
+    ///
+    /// With `bidirectional`, the outputs are inverted and the pins are sampled for the eRPM
+    /// responses after each frame.
+    pub fn init(
+        mut pwm_tim5: SimplePwm<'static, TIM5>,
+        mut pwm_tim3: SimplePwm<'static, TIM3>,
+        dma_tim5: Peri<'static, DMA1_CH0>,
+        dma_tim3: Peri<'static, DMA1_CH2>,
+        bit_rate: Hertz,
+        bidirectional: bool,
+    ) -> Self {
+        let polarity = if bidirectional {
+            OutputPolarity::ActiveLow
+        } else {
+            OutputPolarity::ActiveHigh
+        };
+        // The outputs stay at the idle level between frames.
+        {
+            pwm_tim5.ch1().set_polarity(polarity);
+            pwm_tim5.ch3().set_polarity(polarity);
+            pwm_tim5.ch1().set_duty_cycle(0);
+            pwm_tim5.ch3().set_duty_cycle(0);
+            pwm_tim5.ch1().enable();
+            pwm_tim5.ch3().enable();
+        }
+        {
+            pwm_tim3.ch3().set_polarity(polarity);
+            pwm_tim3.ch4().set_polarity(polarity);
+            pwm_tim3.ch3().set_duty_cycle(0);
+            pwm_tim3.ch4().set_duty_cycle(0);
+            pwm_tim3.ch3().enable();
+            pwm_tim3.ch4().enable();
+        }
+        if bidirectional {
+            // The ESCs only drive the line while answering.
+            pac::GPIOA.pupdr().modify(|w| {
+                w.set_pupdr(0, Pupdr::PULL_UP);
+                w.set_pupdr(2, Pupdr::PULL_UP);
+            });
+            pac::GPIOB.pupdr().modify(|w| {
+                w.set_pupdr(0, Pupdr::PULL_UP);
+                w.set_pupdr(1, Pupdr::PULL_UP);
+            });
+            // The cycle counter times the sampling.
+            let mut core = unsafe { cortex_m::Peripherals::steal() };
+            core.DCB.enable_trace();
+            core.DWT.enable_cycle_counter();
+        }
+        // The response is sent at 5/4 of the frame bit rate.
+        let sample_rate = bit_rate.0 as u64 * 5 / 4 * RESPONSE_OVERSAMPLING as u64;
+        let sample_period = (SYSCLK as u64 * 16 / sample_rate) as u32;
+        let bits_tim5 = dshot::bit_duty(pwm_tim5.max_duty_cycle());
+        let bits_tim3 = dshot::bit_duty(pwm_tim3.max_duty_cycle());
+        Self {
+            pwm_tim5,
+            pwm_tim3,
+            dma_tim5,
+            dma_tim3,
+            bits_tim5,
+            bits_tim3,
+            bidirectional,
+            sample_period,
+            erpm: [None; 4],
+        }
+    }
+
+    /// Sends one DShot value per motor.
+    async fn send(&mut self, values: [u16; 4]) {
+        let frames = values.map(|value| {
+            if self.bidirectional {
+                dshot::encode_bidirectional_frame(value, false)
+            } else {
+                dshot::encode_frame(value, false)
+            }
+        });
+        let mut samples = [0u16; RESPONSE_SAMPLES];
+
+        // M1 and M2 on TIM3 CH3 and CH4, interleaved for the burst.
+        let m1 = dshot::frame_duty(frames[0], self.bits_tim3);
+        let m2 = dshot::frame_duty(frames[1], self.bits_tim3);
+        let mut duty_tim3 = [0u16; 2 * dshot::FRAME_SLOTS];
+        for (slots, (m1, m2)) in duty_tim3.chunks_exact_mut(2).zip(m1.iter().zip(&m2)) {
+            slots[0] = *m1;
+            slots[1] = *m2;
+        }
+        self.pwm_tim3
+            .waveform_up_multi_channel(
+                self.dma_tim3.reborrow(),
+                Channel::Ch3,
+                Channel::Ch4,
+                &duty_tim3,
+            )
+            .await;
+        if self.bidirectional {
+            capture(pac::GPIOB, [0, 1], self.sample_period, &mut samples);
+            self.erpm[0] = decode_erpm(&samples, 0);
+            self.erpm[1] = decode_erpm(&samples, 1);
+        }
+
+        // M3 and M4 on TIM5 CH1 and CH3, the burst also covers the unused CH2.
+        let m3 = dshot::frame_duty(frames[2], self.bits_tim5);
+        let m4 = dshot::frame_duty(frames[3], self.bits_tim5);
+        let mut duty_tim5 = [0u16; 3 * dshot::FRAME_SLOTS];
+        for (slots, (m3, m4)) in duty_tim5.chunks_exact_mut(3).zip(m3.iter().zip(&m4)) {
+            slots[0] = *m3;
+            slots[2] = *m4;
+        }
         self.pwm_tim5
-            .ch3()
-            .set_duty_cycle(calc(thrust[3], self.offset_tim5));
+            .waveform_up_multi_channel(
+                self.dma_tim5.reborrow(),
+                Channel::Ch1,
+                Channel::Ch3,
+                &duty_tim5,
+            )
+            .await;
+        if self.bidirectional {
+            capture(pac::GPIOA, [0, 2], self.sample_period, &mut samples);
+            self.erpm[2] = decode_erpm(&samples, 0);
+            self.erpm[3] = decode_erpm(&samples, 2);
+        }
+    }
+}
+
+impl EscDriver for DShotEscDriver {
+    async fn update(&mut self, thrust: [f32; 4]) {
+        self.send(thrust.map(dshot::throttle_value)).await;
+    }
+
+    async fn command(&mut self, motor: Option<usize>, command: EscCommand) -> bool {
+        let command = match command {
+            EscCommand::Beep => dshot::Command::Beep1,
+            EscCommand::SpinDirectionNormal => dshot::Command::SpinDirectionNormal,
+            EscCommand::SpinDirectionReversed => dshot::Command::SpinDirectionReversed,
+            EscCommand::SaveSettings => dshot::Command::SaveSettings,
+        };
+        // The other motors receive "stop" frames.
+        let values = core::array::from_fn(|i| match motor {
+            Some(motor) if motor != i => 0,
+            _ => command as u16,
+        });
+        for _ in 0..command.repeat() {
+            self.send(values).await;
+            Timer::after_millis(1).await;
+        }
+        Timer::after_millis(command.delay_ms()).await;
+        true
+    }
+
+    fn erpm(&self) -> [Option<u32>; 4] {
+        self.erpm
     }
 }
 
-fn calc(input: f32, offset: u16) -> u16 {
-    let clamped = f32::max(f32::min(input, 1.0), 0.0);
-    (clamped * offset as f32) as u16 + offset
+/// Switches two motor pins of `port` to inputs and samples the port while the ESCs answer.
+///
+/// Interrupts are disabled while sampling (about 110 us with DShot600).
+fn capture(port: Gpio, pins: [usize; 2], period: u32, samples: &mut [u16; RESPONSE_SAMPLES]) {
+    cortex_m::interrupt::free(|_| {
+        port.moder().modify(|w| {
+            for pin in pins {
+                w.set_moder(pin, Moder::INPUT);
+            }
+        });
+        let start = DWT::cycle_count();
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let due = (i as u32 * period) >> 4;
+            while DWT::cycle_count().wrapping_sub(start) < due {}
+            *sample = port.idr().read().0 as u16;
+        }
+        port.moder().modify(|w| {
+            for pin in pins {
+                w.set_moder(pin, Moder::ALTERNATE);
+            }
+        });
+    });
+}
+
+fn decode_erpm(samples: &[u16; RESPONSE_SAMPLES], pin: usize) -> Option<u32> {
+    let levels = samples.iter().map(|sample| sample & (1 << pin) != 0);
+    dshot::response_levels(levels, RESPONSE_OVERSAMPLING as f32)
+        .and_then(dshot::decode_response)
+        .map(dshot::erpm)
+        .ok()
 }
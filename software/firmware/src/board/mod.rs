@@ -4,6 +4,25 @@ pub use flightcontroller::*;
 #[cfg(feature = "flightcontroller")]
 mod flightcontroller;
 
+use protocol::EscCommand;
+
+#[allow(async_fn_in_trait)]
 pub trait EscDriver {
-    fn update(&mut self, thrust: [f32; 4]);
+    async fn update(&mut self, thrust: [f32; 4]);
+
+    /// Sends a special command to one motor (or all, if `None`). Returns `false` if the
+    /// protocol does not support special commands.
+    async fn command(&mut self, _motor: Option<usize>, _command: EscCommand) -> bool {
+        false
+    }
+
+    /// Electrical RPM per motor, if the protocol reports it.
+    fn erpm(&self) -> [Option<u32>; 4] {
+        [None; 4]
+    }
+}
+
+pub trait ServoDriver {
+    /// Sets the servo positions `[-1.0 .. 1.0]`, one per output. `None` stops the pulses.
+    fn update(&mut self, positions: &[Option<f32>]);
 }
@@ -0,0 +1,167 @@
+use defmt::info;
+use embassy_time::Timer;
+use protocol::CalibrationResult;
+use protocol::CalibrationStep;
+use stabilization::calibration::AccelCalibrator;
+use stabilization::calibration::AccelOrientation;
+use stabilization::calibration::CalibrationError;
+use stabilization::calibration::GyroCalibrator;
+use stabilization::calibration::GyroTemperatureCalibrator;
+use stabilization::calibration::ImuCalibration;
+use stabilization::calibration::MotionThresholds;
+use stabilization::magnetometer::MagCalibration;
+use stabilization::magnetometer::MagCalibrator;
+
+use crate::imu::Driver;
+use crate::imu::Imu;
+
+const SAMPLE_PERIOD_MS: u64 = 2;
+const GYRO_SAMPLES: u32 = 500;
+const ACCEL_SAMPLES: u32 = 250;
+/// Minimum distance between accepted magnetometer samples, in sensor units.
+const MAG_SAMPLE_DISTANCE: f32 = 2.0;
+
+/// Drives the calibration steps requested by the remote.
+pub struct Calibration {
+    accel: AccelCalibrator,
+    temperature: Option<GyroTemperatureCalibrator>,
+    mag: Option<MagCalibrator>,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Self {
+            accel: AccelCalibrator::new(ACCEL_SAMPLES, MotionThresholds::default()),
+            temperature: None,
+            mag: None,
+        }
+    }
+
+    /// Estimates the gyro bias, refused if the vehicle is moving.
+    pub async fn gyro<D: Driver>(&mut self, imu: &mut Imu<D>) -> Result<(), CalibrationError> {
+        let mut calibrator = GyroCalibrator::new(GYRO_SAMPLES, MotionThresholds::default());
+        loop {
+            let (gyro, accel, temperature) = imu.get_raw();
+            if calibrator.add(gyro, accel, temperature) {
+                break;
+            }
+            Timer::after_millis(SAMPLE_PERIOD_MS).await;
+        }
+        calibrator.finish(imu.calibration_mut())?;
+        info!("Gyro bias: {}", imu.calibration().gyro_bias);
+        Ok(())
+    }
+
+    /// Runs a calibration step. Returns whether the calibration changed and needs to be stored.
+    pub async fn run<D: Driver>(
+        &mut self,
+        step: CalibrationStep,
+        imu: &mut Imu<D>,
+    ) -> Result<bool, CalibrationError> {
+        match step {
+            CalibrationStep::Gyro => {
+                self.gyro(imu).await?;
+                Ok(true)
+            }
+            CalibrationStep::Accel { orientation } => {
+                loop {
+                    let (_, accel, _) = imu.get_raw();
+                    if self.accel.add(accel) {
+                        break;
+                    }
+                    Timer::after_millis(SAMPLE_PERIOD_MS).await;
+                }
+                self.accel
+                    .finish_orientation(map_orientation(orientation))?;
+                Ok(false)
+            }
+            CalibrationStep::AccelFinish => {
+                self.accel.finish(imu.calibration_mut())?;
+                self.accel = AccelCalibrator::new(ACCEL_SAMPLES, MotionThresholds::default());
+                info!(
+                    "Accel offset: {}, scale: {}",
+                    imu.calibration().accel_offset,
+                    imu.calibration().accel_scale
+                );
+                Ok(true)
+            }
+            CalibrationStep::GyroTemperatureStart => {
+                if imu.get_raw().2.is_none() {
+                    return Err(CalibrationError::NoTemperature);
+                }
+                self.temperature = Some(GyroTemperatureCalibrator::default());
+                Ok(false)
+            }
+            CalibrationStep::GyroTemperatureFinish => {
+                let Some(temperature) = self.temperature.take() else {
+                    return Err(CalibrationError::Incomplete);
+                };
+                temperature.finish(imu.calibration_mut())?;
+                Ok(true)
+            }
+            CalibrationStep::MagStart => {
+                self.mag = Some(MagCalibrator::new(MAG_SAMPLE_DISTANCE));
+                Ok(false)
+            }
+            CalibrationStep::MagFinish => {
+                let Some(mag) = self.mag.take() else {
+                    return Err(CalibrationError::Incomplete);
+                };
+                let mag_calibration = mag.finish()?;
+                info!(
+                    "Mag offset: {}, soft iron: {}",
+                    mag_calibration.offset, mag_calibration.soft_iron
+                );
+                imu.set_mag_calibration(mag_calibration);
+                Ok(true)
+            }
+            CalibrationStep::Reset => {
+                *imu.calibration_mut() = ImuCalibration::default();
+                imu.set_mag_calibration(MagCalibration::default());
+                Ok(true)
+            }
+        }
+    }
+
+    /// Feeds the temperature drift and magnetometer calibrations, if they are running.
+    ///
+    /// The vehicle needs to stay still while it warms up, but has to be rotated in all
+    /// directions for the magnetometer calibration.
+    pub fn sample<D: Driver>(&mut self, imu: &mut Imu<D>) {
+        if let Some(calibrator) = self.temperature.as_mut() {
+            let (gyro, _, temperature) = imu.get_raw();
+            if let Some(temperature) = temperature {
+                calibrator.add(gyro, temperature);
+            }
+        }
+        if let Some(calibrator) = self.mag.as_mut() {
+            imu.get_raw();
+            if let Some(mag) = imu.get_raw_magnetometer() {
+                calibrator.add(mag);
+            }
+        }
+    }
+}
+
+pub fn to_result(result: Result<bool, CalibrationError>) -> CalibrationResult {
+    match result {
+        Ok(_) => CalibrationResult::Ok,
+        Err(CalibrationError::Motion) => CalibrationResult::Motion,
+        Err(CalibrationError::WrongOrientation) => CalibrationResult::WrongOrientation,
+        Err(CalibrationError::Incomplete) => CalibrationResult::Incomplete,
+        Err(CalibrationError::TemperatureRange) => CalibrationResult::TemperatureRange,
+        Err(CalibrationError::NoTemperature) => CalibrationResult::NoTemperature,
+        Err(CalibrationError::Fit) => CalibrationResult::Fit,
+    }
+}
+
+fn map_orientation(orientation: protocol::AccelOrientation) -> AccelOrientation {
+    match orientation {
+        protocol::AccelOrientation::Level => AccelOrientation::Level,
+        protocol::AccelOrientation::UpsideDown => AccelOrientation::UpsideDown,
+        protocol::AccelOrientation::NoseUp => AccelOrientation::NoseUp,
+        protocol::AccelOrientation::NoseDown => AccelOrientation::NoseDown,
+        protocol::AccelOrientation::LeftSideDown => AccelOrientation::LeftSideDown,
+        protocol::AccelOrientation::RightSideDown => AccelOrientation::RightSideDown,
+    }
+}
@@ -0,0 +1,145 @@
+use defmt::warn;
+use drivers::gps::Event;
+use drivers::gps::Parser;
+use drivers::gps::Solution;
+use drivers::ubx;
+use embassy_time::Duration;
+use embassy_time::Timer;
+use embassy_time::with_timeout;
+
+pub use crate::board::GpsUart;
+
+/// Baud rates at which a receiver is searched, the u-blox default first.
+const BAUDRATES: [u32; 5] = [9600, 38400, 57600, 115_200, 230_400];
+/// Baud rate of configured u-blox receivers.
+const BAUDRATE: u32 = 115_200;
+/// Period of the navigation solution [ms].
+const NAVIGATION_PERIOD: u16 = 200;
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+/// NMEA receivers send at least one GGA sentence per second.
+const NMEA_TIMEOUT: Duration = Duration::from_millis(1500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, defmt::Format)]
+pub enum Protocol {
+    Ubx,
+    Nmea,
+}
+
+pub struct Gps {
+    uart: GpsUart,
+    parser: Parser,
+    buf: [u8; 128],
+    /// Range of received bytes in `buf` not fed to the parser yet.
+    pending: core::ops::Range<usize>,
+}
+
+impl Gps {
+    pub fn init(uart: GpsUart) -> Self {
+        Self {
+            uart,
+            parser: Parser::new(),
+            buf: [0; 128],
+            pending: 0..0,
+        }
+    }
+
+    /// Configures a u-blox receiver for UBX, otherwise searches for a NMEA receiver. Returns
+    /// `None` if no receiver was found.
+    pub async fn configure(&mut self) -> Option<Protocol> {
+        // The port configuration is sent at all baud rates, as the current one is unknown.
+        for baudrate in BAUDRATES {
+            self.set_baudrate(baudrate);
+            self.send(&ubx::cfg_prt(BAUDRATE)).await;
+            // Let the last bytes go out before changing the baud rate again.
+            Timer::after_millis(10).await;
+        }
+        self.set_baudrate(BAUDRATE);
+        let mut ubx = true;
+        for frame in [
+            ubx::cfg_rate(NAVIGATION_PERIOD),
+            ubx::cfg_nav5(),
+            ubx::cfg_msg(ubx::CLASS_NAV, ubx::NAV_PVT, 1),
+        ] {
+            ubx = ubx && self.command(&frame).await;
+        }
+        if ubx {
+            return Some(Protocol::Ubx);
+        }
+
+        for baudrate in BAUDRATES {
+            self.set_baudrate(baudrate);
+            let nmea = async {
+                loop {
+                    if let Event::Nmea(_) = self.next_event().await {
+                        break;
+                    }
+                }
+            };
+            if with_timeout(NMEA_TIMEOUT, nmea).await.is_ok() {
+                return Some(Protocol::Nmea);
+            }
+        }
+        None
+    }
+
+    /// Waits for the next navigation solution.
+    pub async fn next(&mut self) -> Solution {
+        loop {
+            if let Some(solution) = self.next_event().await.solution() {
+                return solution;
+            }
+        }
+    }
+
+    async fn next_event(&mut self) -> Event {
+        loop {
+            for i in self.pending.clone() {
+                self.pending.start = i + 1;
+                if let Some(event) = self.parser.push(self.buf[i]) {
+                    return event;
+                }
+            }
+            match self.uart.read_until_idle(&mut self.buf).await {
+                Ok(len) => self.pending = 0..len,
+                // Expected while probing the wrong baud rate.
+                Err(_) => self.pending = 0..0,
+            }
+        }
+    }
+
+    /// Sends a configuration message, returns whether the receiver acknowledged it.
+    async fn command(&mut self, frame: &ubx::Frame) -> bool {
+        self.send(frame).await;
+        let ack = async {
+            loop {
+                match self.next_event().await {
+                    Event::Ubx(ubx::Message::Ack { class, id })
+                        if (class, id) == (frame.class(), frame.id()) =>
+                    {
+                        return true;
+                    }
+                    Event::Ubx(ubx::Message::Nak { class, id })
+                        if (class, id) == (frame.class(), frame.id()) =>
+                    {
+                        return false;
+                    }
+                    _ => {}
+                }
+            }
+        };
+        with_timeout(ACK_TIMEOUT, ack).await.unwrap_or(false)
+    }
+
+    async fn send(&mut self, frame: &ubx::Frame) {
+        if let Err(e) = self.uart.write(frame.as_bytes()).await {
+            warn!("Failed to send to GPS: {}", e);
+        }
+    }
+
+    fn set_baudrate(&mut self, baudrate: u32) {
+        if let Err(e) = self.uart.set_baudrate(baudrate) {
+            warn!("Failed to set GPS baud rate {}: {}", baudrate, e);
+        }
+        self.pending = 0..0;
+    }
+}
@@ -1,34 +1,87 @@
 use defmt::info;
 use embassy_time::Delay;
+use stabilization::calibration::ImuCalibration;
+use stabilization::magnetometer::MagCalibration;
 
 pub use crate::board::{ImuCs, ImuSpi};
 
 pub trait Driver {
     fn init(spi: ImuSpi, cs: ImuCs) -> Self;
     fn get_rotations(&mut self) -> ([f32; 3], [f32; 3]);
+    /// Die temperature in degree celsius, if the sensor provides it.
+    fn get_temperature(&mut self) -> Option<f32>;
+    /// Magnetic field from the last `get_rotations` call, if the sensor has a magnetometer.
+    fn get_magnetometer(&mut self) -> Option<[f32; 3]>;
 }
 
+#[cfg(not(feature = "imu-mpu9250"))]
+pub type ImuDriver = Icm20689;
+#[cfg(feature = "imu-mpu9250")]
+pub type ImuDriver = Mpu9250;
+
 pub struct Imu<D: Driver> {
     driver: D,
+    calibration: ImuCalibration,
+    mag_calibration: MagCalibration,
 }
 
 impl<D> Imu<D>
 where
     D: Driver,
 {
-    pub fn init(driver: D) -> Self {
-        Self { driver }
+    pub fn init(driver: D, calibration: ImuCalibration, mag_calibration: MagCalibration) -> Self {
+        Self {
+            driver,
+            calibration,
+            mag_calibration,
+        }
     }
 
+    pub fn calibration(&self) -> &ImuCalibration {
+        &self.calibration
+    }
+
+    pub fn calibration_mut(&mut self) -> &mut ImuCalibration {
+        &mut self.calibration
+    }
+
+    pub fn mag_calibration(&self) -> &MagCalibration {
+        &self.mag_calibration
+    }
+
+    pub fn set_mag_calibration(&mut self, mag_calibration: MagCalibration) {
+        self.mag_calibration = mag_calibration;
+    }
+
+    /// Returns calibrated `(gyro, accel)` readings.
     pub fn get_rotations(&mut self) -> ([f32; 3], [f32; 3]) {
-        self.driver.get_rotations()
+        let (gyro, accel, temperature) = self.get_raw();
+        self.calibration.apply(gyro, accel, temperature)
+    }
+
+    /// Returns uncalibrated `(gyro, accel, temperature)` readings.
+    pub fn get_raw(&mut self) -> ([f32; 3], [f32; 3], Option<f32>) {
+        let (gyro, accel) = self.driver.get_rotations();
+        (gyro, accel, self.driver.get_temperature())
+    }
+
+    /// Returns the calibrated magnetometer reading that belongs to the last IMU reading.
+    pub fn get_magnetometer(&mut self) -> Option<[f32; 3]> {
+        self.get_raw_magnetometer()
+            .map(|mag| self.mag_calibration.apply(mag))
+    }
+
+    pub fn get_raw_magnetometer(&mut self) -> Option<[f32; 3]> {
+        self.driver.get_magnetometer()
     }
 }
 
+#[cfg(not(feature = "imu-mpu9250"))]
 pub struct Icm20689 {
     driver: icm20689::ICM20689<icm20689::SpiInterface<ImuSpi, ImuCs>>,
 }
 
+#[cfg(not(feature = "imu-mpu9250"))]
 impl Driver for Icm20689 {
     fn init(spi: ImuSpi, cs: ImuCs) -> Self {
         let mut delay = Delay;
@@ -53,4 +106,56 @@ impl Driver for Icm20689 {
         let gyro = self.driver.get_scaled_gyro().unwrap();
         (gyro, accel)
     }
+
+    /// The icm20689 crate does not read the temperature, so the gyro temperature compensation
+    /// is only available with the MPU-9250.
+    fn get_temperature(&mut self) -> Option<f32> {
+        None
+    }
+
+    fn get_magnetometer(&mut self) -> Option<[f32; 3]> {
+        None
+    }
+}
+
+/// MPU-9250 of the custom flight controller, including its AK8963 magnetometer.
+#[cfg(feature = "imu-mpu9250")]
+pub struct Mpu9250 {
+    driver: mpu9250::Mpu9250<mpu9250::SpiDevice<ImuSpi, ImuCs>, mpu9250::Marg>,
+    mag: [f32; 3],
+    temperature: f32,
+}
+
+#[cfg(feature = "imu-mpu9250")]
+impl Driver for Mpu9250 {
+    fn init(spi: ImuSpi, cs: ImuCs) -> Self {
+        let mut delay = Delay;
+        let driver = mpu9250::Mpu9250::marg_default(spi, cs, &mut delay).unwrap();
+        info!("MPU-9250 initialized");
+        Self {
+            driver,
+            mag: [0.0; 3],
+            temperature: 0.0,
+        }
+    }
+
+    fn get_rotations(&mut self) -> ([f32; 3], [f32; 3]) {
+        const STANDARD_GRAVITY: f32 = 9.80665;
+        let measurements = self.driver.all::<[f32; 3]>().unwrap();
+        self.mag = measurements.mag;
+        self.temperature = measurements.temp;
+        // The mpu9250 crate reports rad/s and m/s^2, the rest of the firmware uses dps and g.
+        (
+            measurements.gyro.map(f32::to_degrees),
+            measurements.accel.map(|a| a / STANDARD_GRAVITY),
+        )
+    }
+
+    fn get_temperature(&mut self) -> Option<f32> {
+        Some(self.temperature)
+    }
+
+    fn get_magnetometer(&mut self) -> Option<[f32; 3]> {
+        Some(self.mag)
+    }
 }
@@ -3,38 +3,223 @@
 
 use defmt::{error, info, warn};
 use defmt_rtt as _;
+use drivers::dshot;
+use drivers::gps::FixType;
+use drivers::gps::Solution;
+use embassy_executor::InterruptExecutor;
 use embassy_executor::Spawner;
+use embassy_stm32::interrupt;
+use embassy_stm32::interrupt::InterruptExt;
+use embassy_stm32::interrupt::Priority;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::mutex::Mutex;
-use embassy_time::Timer;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Ticker;
 use embedded_io_async::Write;
-use panic_probe as _;
 use stabilization::Kf;
+use stabilization::allocation::ActuatorCommands;
+use stabilization::allocation::ControlAllocator;
+use stabilization::allocation::Demand;
+use stabilization::altitude::AltitudeEstimator;
+use stabilization::altitude_hold::ThrottleController;
+use stabilization::altitude_hold::ThrottleMode;
+use stabilization::attitude::AttitudeBackend;
+use stabilization::esc_calibration::EscCalibration;
+use stabilization::esc_calibration::EscCalibrationError;
+use stabilization::esc_calibration::EscCalibrationPhase;
+use stabilization::fixed_wing::FixedWingConfig;
+use stabilization::fixed_wing::FixedWingController;
+use stabilization::fixed_wing::FixedWingState;
+use stabilization::geofence;
+use stabilization::geofence::Breach;
+use stabilization::geofence::FenceError;
+use stabilization::geofence::Geofence;
+use stabilization::hover::HoverController;
+use stabilization::landing::LandingDetector;
+use stabilization::landing::TakeoffRamp;
+use stabilization::mission;
+use stabilization::mission::Mission;
+use stabilization::mission::MissionError;
+use stabilization::mission::MissionExecutor;
+use stabilization::motor_test::MotorTest;
+use stabilization::motor_test::MotorTestError;
+use stabilization::navigation::GpsMeasurement;
+use stabilization::navigation::NavigationFilter;
+use stabilization::output::OutputConditioner;
+use stabilization::position_hold::PositionController;
+use stabilization::return_home::ReturnConfig;
+use stabilization::return_home::ReturnPhase;
+use stabilization::return_home::ReturnToHome;
+use stabilization::servo;
+use stabilization::servo::FlightControls;
+use stabilization::servo::Servo;
+use stabilization::transition::TransitionController;
+use stabilization::transition::TransitionOutput;
+use stabilization::transition::TransitionState;
+use stabilization::yaw::YawMixer;
 
+mod baro;
 mod board;
+mod calibration;
+mod gps;
 mod imu;
 mod radio;
+mod storage;
+mod watchdog;
 
+use baro::Baro;
 use board::Board;
 use board::UsbDevice;
 use board::UsbReceiver;
+use calibration::Calibration;
+use gps::Gps;
 use imu::Driver;
 use imu::Imu;
+use imu::ImuDriver;
+use protocol::AttitudeEstimator;
+use protocol::CalibrationResult;
+use protocol::CalibrationStep;
+use protocol::EscCalibrationResult;
+use protocol::EscCalibrationStep;
+use protocol::EscCommand;
+use protocol::EscProtocol;
+use protocol::FailsafeAction;
+use protocol::FenceAction;
+use protocol::FenceBreach;
+use protocol::FenceResult;
+use protocol::FlightMode;
+use protocol::GpsFix;
 use protocol::Message;
+use protocol::MissionItem;
+use protocol::MissionResult;
+use protocol::MotorTestCommand;
+use protocol::MotorTestResult;
+use protocol::OutputSetting;
+use protocol::ServoFunction;
+use protocol::ServoSetting;
 use radio::Radio;
+use storage::Settings;
+use storage::Storage;
 
 use crate::board::EscDriver;
+use crate::board::SERVO_COUNT;
+use crate::board::ServoDriver;
 use crate::board::UsbSender;
 
 // Maybe needs to be improved later to some  double buffering with atomic pointer switching.
 static THRUST: Mutex<CriticalSectionRawMutex, [f32; 4]> = Mutex::new([0.0; 4]);
+static YAW: Mutex<CriticalSectionRawMutex, f32> = Mutex::new(0.0);
+/// Roll and pitch sticks.
+static STICKS: Mutex<CriticalSectionRawMutex, [f32; 2]> = Mutex::new([0.0; 2]);
+/// Reception time of the last radio frame.
+static RADIO_RECEIVED: Mutex<CriticalSectionRawMutex, Option<Instant>> = Mutex::new(None);
+static FLIGHT_MODE: Mutex<CriticalSectionRawMutex, FlightMode> = Mutex::new(FlightMode::Manual);
+static ARM_SWITCH: Mutex<CriticalSectionRawMutex, bool> = Mutex::new(false);
+static FORWARD_SWITCH: Mutex<CriticalSectionRawMutex, bool> = Mutex::new(false);
 static USB_CONNECTED: Mutex<CriticalSectionRawMutex, bool> = Mutex::new(false);
+static GPS_SOLUTION: Mutex<CriticalSectionRawMutex, Option<Solution>> = Mutex::new(None);
+static CALIBRATION_REQUEST: Mutex<CriticalSectionRawMutex, Option<CalibrationStep>> =
+    Mutex::new(None);
+static DECLINATION_REQUEST: Mutex<CriticalSectionRawMutex, Option<f32>> = Mutex::new(None);
+static ESC_PROTOCOL_REQUEST: Mutex<CriticalSectionRawMutex, Option<(EscProtocol, Option<u16>)>> =
+    Mutex::new(None);
+static MOTOR_POLES_REQUEST: Mutex<CriticalSectionRawMutex, Option<u8>> = Mutex::new(None);
+static OUTPUT_TIMEOUT_REQUEST: Mutex<CriticalSectionRawMutex, Option<u16>> = Mutex::new(None);
+static MOTOR_TEST_REQUEST: Mutex<CriticalSectionRawMutex, Option<MotorTestCommand>> =
+    Mutex::new(None);
+static MOTOR_TEST_THRUST: Mutex<CriticalSectionRawMutex, Option<[f32; 4]>> = Mutex::new(None);
+static ESC_CALIBRATION_REQUEST: Mutex<CriticalSectionRawMutex, Option<EscCalibrationStep>> =
+    Mutex::new(None);
+static SERVO_REQUEST: Mutex<CriticalSectionRawMutex, Option<(u8, ServoSetting)>> = Mutex::new(None);
+static OUTPUT_REQUEST: Mutex<CriticalSectionRawMutex, Option<OutputSetting>> = Mutex::new(None);
+static ATTITUDE_ESTIMATOR_REQUEST: Mutex<CriticalSectionRawMutex, Option<AttitudeEstimator>> =
+    Mutex::new(None);
+static ESC_COMMAND_REQUEST: Mutex<CriticalSectionRawMutex, Option<(Option<u8>, EscCommand)>> =
+    Mutex::new(None);
+static FAILSAFE_ACTION_REQUEST: Mutex<CriticalSectionRawMutex, Option<FailsafeAction>> =
+    Mutex::new(None);
+static MISSION_REQUEST: Mutex<CriticalSectionRawMutex, Option<MissionRequest>> = Mutex::new(None);
+static FENCE_REQUEST: Mutex<CriticalSectionRawMutex, Option<FenceRequest>> = Mutex::new(None);
+
+/// The uploader waits for the acknowledgement of each item, so one request is pending at most.
+enum MissionRequest {
+    Item {
+        index: u16,
+        count: u16,
+        item: MissionItem,
+    },
+    Clear,
+}
+
+/// Like mission items, each vertex is acknowledged before the next one is sent.
+enum FenceRequest {
+    Limits {
+        max_altitude: Option<f32>,
+        max_distance: Option<f32>,
+        vertices: u8,
+        action: FenceAction,
+    },
+    Vertex {
+        index: u8,
+        latitude: i32,
+        longitude: i32,
+    },
+}
+
+/// Runs the output watchdog, so that it preempts the control loop.
+static SUPERVISOR_EXECUTOR: InterruptExecutor = InterruptExecutor::new();
+
+#[interrupt]
+unsafe fn UART4() {
+    unsafe { SUPERVISOR_EXECUTOR.on_interrupt() }
+}
+
+const BOOT_CALIBRATION_ATTEMPTS: u32 = 5;
+/// Arming is refused above this throttle.
+const ARM_THROTTLE_LIMIT: f32 = 0.05;
+const LOOP_PERIOD_MS: u64 = 20;
+const LOOP_DT: f32 = LOOP_PERIOD_MS as f32 / 1000.0; // [s]
+/// Telemetry is only sent every n-th control loop iteration.
+const TELEMETRY_DIVIDER: u32 = 10;
+/// The failsafe action starts if no radio frame was received for this time.
+const RADIO_TIMEOUT: Duration = Duration::from_millis(1000);
+/// Airspeed of the fixed-wing flight [m/s].
+const CRUISE_AIRSPEED: f32 = 18.0;
 
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
     info!("Starting ...");
-    let mut board = Board::init();
-    board.esc_driver.update([0.0; 4]);
+    let board = Board::init();
+
+    info!("Loading settings ...");
+    let mut storage = Storage::new(board.flash);
+    let mut settings = storage.load();
+    let mut mission = storage.load_mission();
+    info!("Done loading settings ({} mission items)", mission.len());
+
+    info!("Setting up ESCs ({}) ...", settings.esc_protocol);
+    let mut esc_driver = board.esc.init(settings.esc_protocol, settings.esc_rate);
+    let analog_esc = !matches!(settings.esc_protocol, EscProtocol::DShot { .. });
+    esc_driver.update([0.0; 4]).await;
+    info!("Done setting up ESCs");
+
+    info!(
+        "Setting up watchdogs (last reset: {}) ...",
+        board.reset_reason
+    );
+    watchdog::set_output_timeout(settings.output_timeout);
+    interrupt::UART4.set_priority(Priority::P6);
+    let supervisor_spawner = SUPERVISOR_EXECUTOR.start(interrupt::UART4);
+    if let Err(e) = supervisor_spawner.spawn(watchdog::supervise_outputs()) {
+        error!("Failed to spawn output watchdog task: {}", e);
+        panic!()
+    }
+    if let Err(e) = spawner.spawn(watchdog::feed_hardware(board.watchdog)) {
+        error!("Failed to spawn hardware watchdog task: {}", e);
+        panic!()
+    }
+    info!("Done setting up watchdogs");
 
     info!("Setting up usb ...");
     let (mut usb_sender, usb_receiver) = board.usb_class.split();
@@ -56,12 +241,86 @@ async fn main(spawner: Spawner) {
     }
     info!("Done setting up radio");
 
+    info!("Setting up GPS ...");
+    let gps = Gps::init(board.gps_uart);
+    if let Err(e) = spawner.spawn(poll_gps(gps)) {
+        error!("Failed to spawn GPS task: {}", e);
+        panic!()
+    }
+    info!("Done setting up GPS");
+
     info!("Setting up IMU ...");
-    let imu_driver = imu::Icm20689::init(board.imu_spi, board.imu_cs);
-    let mut imu = Imu::init(imu_driver);
-    let mut kf = Kf::new(0.02);
+    let imu_driver = ImuDriver::init(board.imu_spi, board.imu_cs);
+    let mut imu = Imu::init(
+        imu_driver,
+        settings.imu_calibration,
+        settings.mag_calibration,
+    );
+    let mut calibration = Calibration::new();
+    for attempt in 1..=BOOT_CALIBRATION_ATTEMPTS {
+        match calibration.gyro(&mut imu).await {
+            Ok(()) => break,
+            Err(e) => warn!(
+                "Gyro calibration failed (attempt {}): {}, keep the vehicle still",
+                attempt,
+                defmt::Debug2Format(&e)
+            ),
+        }
+    }
+    let mut kf = Kf::new(LOOP_DT, attitude_backend(settings.attitude_estimator));
+    kf.set_declination(settings.declination.to_radians());
     info!("Done setting up IMU");
 
+    info!("Setting up barometer ...");
+    let mut baro = Baro::init(board.baro_i2c).await;
+    let mut altitude_estimator = AltitudeEstimator::new(Default::default());
+    info!("Done setting up barometer");
+    let mut navigation = NavigationFilter::new(Default::default());
+    let mut position = PositionController::new(Default::default());
+    let return_config = ReturnConfig::default();
+    let mut return_home = ReturnToHome::new(return_config);
+    let mut mission_upload = Mission::new();
+    let mut mission_executor = MissionExecutor::new(Default::default());
+    let mut mission_active = false;
+    let mut mission_index = 0;
+    // Fence, action and number of vertices of the upload in progress.
+    let mut fence_upload: Option<(Geofence, FenceAction, u8)> = None;
+    let mut fence_breach: Option<Breach> = None;
+    // Breach action, until the flight mode is changed.
+    let mut fence_response: Option<FenceAction> = None;
+
+    let hover = HoverController::new(Default::default());
+    let mut attitude_converged = false;
+    let mut throttle = ThrottleController::new(Default::default());
+    let mut output = OutputConditioner::new(settings.output);
+    let mut motor_test = MotorTest::new(Default::default());
+    let mut esc_calibration = EscCalibration::new(Default::default());
+    let mut transition = TransitionController::new(Default::default());
+    let yaw_mixer = YawMixer::new(Default::default());
+    let mut allocator = ControlAllocator::new(Default::default());
+    let fixed_wing_config = FixedWingConfig::default();
+    let mut fixed_wing_control = FixedWingController::new(fixed_wing_config);
+    // Height [m] held in fixed-wing flight, moved by the climb rate.
+    let mut fixed_wing_height: Option<f32> = None;
+    let mut servo_driver = board.servos;
+    let mut servos = settings.servos.map(Servo::new);
+    let mut armed = false;
+    // Set while a new declination still needs to be stored.
+    let mut declination_changed = false;
+    // Set by the automatic disarming after a landing, until the arm switch is off.
+    let mut rearm_blocked = false;
+    let mut landing_detector = LandingDetector::new(Default::default());
+    let mut takeoff = TakeoffRamp::new(Default::default());
+    let mut ticker = Ticker::every(Duration::from_millis(LOOP_PERIOD_MS));
+    let mut iteration = 0u32;
+    let mut boot_reported = false;
+    let mut gps_solution: Option<Solution> = None;
+    // Without GPS, the modes needing a position fall back to altitude hold or a descent.
+    let mut position_available = false;
+    let mut heading_available = false;
+    let mut flight_mode = FlightMode::Manual;
+    let mut failsafe = false;
+    let mut auto: Option<FailsafeAction> = None;
     let mut thrust_old = [0f32; 4];
     loop {
         let thrust_input;
@@ -74,16 +333,834 @@ async fn main(spawner: Spawner) {
             thrust_old = thrust_input;
         }
 
+        let calibration_request = CALIBRATION_REQUEST.lock().await.take();
+        if let Some(step) = calibration_request {
+            // The calibration blocks the control loop, so it is only run on the ground.
+            let status = if armed {
+                warn!("Ignoring calibration {}, motors are armed", step);
+                CalibrationResult::Armed
+            } else {
+                info!("Calibrating: {}", step);
+                let result = calibration.run(step, &mut imu).await;
+                let mut status = calibration::to_result(result);
+                if result == Ok(true) {
+                    settings.imu_calibration = *imu.calibration();
+                    settings.mag_calibration = *imu.mag_calibration();
+                    if let Err(e) = storage.store(&settings) {
+                        error!("Failed to store settings: {}", e);
+                        status = CalibrationResult::StorageError;
+                    }
+                }
+                ticker.reset();
+                status
+            };
+            if *USB_CONNECTED.lock().await {
+                send_usb(
+                    &mut usb_sender,
+                    &Message::CalibrationStatus {
+                        step,
+                        result: status,
+                    },
+                )
+                .await;
+            }
+        }
+        calibration.sample(&mut imu);
+
+        let declination_request = DECLINATION_REQUEST.lock().await.take();
+        if let Some(declination) = declination_request {
+            info!("Setting declination: {}", declination);
+            kf.set_declination(declination.to_radians());
+            settings.declination = declination;
+            declination_changed = true;
+        }
+        // Erasing the flash stalls the control loop, so the declination is stored once disarmed.
+        if declination_changed && !armed {
+            declination_changed = false;
+            if let Err(e) = storage.store(&settings) {
+                error!("Failed to store settings: {}", e);
+            }
+        }
+
+        let failsafe_action_request = FAILSAFE_ACTION_REQUEST.lock().await.take();
+        if let Some(action) = failsafe_action_request {
+            if armed {
+                warn!("Ignoring failsafe action {}, motors are armed", action);
+            } else {
+                info!("Setting failsafe action: {}", action);
+                settings.failsafe_action = action;
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            }
+        }
+
+        let attitude_estimator_request = ATTITUDE_ESTIMATOR_REQUEST.lock().await.take();
+        if let Some(estimator) = attitude_estimator_request {
+            if armed {
+                warn!(
+                    "Ignoring attitude estimator {}, motors are armed",
+                    estimator
+                );
+            } else {
+                info!("Setting attitude estimator: {}", estimator);
+                kf.set_backend(attitude_backend(estimator));
+                settings.attitude_estimator = estimator;
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            }
+        }
+
+        let esc_protocol_request = ESC_PROTOCOL_REQUEST.lock().await.take();
+        if let Some((protocol, rate)) = esc_protocol_request {
+            if armed {
+                warn!("Ignoring ESC protocol {}, motors are armed", protocol);
+            } else {
+                info!(
+                    "Setting ESC protocol: {} at {} Hz (active after reboot)",
+                    protocol, rate
+                );
+                settings.esc_protocol = protocol;
+                settings.esc_rate = rate;
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            }
+        }
+
+        let motor_poles_request = MOTOR_POLES_REQUEST.lock().await.take();
+        if let Some(poles) = motor_poles_request {
+            if armed {
+                warn!("Ignoring motor poles {}, motors are armed", poles);
+            } else {
+                info!("Setting motor poles: {}", poles);
+                settings.motor_poles = poles;
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            }
+        }
+
+        let output_timeout_request = OUTPUT_TIMEOUT_REQUEST.lock().await.take();
+        if let Some(timeout) = output_timeout_request {
+            if armed {
+                warn!("Ignoring output timeout {} ms, motors are armed", timeout);
+            } else {
+                info!("Setting output timeout: {} ms", timeout);
+                watchdog::set_output_timeout(timeout);
+                settings.output_timeout = timeout;
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            }
+        }
+
+        let servo_request = SERVO_REQUEST.lock().await.take();
+        if let Some((index, setting)) = servo_request {
+            let index = usize::from(index);
+            if armed {
+                warn!("Ignoring setting for servo {}, motors are armed", index);
+            } else if index < SERVO_COUNT {
+                info!("Setting servo {}: {}", index, setting);
+                let config = &mut settings.servos[index];
+                match setting {
+                    ServoSetting::Function(function) => settings.servo_functions[index] = function,
+                    ServoSetting::Trim(trim) => config.trim = trim,
+                    ServoSetting::Endpoints { min, max } => config.endpoints = (min, max),
+                    ServoSetting::Reversed(reversed) => config.reversed = reversed,
+                    ServoSetting::Rate(rate) => config.rate = rate,
+                }
+                servos[index].set_config(settings.servos[index]);
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            } else {
+                warn!(
+                    "Ignoring setting for servo {}, only {} outputs",
+                    index, SERVO_COUNT
+                );
+            }
+        }
+
+        let output_request = OUTPUT_REQUEST.lock().await.take();
+        if let Some(setting) = output_request {
+            if armed {
+                warn!("Ignoring output setting {}, motors are armed", setting);
+            } else if let OutputSetting::Endpoints { motor, .. } = setting
+                && motor >= 4
+            {
+                warn!("Ignoring endpoints for motor {}, only 4 outputs", motor);
+            } else {
+                info!("Setting motor outputs: {}", setting);
+                let config = &mut settings.output;
+                match setting {
+                    OutputSetting::Idle(idle) => config.idle = idle,
+                    OutputSetting::Endpoints { motor, min, max } => {
+                        config.endpoints[usize::from(motor)] = (min, max)
+                    }
+                    OutputSetting::Linearize(linearize) => config.linearize = linearize,
+                }
+                output.set_config(settings.output);
+                if let Err(e) = storage.store(&settings) {
+                    error!("Failed to store settings: {}", e);
+                }
+            }
+        }
+
+        let esc_command_request = ESC_COMMAND_REQUEST.lock().await.take();
+        if let Some((motor, command)) = esc_command_request {
+            if armed {
+                warn!("Ignoring ESC command {}, motors are armed", command);
+            } else {
+                info!("Sending ESC command {} to motor {}", command, motor);
+                if !esc_driver.command(motor.map(usize::from), command).await {
+                    warn!("ESC protocol does not support commands");
+                }
+                ticker.reset();
+            }
+        }
+
+        let mission_request = MISSION_REQUEST.lock().await.take();
+        if let Some(request) = mission_request {
+            let (index, result) = match request {
+                MissionRequest::Clear if armed => (0, MissionResult::Armed),
+                MissionRequest::Clear => {
+                    info!("Clearing mission");
+                    mission = Mission::new();
+                    let result = match storage.store_mission(&mission) {
+                        Ok(()) => MissionResult::Cleared,
+                        Err(e) => {
+                            error!("Failed to store mission: {}", e);
+                            MissionResult::StorageError
+                        }
+                    };
+                    (0, result)
+                }
+                MissionRequest::Item { index, .. } if armed => (index, MissionResult::Armed),
+                MissionRequest::Item { index, count, .. }
+                    if usize::from(count) > mission::MAX_ITEMS =>
+                {
+                    (index, MissionResult::TooManyItems)
+                }
+                MissionRequest::Item { index, count, .. } if index >= count => {
+                    (index, MissionResult::OutOfSequence)
+                }
+                MissionRequest::Item { index, count, item } => {
+                    let result = match mission_upload.set(usize::from(index), mission_item(item)) {
+                        Err(e) => mission_error(e),
+                        Ok(()) if index + 1 < count => MissionResult::Accepted,
+                        Ok(()) => {
+                            info!("Storing mission ({} items)", count);
+                            mission = mission_upload.clone();
+                            match storage.store_mission(&mission) {
+                                Ok(()) => MissionResult::Stored,
+                                Err(e) => {
+                                    error!("Failed to store mission: {}", e);
+                                    MissionResult::StorageError
+                                }
+                            }
+                        }
+                    };
+                    (index, result)
+                }
+            };
+            if *USB_CONNECTED.lock().await {
+                send_usb(&mut usb_sender, &Message::MissionAck { index, result }).await;
+            }
+            ticker.reset();
+        }
+
+        let fence_request = FENCE_REQUEST.lock().await.take();
+        if let Some(request) = fence_request {
+            let (vertex, result) = match request {
+                FenceRequest::Limits { .. } if armed => (None, FenceResult::Armed),
+                FenceRequest::Limits { vertices, .. }
+                    if usize::from(vertices) > geofence::MAX_VERTICES =>
+                {
+                    (None, FenceResult::TooManyVertices)
+                }
+                FenceRequest::Limits {
+                    vertices: 1 | 2, ..
+                } => (None, FenceResult::TooFewVertices),
+                FenceRequest::Limits {
+                    max_altitude,
+                    max_distance,
+                    vertices,
+                    action,
+                } => {
+                    let fence = Geofence::new(max_altitude, max_distance);
+                    if vertices == 0 {
+                        fence_upload = None;
+                        (
+                            None,
+                            store_fence(&mut storage, &mut settings, fence, action),
+                        )
+                    } else {
+                        fence_upload = Some((fence, action, vertices));
+                        (None, FenceResult::Accepted)
+                    }
+                }
+                FenceRequest::Vertex { index, .. } if armed => (Some(index), FenceResult::Armed),
+                FenceRequest::Vertex {
+                    index,
+                    latitude,
+                    longitude,
+                } => {
+                    let mut result = match &mut fence_upload {
+                        Some((fence, _, vertices)) if index < *vertices => {
+                            match fence.set_vertex(usize::from(index), [latitude, longitude]) {
+                                Err(e) => fence_error(e),
+                                Ok(()) if index + 1 < *vertices => FenceResult::Accepted,
+                                Ok(()) => FenceResult::Stored,
+                            }
+                        }
+                        _ => FenceResult::OutOfSequence,
+                    };
+                    if result == FenceResult::Stored
+                        && let Some((fence, action, _)) = fence_upload.take()
+                    {
+                        result = store_fence(&mut storage, &mut settings, fence, action);
+                    }
+                    (Some(index), result)
+                }
+            };
+            if *USB_CONNECTED.lock().await {
+                send_usb(&mut usb_sender, &Message::FenceAck { vertex, result }).await;
+            }
+            ticker.reset();
+        }
+
         let (gyro, accel) = imu.get_rotations();
-        let (rates, thrust) = kf.update(gyro, accel, thrust_input);
+        let mag = imu.get_magnetometer();
+        let attitude = kf.update(gyro, accel, mag);
+        if attitude.converged != attitude_converged {
+            attitude_converged = attitude.converged;
+            info!("Attitude estimate converged: {}", attitude_converged);
+        }
+        navigation.predict(kf.navigation_acceleration(), LOOP_DT);
+        if let Some(solution) = GPS_SOLUTION.lock().await.take() {
+            if gps_solution.map(|solution| solution.fix) != Some(solution.fix) {
+                info!(
+                    "GPS fix: {} ({} satellites)",
+                    defmt::Debug2Format(&solution.fix),
+                    solution.satellites
+                );
+            }
+            if let Some(measurement) = gps_measurement(&solution)
+                && !navigation.update_gps(&measurement)
+            {
+                warn!("GPS glitch, solution rejected");
+            }
+            gps_solution = Some(solution);
+        }
+        let baro_data = match baro.as_mut() {
+            Some(baro) => baro.read().await,
+            None => None,
+        };
+        altitude_estimator.predict(kf.vertical_acceleration(), LOOP_DT);
+        if let Some((_, pressure_altitude)) = baro_data {
+            let mean_thrust = thrust_input.iter().sum::<f32>() / 4.0;
+            altitude_estimator.update_baro(pressure_altitude, mean_thrust);
+            navigation.update_baro(pressure_altitude);
+        }
+
+        let mode = *FLIGHT_MODE.lock().await;
+        let stick = thrust_input.iter().sum::<f32>() / 4.0;
+        let arm_switch = *ARM_SWITCH.lock().await;
+        if !arm_switch {
+            rearm_blocked = false;
+        }
+        if arm_switch != armed {
+            if !arm_switch {
+                info!("Disarmed");
+                armed = false;
+            } else if rearm_blocked {
+                if iteration % TELEMETRY_DIVIDER == 0 {
+                    warn!("Not arming, disarmed after landing, switch off first");
+                }
+            } else if esc_calibration.active() {
+                if iteration % TELEMETRY_DIVIDER == 0 {
+                    warn!("Not arming, ESC calibration is running");
+                }
+            } else if !inside_fence(&settings.geofence, &navigation) {
+                if iteration % TELEMETRY_DIVIDER == 0 {
+                    warn!("Not arming, outside the geofence or no position");
+                }
+            } else if stick <= ARM_THROTTLE_LIMIT {
+                info!("Armed");
+                armed = true;
+                landing_detector.reset();
+                takeoff.stop();
+                throttle.reset();
+                altitude_estimator.set_ground_reference();
+                match navigation.estimate() {
+                    Some(estimate) => return_home.set_home(estimate.position),
+                    None => warn!("No home position, return to home lands in place"),
+                }
+            } else if iteration % TELEMETRY_DIVIDER == 0 {
+                warn!("Not arming, throttle is not low");
+            }
+        }
+
+        let mut motor_test_result = None;
+        let motor_test_request = MOTOR_TEST_REQUEST.lock().await.take();
+        match motor_test_request {
+            Some(MotorTestCommand::Start) => {
+                motor_test_result = Some(match motor_test.start(armed, stick) {
+                    Ok(()) => MotorTestResult::Started,
+                    Err(e) => motor_test_error(e),
+                });
+            }
+            Some(MotorTestCommand::Stop) => {
+                motor_test.stop();
+                motor_test_result = Some(MotorTestResult::Stopped);
+            }
+            Some(MotorTestCommand::KeepAlive) => {
+                if let Err(e) = motor_test.keep_alive() {
+                    motor_test_result = Some(motor_test_error(e));
+                }
+            }
+            None => {}
+        }
+        let motor_test_thrust = MOTOR_TEST_THRUST.lock().await.take();
+        if let Some(thrust) = motor_test_thrust
+            && let Err(e) = motor_test.set_thrust(thrust)
+        {
+            motor_test_result = Some(motor_test_error(e));
+        }
+        let motor_test_output = match motor_test.update(armed, stick, LOOP_DT) {
+            Ok(thrust) => thrust,
+            Err(e) => {
+                warn!("Motor test stopped: {}", defmt::Debug2Format(&e));
+                motor_test_result = Some(motor_test_error(e));
+                None
+            }
+        };
+        if let Some(result) = motor_test_result
+            && *USB_CONNECTED.lock().await
+        {
+            send_usb(&mut usb_sender, &Message::MotorTestStatus { result }).await;
+        }
+
+        let mut esc_calibration_result = None;
+        let esc_calibration_request = ESC_CALIBRATION_REQUEST.lock().await.take();
+        match esc_calibration_request {
+            Some(EscCalibrationStep::Start) if !analog_esc => {
+                esc_calibration_result = Some(EscCalibrationResult::NotSupported);
+            }
+            Some(EscCalibrationStep::Start) => {
+                esc_calibration_result = Some(match esc_calibration.start(armed, stick) {
+                    Ok(()) => {
+                        warn!("ESC calibration: max throttle, connect the battery");
+                        motor_test.stop();
+                        EscCalibrationResult::MaxThrottle
+                    }
+                    Err(e) => esc_calibration_error(e),
+                });
+            }
+            Some(EscCalibrationStep::BatteryConnected) => {
+                esc_calibration_result = Some(match esc_calibration.battery_connected() {
+                    Ok(()) => {
+                        info!("ESC calibration: min throttle");
+                        EscCalibrationResult::MinThrottle
+                    }
+                    Err(e) => esc_calibration_error(e),
+                });
+            }
+            Some(EscCalibrationStep::Abort) => {
+                esc_calibration.abort();
+                esc_calibration_result = Some(EscCalibrationResult::Aborted);
+            }
+            None => {}
+        }
+        let esc_calibration_phase = esc_calibration.phase();
+        let esc_calibration_output = match esc_calibration.update(armed, stick, LOOP_DT) {
+            Ok(output) => {
+                if esc_calibration_phase == EscCalibrationPhase::MinThrottle
+                    && !esc_calibration.active()
+                {
+                    info!("ESC calibration finished");
+                    esc_calibration_result = Some(EscCalibrationResult::Finished);
+                }
+                output
+            }
+            Err(e) => {
+                warn!("ESC calibration aborted: {}", defmt::Debug2Format(&e));
+                esc_calibration_result = Some(esc_calibration_error(e));
+                None
+            }
+        };
+        if let Some(result) = esc_calibration_result
+            && *USB_CONNECTED.lock().await
+        {
+            send_usb(&mut usb_sender, &Message::EscCalibrationStatus { result }).await;
+        }
+
+        let radio_lost = RADIO_RECEIVED
+            .lock()
+            .await
+            .is_some_and(|received| received.elapsed() > RADIO_TIMEOUT);
+        if (armed && radio_lost) != failsafe {
+            failsafe = armed && radio_lost;
+            if failsafe {
+                warn!("Radio lost, failsafe: {}", settings.failsafe_action);
+            } else {
+                info!("Failsafe ended");
+            }
+        }
+        if mode != flight_mode {
+            info!("Flight mode: {}", mode);
+            flight_mode = mode;
+            position.reset();
+            // Gives the control back to the pilot.
+            fence_response = None;
+        }
+        let navigation_estimate = navigation.estimate();
+        if navigation_estimate.is_some() != position_available {
+            position_available = navigation_estimate.is_some();
+            if position_available {
+                info!("Position estimate available");
+            } else {
+                warn!("Position estimate lost");
+            }
+        }
+        let breach = match (
+            &navigation_estimate,
+            navigation.origin(),
+            return_home.home(),
+        ) {
+            (Some(estimate), Some(frame), Some(home)) if armed => {
+                settings.geofence.check(frame, home, estimate.position)
+            }
+            _ => None,
+        };
+        if breach != fence_breach {
+            fence_breach = breach;
+            if breach.is_some() {
+                warn!(
+                    "Geofence breach: {}, action: {}",
+                    defmt::Debug2Format(&breach),
+                    settings.fence_action
+                );
+                if settings.fence_action != FenceAction::Warn {
+                    fence_response = Some(settings.fence_action);
+                    position.reset();
+                }
+            } else {
+                info!("Inside the geofence again");
+            }
+            if *USB_CONNECTED.lock().await {
+                let breach = breach.map(fence_breach_status);
+                send_usb(&mut usb_sender, &Message::FenceStatus { breach }).await;
+            }
+        }
+        if !armed {
+            fence_response = None;
+        }
+        // Return to home or landing, by the mode switch, as failsafe or as geofence action.
+        let requested = match (failsafe, fence_response, mode) {
+            (true, _, _) => Some(settings.failsafe_action),
+            (false, Some(FenceAction::Land), _) => Some(FailsafeAction::Land),
+            (false, Some(FenceAction::ReturnToHome), _) | (false, _, FlightMode::ReturnToHome) => {
+                Some(FailsafeAction::ReturnToHome)
+            }
+            _ => None,
+        };
+        let fence_hold = requested.is_none() && fence_response == Some(FenceAction::Hold);
+        // On the ground, the failsafe or geofence action would take off, e.g. to climb for the
+        // return to home.
+        if armed
+            && requested.is_some()
+            && (failsafe || fence_response.is_some())
+            && landing_detector.landed()
+        {
+            warn!("Failsafe on the ground, disarming");
+            armed = false;
+            rearm_blocked = true;
+        }
+        // The position control needs the heading. Without a magnetometer, the yaw is only
+        // relative to the start and the corrections would be turned by the unknown offset, so
+        // altitude hold or the emergency descent take over.
+        let heading = kf.heading();
+        if heading.is_some() != heading_available {
+            heading_available = heading.is_some();
+            if heading_available {
+                info!("Heading reference available");
+            } else {
+                warn!("No heading reference, position control disabled");
+            }
+        }
+        let guidance = navigation_estimate.zip(heading);
+        let guided_estimate = guidance.map(|(estimate, _)| estimate);
+        if requested != auto
+            && let Some(estimate) = &guided_estimate
+        {
+            match requested {
+                Some(FailsafeAction::ReturnToHome) => return_home.start(estimate),
+                Some(FailsafeAction::Land) => return_home.land(estimate),
+                None => {}
+            }
+            auto = requested;
+            position.reset();
+        }
+        // The mission runs until the mode is left, the failsafe action interrupts it.
+        if !armed || requested.is_some() || fence_hold || mode != FlightMode::Mission {
+            mission_active = false;
+        } else if !mission_active {
+            match (&guided_estimate, return_home.home()) {
+                (Some(estimate), Some(_)) if !mission.is_empty() => {
+                    info!("Starting mission ({} items)", mission.len());
+                    let forward = transition.state() != TransitionState::Hover;
+                    mission_executor.start(estimate, forward);
+                    mission_active = true;
+                    mission_index = 0;
+                    position.reset();
+                }
+                _ if iteration % TELEMETRY_DIVIDER == 0 => {
+                    warn!("Not starting mission, needs a mission, a home, a position and a heading")
+                }
+                _ => {}
+            }
+        }
+        let fixed_wing = transition.state() == TransitionState::FixedWing;
+        let mission_output = match (&guided_estimate, navigation.origin(), return_home.home()) {
+            (Some(estimate), Some(frame), Some(home)) if mission_active => {
+                Some(mission_executor.update(
+                    &mission,
+                    frame,
+                    home,
+                    transition.state(),
+                    estimate,
+                    LOOP_DT,
+                ))
+            }
+            _ => None,
+        };
+        if mission_active && mission_executor.index() != mission_index {
+            mission_index = mission_executor.index();
+            if mission_executor.finished(&mission) {
+                info!("Mission finished, holding position");
+            } else {
+                info!("Mission item {}", mission_index);
+            }
+        }
+        let estimate = altitude_estimator
+            .initialized()
+            .then(|| altitude_estimator.estimate());
+        let ground_height = estimate.as_ref().map(|estimate| estimate.height);
+        let vertical_speed = estimate.as_ref().map(|estimate| estimate.vertical_speed);
+
+        // The takeoff item of the mission starts with a thrust ramp if on the ground.
+        let takeoff_item = mission_output.is_some()
+            && matches!(
+                mission.get(mission_executor.index()),
+                Some(mission::MissionItem::Takeoff { .. })
+            );
+        if !takeoff_item {
+            takeoff.stop();
+        } else if !takeoff.active() && landing_detector.landed() {
+            info!("Taking off");
+            takeoff.start();
+        }
+        let takeoff_thrust = takeoff.update(
+            throttle.hover_thrust(),
+            vertical_speed.unwrap_or(0.0),
+            LOOP_DT,
+        );
+
+        let sticks = *STICKS.lock().await;
+        // The landing flag enables the automatic disarming after the touchdown. The bank angle
+        // is for the fixed-wing flight, by the roll stick unless navigating automatically.
+        let stick_bank = sticks[0] * fixed_wing_config.max_bank;
+        let (setpoint, climb_rate, auto_forward, landing, bank) = match (requested, &guidance) {
+            (Some(_), Some((estimate, yaw_angle))) => match return_home.update(estimate) {
+                Some(output) => (
+                    position.fly_to(output.target, output.speed, *yaw_angle, estimate, LOOP_DT),
+                    output.climb_rate,
+                    output.forward,
+                    return_home.phase() == ReturnPhase::Land,
+                    mission_executor.bank_to(output.target, estimate),
+                ),
+                None => (
+                    [0.0; 2],
+                    return_config.descent.climb_rate(ground_height),
+                    false,
+                    true,
+                    0.0,
+                ),
+            },
+            // Without a position estimate or a heading, the only option is an emergency descent,
+            // level.
+            (Some(_), None) => (
+                [0.0; 2],
+                return_config.descent.climb_rate(ground_height),
+                false,
+                true,
+                0.0,
+            ),
+            (None, Some((estimate, yaw_angle))) => match mission_output {
+                _ if takeoff_thrust.is_some() => {
+                    position.reset();
+                    ([0.0; 2], 0.0, false, false, 0.0)
+                }
+                // The path following only steers by the bank angle.
+                Some(output) if fixed_wing => {
+                    position.reset();
+                    (
+                        [0.0; 2],
+                        output.climb_rate,
+                        output.forward,
+                        false,
+                        output.bank,
+                    )
+                }
+                Some(output) => (
+                    position.fly_to(output.target, output.speed, *yaw_angle, estimate, LOOP_DT),
+                    output.climb_rate,
+                    output.forward,
+                    output.land,
+                    output.bank,
+                ),
+                None if fence_hold => (
+                    position.update([0.0; 2], *yaw_angle, estimate, LOOP_DT),
+                    0.0,
+                    false,
+                    false,
+                    stick_bank,
+                ),
+                // Position hold, also if the mission can not be started.
+                None if matches!(mode, FlightMode::PositionHold | FlightMode::Mission) => (
+                    position.update(sticks, *yaw_angle, estimate, LOOP_DT),
+                    0.0,
+                    false,
+                    false,
+                    stick_bank,
+                ),
+                None => ([0.0; 2], 0.0, false, false, stick_bank),
+            },
+            (None, None) => ([0.0; 2], 0.0, false, false, stick_bank),
+        };
+
+        let forward_switch = *FORWARD_SWITCH.lock().await || auto_forward;
+        let (transition_state, transition_aborted) = (transition.state(), transition.aborted());
+        if !armed {
+            transition.reset();
+        }
+        // No airspeed sensor, the groundspeed stands in for it.
+        let groundspeed = navigation_estimate
+            .as_ref()
+            .map(|estimate| libm::hypotf(estimate.velocity[0], estimate.velocity[1]));
+        let airspeed = groundspeed.unwrap_or(0.0);
+        let transition_output = transition.update(forward_switch, groundspeed, LOOP_DT);
+        if transition.state() != transition_state || transition.aborted() != transition_aborted {
+            info!(
+                "Transition: {} (aborted: {})",
+                defmt::Debug2Format(&transition.state()),
+                transition.aborted()
+            );
+        }
+
+        let (vertical_mode, throttle_stick) = match (takeoff_thrust, requested) {
+            (Some(thrust), _) => (ThrottleMode::Manual, thrust),
+            (None, Some(_)) => (ThrottleMode::Auto, stick),
+            (None, None) if mission_output.is_some() || fence_hold => (ThrottleMode::Auto, stick),
+            (None, None) => (throttle_mode(mode), stick),
+        };
+        throttle.set_climb_rate(climb_rate);
+        let collective = throttle.update(vertical_mode, throttle_stick, estimate.as_ref(), LOOP_DT);
+        let landed = landing_detector.update(
+            collective,
+            throttle.hover_thrust(),
+            vertical_speed,
+            gyro,
+            LOOP_DT,
+        );
+        if armed && landing && landed {
+            info!("Landed, disarming");
+            armed = false;
+            rearm_blocked = true;
+        }
+        // The downwash near the ground disturbs the barometer, from the next reading on.
+        altitude_estimator.set_ground_effect(armed && (takeoff.active() || landing || landed));
+        // The yaw stick is split between motor torque and differential tilt.
+        let yaw = yaw_mixer.update(*YAW.lock().await, transition_output.tilt);
+        // Shift all motors by the same amount, so that per-motor thrust is passed through
+        // unchanged in manual mode.
+        let base_thrust = thrust_input.map(|t| t + collective - stick);
+        let (rates, thrust) = hover.update(&attitude, setpoint, base_thrust);
+        let mut motor_thrust = thrust;
+        for (thrust, yaw) in motor_thrust.iter_mut().zip(yaw.motors) {
+            *thrust += yaw;
+        }
+        let fixed_wing_commands = if transition.state() != TransitionState::Hover {
+            let (height, vertical_speed) = estimate.as_ref().map_or((0.0, 0.0), |estimate| {
+                (estimate.height, estimate.vertical_speed)
+            });
+            let target_height = fixed_wing_height.get_or_insert(height);
+            *target_height += climb_rate * LOOP_DT;
+            // The attitude estimate is north-west-up, the fixed-wing control pitches the nose up
+            // and yaws right for positive values.
+            let [roll_rate, pitch_rate, yaw_rate] = attitude.rates.map(f32::to_radians);
+            let state = FixedWingState {
+                roll: attitude.euler[0],
+                pitch: -attitude.euler[1],
+                rates: [roll_rate, -pitch_rate, -yaw_rate],
+                airspeed,
+                height,
+                vertical_speed,
+            };
+            let output =
+                fixed_wing_control.update(bank, CRUISE_AIRSPEED, *target_height, &state, LOOP_DT);
+            Some(ActuatorCommands {
+                motors: [output.throttle; 4],
+                aileron: output.aileron,
+                elevator: output.elevator,
+                rudder: output.rudder,
+            })
+        } else {
+            fixed_wing_control.reset();
+            fixed_wing_height = None;
+            None
+        };
+        // The transition blends the motor mixing with the fixed-wing control, the torques are then
+        // shared between the motors and the control surfaces, depending on the tilt and the airspeed.
+        let motor_commands = ActuatorCommands {
+            motors: motor_thrust,
+            ..Default::default()
+        };
+        let hover_demand = allocator.demand(&motor_commands, transition_output.tilt, airspeed);
+        let demand = match fixed_wing_commands {
+            Some(commands) => blend_demand(
+                &transition_output,
+                &hover_demand,
+                &allocator.demand(&commands, transition_output.tilt, airspeed),
+            ),
+            None => hover_demand,
+        };
+        let actuators = allocator.update(&demand, transition_output.tilt, airspeed, LOOP_DT);
+        let motor_rpm = esc_driver
+            .erpm()
+            .map(|erpm| erpm.map(|erpm| dshot::rpm(erpm, settings.motor_poles)));
         /*info!(
             "thrust_input={}, thrust={}, rates={}",
             thrust_input, thrust, rates
         );*/
 
-        {
+        iteration = iteration.wrapping_add(1);
+        if iteration % TELEMETRY_DIVIDER == 0 {
             let usb_connected = USB_CONNECTED.lock().await;
             if *usb_connected {
+                if !boot_reported {
+                    send_usb(
+                        &mut usb_sender,
+                        &Message::Boot {
+                            reset_reason: board.reset_reason,
+                        },
+                    )
+                    .await;
+                    boot_reported = true;
+                }
                 send_usb(
                     &mut usb_sender,
                     &Message::ImuData {
@@ -95,11 +1172,286 @@ async fn main(spawner: Spawner) {
                     },
                 )
                 .await;
+                if let Some((measurement, altitude)) = baro_data {
+                    send_usb(
+                        &mut usb_sender,
+                        &Message::BaroData {
+                            pressure: measurement.pressure,
+                            temperature: measurement.temperature,
+                            altitude,
+                        },
+                    )
+                    .await;
+                }
+                if motor_rpm.iter().any(Option::is_some) {
+                    send_usb(&mut usb_sender, &Message::EscTelemetry { rpm: motor_rpm }).await;
+                }
+                if let Some(estimate) = estimate {
+                    send_usb(
+                        &mut usb_sender,
+                        &Message::AltitudeData {
+                            height: estimate.height,
+                            vertical_speed: estimate.vertical_speed,
+                            height_std: estimate.altitude_std,
+                            vertical_speed_std: estimate.vertical_speed_std,
+                        },
+                    )
+                    .await;
+                }
+                if let Some(solution) = gps_solution {
+                    send_usb(
+                        &mut usb_sender,
+                        &Message::GpsData {
+                            fix: gps_fix(solution.fix),
+                            latitude: solution.latitude,
+                            longitude: solution.longitude,
+                            altitude: solution.altitude,
+                            velocity: solution.velocity,
+                            vertical_velocity: solution.vertical_velocity,
+                            horizontal_accuracy: solution.horizontal_accuracy,
+                            vertical_accuracy: solution.vertical_accuracy,
+                            speed_accuracy: solution.speed_accuracy,
+                            satellites: solution.satellites,
+                        },
+                    )
+                    .await;
+                }
+                if let Some(estimate) = navigation.estimate() {
+                    send_usb(
+                        &mut usb_sender,
+                        &Message::NavigationData {
+                            position: estimate.position,
+                            velocity: estimate.velocity,
+                            position_std: estimate.position_std,
+                            velocity_std: estimate.velocity_std,
+                        },
+                    )
+                    .await;
+                }
             }
         }
 
-        board.esc_driver.update(thrust_input);
-        Timer::after_millis(1000).await;
+        // The motor test and the ESC calibration bypass the output conditioning, as they only
+        // run while disarmed.
+        let motor_output = match (esc_calibration_output, motor_test_output) {
+            (Some(output), _) => [output; 4],
+            (None, Some(thrust)) => thrust,
+            (None, None) => output.update(armed, actuators.motors, LOOP_DT),
+        };
+        esc_driver.update(motor_output).await;
+        let controls = FlightControls {
+            tilt_left: transition_output.tilt + yaw.tilt_left,
+            tilt_right: transition_output.tilt + yaw.tilt_right,
+            elevator: actuators.elevator,
+            rudder: actuators.rudder,
+            aileron: actuators.aileron,
+        };
+        let mut servo_positions = [None; SERVO_COUNT];
+        for ((position, servo), function) in servo_positions
+            .iter_mut()
+            .zip(servos.iter_mut())
+            .zip(settings.servo_functions)
+        {
+            *position = function
+                .map(|function| servo.update(controls.command(servo_function(function)), LOOP_DT));
+        }
+        servo_driver.update(&servo_positions);
+        watchdog::feed_outputs();
+        ticker.next().await;
+    }
+}
+
+/// Blends the multicopter and the fixed-wing demand during the transitions.
+fn blend_demand(
+    transition: &TransitionOutput,
+    multicopter: &Demand,
+    fixed_wing: &Demand,
+) -> Demand {
+    let [roll, pitch, yaw] = multicopter.torque;
+    let [fixed_wing_roll, fixed_wing_pitch, fixed_wing_yaw] = fixed_wing.torque;
+    Demand {
+        torque: [
+            transition.blend(roll, fixed_wing_roll),
+            transition.blend(pitch, fixed_wing_pitch),
+            transition.blend(yaw, fixed_wing_yaw),
+        ],
+        forward: transition.blend(multicopter.forward, fixed_wing.forward),
+        up: transition.blend(multicopter.up, fixed_wing.up),
+    }
+}
+
+fn throttle_mode(mode: FlightMode) -> ThrottleMode {
+    match mode {
+        FlightMode::Manual => ThrottleMode::Manual,
+        // Without a mission running, the position is held.
+        FlightMode::AltitudeHold | FlightMode::PositionHold | FlightMode::Mission => {
+            ThrottleMode::AltitudeHold
+        }
+        FlightMode::ReturnToHome => ThrottleMode::Auto,
+    }
+}
+
+fn mission_item(item: MissionItem) -> mission::MissionItem {
+    match item {
+        MissionItem::Takeoff { altitude } => mission::MissionItem::Takeoff { altitude },
+        MissionItem::Waypoint {
+            latitude,
+            longitude,
+            altitude,
+        } => mission::MissionItem::Waypoint {
+            latitude,
+            longitude,
+            altitude,
+        },
+        MissionItem::Loiter {
+            latitude,
+            longitude,
+            altitude,
+            radius,
+            time,
+        } => mission::MissionItem::Loiter {
+            latitude,
+            longitude,
+            altitude,
+            radius,
+            time,
+        },
+        MissionItem::Transition { forward } => mission::MissionItem::Transition { forward },
+        MissionItem::Land { position } => mission::MissionItem::Land { position },
+    }
+}
+
+fn mission_error(error: MissionError) -> MissionResult {
+    match error {
+        MissionError::OutOfSequence => MissionResult::OutOfSequence,
+        MissionError::TooManyItems => MissionResult::TooManyItems,
+    }
+}
+
+/// Pre-arm check, the vehicle is armed at the home position.
+fn inside_fence(fence: &Geofence, navigation: &NavigationFilter) -> bool {
+    if !fence.enabled() {
+        return true;
+    }
+    match (navigation.estimate(), navigation.origin()) {
+        (Some(estimate), Some(frame)) => fence
+            .check(frame, estimate.position, estimate.position)
+            .is_none(),
+        _ => false,
+    }
+}
+
+fn store_fence(
+    storage: &mut Storage,
+    settings: &mut Settings,
+    fence: Geofence,
+    action: FenceAction,
+) -> FenceResult {
+    info!(
+        "Storing geofence ({} vertices), action: {}",
+        fence.vertices(),
+        action
+    );
+    settings.geofence = fence;
+    settings.fence_action = action;
+    match storage.store(settings) {
+        Ok(()) => FenceResult::Stored,
+        Err(e) => {
+            error!("Failed to store settings: {}", e);
+            FenceResult::StorageError
+        }
+    }
+}
+
+fn fence_breach_status(breach: Breach) -> FenceBreach {
+    match breach {
+        Breach::Altitude => FenceBreach::Altitude,
+        Breach::Distance => FenceBreach::Distance,
+        Breach::Polygon => FenceBreach::Polygon,
+    }
+}
+
+fn fence_error(error: FenceError) -> FenceResult {
+    match error {
+        FenceError::OutOfSequence => FenceResult::OutOfSequence,
+        FenceError::TooManyVertices => FenceResult::TooManyVertices,
+    }
+}
+
+fn attitude_backend(estimator: AttitudeEstimator) -> AttitudeBackend {
+    match estimator {
+        AttitudeEstimator::Fusion => AttitudeBackend::Fusion,
+        AttitudeEstimator::Mahony => AttitudeBackend::Mahony,
+        AttitudeEstimator::Madgwick => AttitudeBackend::Madgwick,
+        AttitudeEstimator::Eskf => AttitudeBackend::Eskf,
+    }
+}
+
+fn gps_fix(fix: FixType) -> GpsFix {
+    match fix {
+        FixType::NoFix => GpsFix::NoFix,
+        FixType::DeadReckoning => GpsFix::DeadReckoning,
+        FixType::Fix2d => GpsFix::Fix2d,
+        FixType::Fix3d => GpsFix::Fix3d,
+        FixType::TimeOnly => GpsFix::TimeOnly,
+    }
+}
+
+/// Only 3D fixes are used for navigation.
+fn gps_measurement(solution: &Solution) -> Option<GpsMeasurement> {
+    (solution.fix == FixType::Fix3d).then_some(GpsMeasurement {
+        latitude: solution.latitude,
+        longitude: solution.longitude,
+        altitude: solution.altitude,
+        velocity: solution.velocity,
+        vertical_velocity: solution.vertical_velocity,
+        horizontal_accuracy: solution.horizontal_accuracy,
+        vertical_accuracy: solution.vertical_accuracy,
+        speed_accuracy: solution.speed_accuracy,
+    })
+}
+
+fn motor_test_error(error: MotorTestError) -> MotorTestResult {
+    match error {
+        MotorTestError::Armed => MotorTestResult::Armed,
+        MotorTestError::ThrottleNotLow => MotorTestResult::ThrottleNotLow,
+        MotorTestError::NotActive => MotorTestResult::NotActive,
+        MotorTestError::Timeout => MotorTestResult::Timeout,
+    }
+}
+
+fn servo_function(function: ServoFunction) -> servo::ServoFunction {
+    match function {
+        ServoFunction::TiltLeft => servo::ServoFunction::TiltLeft,
+        ServoFunction::TiltRight => servo::ServoFunction::TiltRight,
+        ServoFunction::Elevator => servo::ServoFunction::Elevator,
+        ServoFunction::Rudder => servo::ServoFunction::Rudder,
+        ServoFunction::AileronLeft => servo::ServoFunction::AileronLeft,
+        ServoFunction::AileronRight => servo::ServoFunction::AileronRight,
+        ServoFunction::ElevonLeft => servo::ServoFunction::ElevonLeft,
+        ServoFunction::ElevonRight => servo::ServoFunction::ElevonRight,
+    }
+}
+
+fn esc_calibration_error(error: EscCalibrationError) -> EscCalibrationResult {
+    match error {
+        EscCalibrationError::Armed => EscCalibrationResult::Armed,
+        EscCalibrationError::ThrottleNotLow => EscCalibrationResult::ThrottleNotLow,
+        EscCalibrationError::NotActive => EscCalibrationResult::NotActive,
+        EscCalibrationError::Timeout => EscCalibrationResult::Timeout,
+    }
+}
+
+#[embassy_executor::task]
+async fn poll_gps(mut gps: Gps) {
+    let Some(protocol) = gps.configure().await else {
+        warn!("No GPS receiver found");
+        return;
+    };
+    info!("GPS receiver found ({})", protocol);
+    loop {
+        let solution = gps.next().await;
+        *GPS_SOLUTION.lock().await = Some(solution);
     }
 }
 
@@ -111,6 +1463,9 @@ async fn poll_radio(mut radio: Radio) {
         pitch: 0.0,
         yaw: 0.0,
         thrust: 0.0,
+        mode: FlightMode::Manual,
+        armed: false,
+        forward: false,
     };
     loop {
         let cmd = match radio.next().await {
@@ -121,19 +1476,46 @@ async fn poll_radio(mut radio: Radio) {
                 continue;
             }
         };
+        {
+            let mut radio_received = RADIO_RECEIVED.lock().await;
+            *radio_received = Some(Instant::now());
+        }
         if cmd == last_cmd {
             continue;
         }
         info!("Got command: {}", cmd);
         match cmd {
             Message::Command {
-                roll: _,
-                pitch: _,
-                yaw: _,
+                roll,
+                pitch,
+                yaw,
                 thrust,
+                mode,
+                armed,
+                forward,
             } => {
-                let mut thrust_cmd = THRUST.lock().await;
-                *thrust_cmd = [thrust; 4];
+                {
+                    let mut thrust_cmd = THRUST.lock().await;
+                    *thrust_cmd = [thrust; 4];
+                }
+                {
+                    let mut yaw_cmd = YAW.lock().await;
+                    *yaw_cmd = yaw;
+                }
+                {
+                    let mut sticks = STICKS.lock().await;
+                    *sticks = [roll, pitch];
+                }
+                {
+                    let mut flight_mode = FLIGHT_MODE.lock().await;
+                    *flight_mode = mode;
+                }
+                {
+                    let mut arm_switch = ARM_SWITCH.lock().await;
+                    *arm_switch = armed;
+                }
+                let mut forward_switch = FORWARD_SWITCH.lock().await;
+                *forward_switch = forward;
             }
             _ => {}
         }
@@ -165,8 +1547,91 @@ async fn poll_usb(mut usb_class: UsbReceiver) {
                 info!("Got command: {}", cmd);
                 match cmd {
                     Message::MotorDebug { thrust } => {
-                        let mut thrust_cmd = THRUST.lock().await;
-                        *thrust_cmd = thrust;
+                        let mut motor_test_thrust = MOTOR_TEST_THRUST.lock().await;
+                        *motor_test_thrust = Some(thrust);
+                    }
+                    Message::MotorTest { command } => {
+                        let mut motor_test_request = MOTOR_TEST_REQUEST.lock().await;
+                        *motor_test_request = Some(command);
+                    }
+                    Message::Calibrate { step } => {
+                        let mut calibration_request = CALIBRATION_REQUEST.lock().await;
+                        *calibration_request = Some(step);
+                    }
+                    Message::SetDeclination { declination } => {
+                        let mut declination_request = DECLINATION_REQUEST.lock().await;
+                        *declination_request = Some(declination);
+                    }
+                    Message::SetEscProtocol { protocol, rate } => {
+                        let mut esc_protocol_request = ESC_PROTOCOL_REQUEST.lock().await;
+                        *esc_protocol_request = Some((protocol, rate));
+                    }
+                    Message::SetOutputTimeout { timeout } => {
+                        let mut output_timeout_request = OUTPUT_TIMEOUT_REQUEST.lock().await;
+                        *output_timeout_request = Some(timeout);
+                    }
+                    Message::SetMotorPoles { poles } => {
+                        let mut motor_poles_request = MOTOR_POLES_REQUEST.lock().await;
+                        *motor_poles_request = Some(poles);
+                    }
+                    Message::EscCalibration { step } => {
+                        let mut esc_calibration_request = ESC_CALIBRATION_REQUEST.lock().await;
+                        *esc_calibration_request = Some(step);
+                    }
+                    Message::SetServo { output, setting } => {
+                        let mut servo_request = SERVO_REQUEST.lock().await;
+                        *servo_request = Some((output, setting));
+                    }
+                    Message::SetOutput { setting } => {
+                        let mut output_request = OUTPUT_REQUEST.lock().await;
+                        *output_request = Some(setting);
+                    }
+                    Message::SetAttitudeEstimator { estimator } => {
+                        let mut attitude_estimator_request =
+                            ATTITUDE_ESTIMATOR_REQUEST.lock().await;
+                        *attitude_estimator_request = Some(estimator);
+                    }
+                    Message::SetFailsafeAction { action } => {
+                        let mut failsafe_action_request = FAILSAFE_ACTION_REQUEST.lock().await;
+                        *failsafe_action_request = Some(action);
+                    }
+                    Message::MissionItem { index, count, item } => {
+                        let mut mission_request = MISSION_REQUEST.lock().await;
+                        *mission_request = Some(MissionRequest::Item { index, count, item });
+                    }
+                    Message::ClearMission => {
+                        let mut mission_request = MISSION_REQUEST.lock().await;
+                        *mission_request = Some(MissionRequest::Clear);
+                    }
+                    Message::SetFence {
+                        max_altitude,
+                        max_distance,
+                        vertices,
+                        action,
+                    } => {
+                        let mut fence_request = FENCE_REQUEST.lock().await;
+                        *fence_request = Some(FenceRequest::Limits {
+                            max_altitude,
+                            max_distance,
+                            vertices,
+                            action,
+                        });
+                    }
+                    Message::FenceVertex {
+                        index,
+                        latitude,
+                        longitude,
+                    } => {
+                        let mut fence_request = FENCE_REQUEST.lock().await;
+                        *fence_request = Some(FenceRequest::Vertex {
+                            index,
+                            latitude,
+                            longitude,
+                        });
+                    }
+                    Message::EscCommand { motor, command } => {
+                        let mut esc_command_request = ESC_COMMAND_REQUEST.lock().await;
+                        *esc_command_request = Some((motor, command));
                     }
                     _ => {}
                 }
@@ -1,3 +1,4 @@
+use protocol::FlightMode;
 use protocol::Message;
 use sbus_rs::channels_parsing;
 
@@ -6,6 +7,8 @@ use crate::board::RadioUart;
 const SCALE_MIN: u16 = 240;
 const SCALE_MID: u16 = 1024;
 const SCALE_MAX: u16 = 1800;
+/// Flags byte: set by the receiver while the transmitter link is lost.
+const FLAG_FAILSAFE: u8 = 0x08;
 
 pub struct Radio {
     uart: RadioUart,
@@ -32,6 +35,10 @@ impl Radio {
             if buf[24] != 0x00 {
                 continue;
             }
+            // Frames with failsafe values are dropped, so the link loss is detected by timeout.
+            if buf[23] & FLAG_FAILSAFE != 0 {
+                continue;
+            }
 
             let channels = channels_parsing(&buf);
             for channel in channels.iter() {
@@ -45,6 +52,16 @@ impl Radio {
                 pitch: scale_principal_axis(channels[1]),
                 yaw: scale_principal_axis(channels[3]),
                 thrust: scale_thrust(channels[2]),
+                // Return to home takes precedence over the mission (channel 9).
+                mode: if scale_switch(channels[7]) {
+                    FlightMode::ReturnToHome
+                } else if scale_switch(channels[8]) {
+                    FlightMode::Mission
+                } else {
+                    scale_mode(channels[4])
+                },
+                armed: scale_switch(channels[5]),
+                forward: scale_switch(channels[6]),
             });
         }
     }
@@ -61,6 +78,22 @@ fn scale_principal_axis(input: u16) -> f32 {
     }
 }
 
+fn scale_mode(input: u16) -> FlightMode {
+    // Three-position switch on channel 5, return to home is on a separate switch (channel 8).
+    const LOW: u16 = SCALE_MIN + (SCALE_MAX - SCALE_MIN) / 3;
+    const HIGH: u16 = SCALE_MAX - (SCALE_MAX - SCALE_MIN) / 3;
+    match input {
+        u16::MIN..LOW => FlightMode::Manual,
+        LOW..HIGH => FlightMode::AltitudeHold,
+        HIGH..=u16::MAX => FlightMode::PositionHold,
+    }
+}
+
+fn scale_switch(input: u16) -> bool {
+    // Two-position switch, e.g. arming on channel 6 or forward flight on channel 7.
+    input >= SCALE_MID
+}
+
 fn scale_thrust(input: u16) -> f32 {
     // Set 0.0 and 1.0 explicitely to avoid rounding error.
     match input {
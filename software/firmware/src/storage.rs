@@ -0,0 +1,162 @@
+use defmt::warn;
+use embassy_stm32::flash::WRITE_SIZE;
+use protocol::AttitudeEstimator;
+use protocol::EscProtocol;
+use protocol::FailsafeAction;
+use protocol::FenceAction;
+use protocol::ServoFunction;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use stabilization::calibration::ImuCalibration;
+use stabilization::geofence::Geofence;
+use stabilization::magnetometer::MagCalibration;
+use stabilization::mission::Mission;
+use stabilization::output::OutputConfig;
+use stabilization::servo::ServoConfig;
+
+use crate::board::SERVO_COUNT;
+pub use crate::board::SettingsFlash;
+use crate::watchdog;
+
+/// Offset of the last 128 KiB flash sector (sector 11), reserved for the settings.
+const SETTINGS_OFFSET: u32 = 0xE_0000;
+const SETTINGS_SIZE: u32 = 128 * 1024;
+/// Needs to be incremented whenever the layout of `Settings` changes.
+const SETTINGS_VERSION: u32 = 11;
+/// The sector before (sector 10) is reserved for the mission.
+const MISSION_OFFSET: u32 = 0xC_0000;
+const MISSION_SIZE: u32 = 128 * 1024;
+/// Needs to be incremented whenever the layout of `Mission` changes.
+const MISSION_VERSION: u32 = 1;
+const MAGIC: u32 = 0x5541_5600; // "UAV\0"
+const MISSION_MAGIC: u32 = 0x4d49_5300; // "MIS\0"
+const HEADER_LEN: usize = 8;
+const MAX_LEN: usize = 1024;
+
+/// Persistent vehicle settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub imu_calibration: ImuCalibration,
+    pub mag_calibration: MagCalibration,
+    pub declination: f32, // [deg]
+    pub esc_protocol: EscProtocol,
+    pub esc_rate: Option<u16>, // [Hz], `None` for the protocol default
+    pub motor_poles: u8,
+    pub output_timeout: u16, // [ms]
+    pub output: OutputConfig,
+    /// `None` for disabled servo outputs.
+    pub servo_functions: [Option<ServoFunction>; SERVO_COUNT],
+    pub servos: [ServoConfig; SERVO_COUNT],
+    pub attitude_estimator: AttitudeEstimator,
+    pub failsafe_action: FailsafeAction,
+    pub geofence: Geofence,
+    pub fence_action: FenceAction,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            imu_calibration: Default::default(),
+            mag_calibration: Default::default(),
+            declination: 0.0,
+            esc_protocol: Default::default(),
+            esc_rate: None,
+            motor_poles: 14,
+            output_timeout: watchdog::DEFAULT_OUTPUT_TIMEOUT_MS,
+            output: Default::default(),
+            servo_functions: [None; SERVO_COUNT],
+            servos: [Default::default(); SERVO_COUNT],
+            attitude_estimator: Default::default(),
+            failsafe_action: Default::default(),
+            geofence: Default::default(),
+            fence_action: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, defmt::Format)]
+pub enum Error {
+    Flash(embassy_stm32::flash::Error),
+    Encode,
+}
+
+pub struct Storage {
+    flash: SettingsFlash,
+}
+
+impl Storage {
+    pub fn new(flash: SettingsFlash) -> Self {
+        Self { flash }
+    }
+
+    /// Loads the settings, falls back to defaults if none (or outdated ones) are stored.
+    pub fn load(&mut self) -> Settings {
+        self.read(SETTINGS_OFFSET, MAGIC | SETTINGS_VERSION)
+            .unwrap_or_else(|| {
+                warn!("No valid settings stored, using defaults");
+                Settings::default()
+            })
+    }
+
+    pub fn store(&mut self, settings: &Settings) -> Result<(), Error> {
+        self.write(
+            SETTINGS_OFFSET,
+            SETTINGS_SIZE,
+            MAGIC | SETTINGS_VERSION,
+            settings,
+        )
+    }
+
+    /// Loads the mission, an empty one if none (or an outdated one) is stored.
+    pub fn load_mission(&mut self) -> Mission {
+        self.read(MISSION_OFFSET, MISSION_MAGIC | MISSION_VERSION)
+            .unwrap_or_default()
+    }
+
+    pub fn store_mission(&mut self, mission: &Mission) -> Result<(), Error> {
+        self.write(
+            MISSION_OFFSET,
+            MISSION_SIZE,
+            MISSION_MAGIC | MISSION_VERSION,
+            mission,
+        )
+    }
+
+    fn read<T: DeserializeOwned>(&mut self, offset: u32, magic: u32) -> Option<T> {
+        let mut buf = [0u8; HEADER_LEN + MAX_LEN];
+        if let Err(e) = self.flash.blocking_read(offset, &mut buf) {
+            warn!("Failed to read flash: {}", e);
+            return None;
+        }
+        let stored_magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        let len = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+        if stored_magic != magic || len > MAX_LEN {
+            return None;
+        }
+        postcard::from_bytes(&buf[HEADER_LEN..HEADER_LEN + len]).ok()
+    }
+
+    /// Erases the whole sector at `offset` and writes `value` with a header.
+    fn write<T: Serialize>(
+        &mut self,
+        offset: u32,
+        size: u32,
+        magic: u32,
+        value: &T,
+    ) -> Result<(), Error> {
+        let mut buf = [0xffu8; HEADER_LEN + MAX_LEN];
+        let len = postcard::to_slice(value, &mut buf[HEADER_LEN..])
+            .map_err(|_| Error::Encode)?
+            .len();
+        buf[0..4].copy_from_slice(&magic.to_le_bytes());
+        buf[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        let padded_len = (HEADER_LEN + len).next_multiple_of(WRITE_SIZE);
+
+        self.flash
+            .blocking_erase(offset, offset + size)
+            .map_err(Error::Flash)?;
+        self.flash
+            .blocking_write(offset, &buf[..padded_len])
+            .map_err(Error::Flash)
+    }
+}
@@ -0,0 +1,97 @@
+//! Supervision of the motor outputs and of the executor.
+//!
+//! The output watchdog runs on a high priority interrupt executor, so that it keeps running if
+//! the control loop hangs, and stops the motors if the outputs are not updated in time. The
+//! hardware watchdog is fed from the thread mode executor and resets the MCU if it stalls.
+//!
+//! A panic or a HardFault stops both executors, so their handlers stop the motors before halting
+//! and leave the reset to the hardware watchdog.
+
+use core::panic::PanicInfo;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+
+use cortex_m_rt::ExceptionFrame;
+use cortex_m_rt::exception;
+use defmt::Display2Format;
+use defmt::error;
+use defmt::info;
+use embassy_time::Duration;
+use embassy_time::Instant;
+use embassy_time::Ticker;
+use embassy_time::Timer;
+
+use crate::board;
+use crate::board::HardwareWatchdog;
+
+pub const DEFAULT_OUTPUT_TIMEOUT_MS: u16 = 100;
+/// The hardware watchdog resets the MCU if it is not fed for this long. Needs to cover the
+/// erase of the 128 KiB settings sector (up to 2 s), which stalls the CPU.
+pub const HARDWARE_TIMEOUT_US: u32 = 3_000_000;
+const CHECK_PERIOD_MS: u64 = 5;
+const FEED_PERIOD_MS: u64 = 100;
+
+static LAST_OUTPUT: AtomicU32 = AtomicU32::new(0); // [ms since boot]
+static OUTPUT_TIMEOUT: AtomicU32 = AtomicU32::new(DEFAULT_OUTPUT_TIMEOUT_MS as u32); // [ms]
+static OUTPUTS_STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// Needs to be called after every output update.
+pub fn feed_outputs() {
+    LAST_OUTPUT.store(now_ms(), Ordering::Relaxed);
+}
+
+pub fn set_output_timeout(timeout: u16) {
+    OUTPUT_TIMEOUT.store(timeout as u32, Ordering::Relaxed);
+}
+
+fn now_ms() -> u32 {
+    Instant::now().as_millis() as u32
+}
+
+#[embassy_executor::task]
+pub async fn supervise_outputs() {
+    feed_outputs();
+    let mut ticker = Ticker::every(Duration::from_millis(CHECK_PERIOD_MS));
+    loop {
+        let age = now_ms().wrapping_sub(LAST_OUTPUT.load(Ordering::Relaxed));
+        let expired = age > OUTPUT_TIMEOUT.load(Ordering::Relaxed);
+        let stopped = OUTPUTS_STOPPED.swap(expired, Ordering::Relaxed);
+        if expired {
+            // Repeated, as a late update from the control loop might overwrite it.
+            board::stop_esc_outputs();
+            if !stopped {
+                error!("Motor outputs not updated for {} ms, stopping motors", age);
+            }
+        } else if stopped {
+            info!("Motor outputs updated again");
+        }
+        ticker.next().await;
+    }
+}
+
+#[embassy_executor::task]
+pub async fn feed_hardware(mut watchdog: HardwareWatchdog) {
+    watchdog.unleash();
+    loop {
+        watchdog.pet();
+        Timer::after_millis(FEED_PERIOD_MS).await;
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    cortex_m::interrupt::disable();
+    board::stop_esc_outputs();
+    error!("{}", Display2Format(info));
+    // Raises a HardFault, which also ends a debugging session.
+    cortex_m::asm::udf()
+}
+
+#[exception]
+unsafe fn HardFault(_frame: &ExceptionFrame) -> ! {
+    board::stop_esc_outputs();
+    loop {
+        cortex_m::asm::nop();
+    }
+}
@@ -17,10 +17,21 @@ pub enum Message {
         pitch: f32,  // [-1.0 .. 1.0]
         yaw: f32,    // [-1.0 .. 1.0]
         thrust: f32, // [ 0.0 .. 1.0]
+        mode: FlightMode,
+        armed: bool,
+        /// Requests the transition to forward flight.
+        forward: bool,
     },
+    /// Motor thrust in the motor test mode, also a keep-alive.
     MotorDebug {
         thrust: [f32; 4], // [0.0 .. 1.0]
     },
+    MotorTest {
+        command: MotorTestCommand,
+    },
+    MotorTestStatus {
+        result: MotorTestResult,
+    },
     ImuData {
         gyro: [f32; 3],
         accel: [f32; 3],
@@ -28,6 +39,423 @@ pub enum Message {
         thrust_input: [f32; 4], // [0.0 .. 1.0]
         thrust: [f32; 4],       // [0.0 .. 1.0]
     },
+    /// Runs a calibration step, only while disarmed.
+    Calibrate {
+        step: CalibrationStep,
+    },
+    CalibrationStatus {
+        step: CalibrationStep,
+        result: CalibrationResult,
+    },
+    /// Applied right away, stored persistently once disarmed.
+    SetDeclination {
+        declination: f32, // [deg], positive east
+    },
+    BaroData {
+        pressure: f32,    // [Pa]
+        temperature: f32, // [degC]
+        altitude: f32,    // [m], pressure altitude
+    },
+    AltitudeData {
+        height: f32,             // [m], above the ground reference
+        vertical_speed: f32,     // [m/s], positive up
+        height_std: f32,         // [m]
+        vertical_speed_std: f32, // [m/s]
+    },
+    /// Selects the ESC protocol, only while disarmed. Takes effect after a reboot.
+    SetEscProtocol {
+        protocol: EscProtocol,
+        /// Output rate of the analog protocols [Hz], `None` for the protocol default.
+        rate: Option<u16>,
+    },
+    /// Number of magnet poles of the motors, to convert eRPM to RPM. Only while disarmed.
+    SetMotorPoles {
+        poles: u8,
+    },
+    EscTelemetry {
+        rpm: [Option<u32>; 4], // [1/min], `None` if no valid response was received
+    },
+    /// Motors are stopped if the outputs are not updated within this time. Only while disarmed.
+    SetOutputTimeout {
+        timeout: u16, // [ms]
+    },
+    /// Sent once after the USB connection is established.
+    Boot {
+        reset_reason: ResetReason,
+    },
+    /// Sends a special command to one motor (index 0..=3) or to all motors, only while disarmed.
+    EscCommand {
+        motor: Option<u8>,
+        command: EscCommand,
+    },
+    /// Calibrates the throttle endpoints of analog ESCs, only while disarmed.
+    EscCalibration {
+        step: EscCalibrationStep,
+    },
+    EscCalibrationStatus {
+        result: EscCalibrationResult,
+    },
+    /// Configures one servo output (index 0..=5), only while disarmed. Stored persistently.
+    SetServo {
+        output: u8,
+        setting: ServoSetting,
+    },
+    GpsData {
+        fix: GpsFix,
+        latitude: i32,                  // [1e-7 deg]
+        longitude: i32,                 // [1e-7 deg]
+        altitude: f32,                  // [m], above mean sea level
+        velocity: [f32; 2],             // [m/s], north and east
+        vertical_velocity: Option<f32>, // [m/s], positive up, not available via NMEA
+        horizontal_accuracy: f32,       // [m]
+        vertical_accuracy: f32,         // [m]
+        speed_accuracy: Option<f32>,    // [m/s], not available via NMEA
+        satellites: u8,
+    },
+    /// Selects the attitude estimation backend, only while disarmed.
+    SetAttitudeEstimator {
+        estimator: AttitudeEstimator,
+    },
+    /// Position and velocity relative to the first GPS fix.
+    NavigationData {
+        position: [f32; 3],     // [m], north, east, down
+        velocity: [f32; 3],     // [m/s], north, east, down
+        position_std: [f32; 3], // [m]
+        velocity_std: [f32; 3], // [m/s]
+    },
+    /// Action on the loss of the radio link, only while disarmed. Stored persistently.
+    SetFailsafeAction {
+        action: FailsafeAction,
+    },
+    /// Item `index` of a mission with `count` items, only while disarmed. Items are sent in order
+    /// and each one is acknowledged, index 0 starts a new upload. The mission is stored
+    /// persistently after the last item.
+    MissionItem {
+        index: u16,
+        count: u16,
+        item: MissionItem,
+    },
+    /// Deletes the stored mission, only while disarmed.
+    ClearMission,
+    MissionAck {
+        index: u16,
+        result: MissionResult,
+    },
+    /// Replaces the geofence, only while disarmed. A polygon of `vertices` vertices follows,
+    /// sent in order and each one acknowledged. The fence is stored persistently after the last
+    /// vertex, or right away without a polygon.
+    SetFence {
+        max_altitude: Option<f32>, // [m], above home
+        max_distance: Option<f32>, // [m], from home
+        vertices: u8,
+        action: FenceAction,
+    },
+    FenceVertex {
+        index: u8,
+        latitude: i32,  // [1e-7 deg]
+        longitude: i32, // [1e-7 deg]
+    },
+    /// Reply to `SetFence` (no vertex index) and `FenceVertex`.
+    FenceAck {
+        vertex: Option<u8>,
+        result: FenceResult,
+    },
+    /// Sent whenever the vehicle leaves or re-enters the geofence.
+    FenceStatus {
+        breach: Option<FenceBreach>,
+    },
+    /// Configures the motor outputs, only while disarmed. Stored persistently.
+    SetOutput {
+        setting: OutputSetting,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum FlightMode {
+    Manual,
+    /// The throttle stick commands the climb rate.
+    AltitudeHold,
+    /// Like `AltitudeHold`, the roll and pitch sticks command the horizontal velocity and the
+    /// position is held with centred sticks. Needs a GPS fix.
+    PositionHold,
+    /// Climbs, flies back to where the vehicle was armed and lands there.
+    ReturnToHome,
+    /// Flies the stored mission from the start. Needs a GPS fix.
+    Mission,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum MotorTestCommand {
+    /// Only allowed while disarmed with the radio throttle low.
+    Start,
+    Stop,
+    /// Needs to be sent regularly, otherwise the test stops.
+    KeepAlive,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum MotorTestResult {
+    Started,
+    Stopped,
+    Timeout,
+    Armed,
+    ThrottleNotLow,
+    NotActive,
+}
+
+/// Side of the vehicle facing down during the accelerometer calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum AccelOrientation {
+    Level,
+    UpsideDown,
+    NoseUp,
+    NoseDown,
+    LeftSideDown,
+    RightSideDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum CalibrationStep {
+    Gyro,
+    Accel {
+        orientation: AccelOrientation,
+    },
+    AccelFinish,
+    /// Only supported with the MPU-9250, the vehicle needs to stay still while it warms up.
+    GyroTemperatureStart,
+    GyroTemperatureFinish,
+    MagStart,
+    MagFinish,
+    Reset,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum CalibrationResult {
+    Ok,
+    Motion,
+    WrongOrientation,
+    Incomplete,
+    TemperatureRange,
+    /// Only the MPU-9250 reports its temperature for the gyro drift calibration.
+    NoTemperature,
+    Fit,
+    /// Calibrations are only run while disarmed.
+    Armed,
+    StorageError,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum EscProtocol {
+    /// Servo PWM (1 .. 2 ms pulses), up to 400 Hz.
+    #[default]
+    Pwm,
+    /// 125 .. 250 us pulses.
+    OneShot125,
+    /// 42 .. 84 us pulses.
+    OneShot42,
+    /// 5 .. 25 us pulses.
+    Multishot,
+    DShot {
+        speed: DShotSpeed,
+        /// The ESCs answer each frame with their eRPM.
+        bidirectional: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum DShotSpeed {
+    DShot150,
+    DShot300,
+    DShot600,
+}
+
+/// Special ESC commands, only supported by digital protocols.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum EscCommand {
+    Beep,
+    SpinDirectionNormal,
+    SpinDirectionReversed,
+    SaveSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum EscCalibrationStep {
+    /// Outputs max throttle. The props must be removed and the battery disconnected.
+    Start,
+    /// Outputs min throttle after the ESCs have been powered and have beeped.
+    BatteryConnected,
+    Abort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum EscCalibrationResult {
+    /// Max throttle is output, the battery can be connected.
+    MaxThrottle,
+    MinThrottle,
+    Finished,
+    Aborted,
+    Timeout,
+    Armed,
+    ThrottleNotLow,
+    NotActive,
+    /// Digital protocols do not need calibration.
+    NotSupported,
+}
+
+/// What a servo output is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum ServoFunction {
+    TiltLeft,
+    TiltRight,
+    Elevator,
+    Rudder,
+    AileronLeft,
+    AileronRight,
+    ElevonLeft,
+    ElevonRight,
+}
+
+/// Positions are `[-1.0 .. 1.0]`, corresponding to 1000 .. 2000 us pulses.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum ServoSetting {
+    /// `None` disables the output.
+    Function(Option<ServoFunction>),
+    /// Position at zero command.
+    Trim(f32),
+    Endpoints {
+        min: f32,
+        max: f32,
+    },
+    Reversed(bool),
+    /// Maximum change of the position [1/s], `None` for no limit.
+    Rate(Option<f32>),
+}
+
+/// Outputs are `[0.0 .. 1.0]` of the ESC range.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum OutputSetting {
+    /// Output while armed at zero thrust, keeps the motors spinning.
+    Idle(f32),
+    /// Output range that thrust is mapped to, for one motor (index 0..=3).
+    Endpoints { motor: u8, min: f32, max: f32 },
+    /// Corrects for thrust being proportional to the command squared.
+    Linearize(bool),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum FailsafeAction {
+    /// Descends at the current position.
+    #[default]
+    Land,
+    /// Returns to the home position, lands at the current position without one.
+    ReturnToHome,
+}
+
+/// Altitudes are above the home position.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum MissionItem {
+    /// Climbs vertically to the altitude.
+    Takeoff {
+        altitude: f32, // [m]
+    },
+    Waypoint {
+        latitude: i32,  // [1e-7 deg]
+        longitude: i32, // [1e-7 deg]
+        altitude: f32,  // [m]
+    },
+    /// Holds the position in hover, circles clockwise in fixed-wing flight.
+    Loiter {
+        latitude: i32,  // [1e-7 deg]
+        longitude: i32, // [1e-7 deg]
+        altitude: f32,  // [m]
+        radius: f32,    // [m]
+        time: f32,      // [s]
+    },
+    /// Transition to forward flight or back to hover.
+    Transition { forward: bool },
+    /// Lands at the position (latitude and longitude [1e-7 deg]), or at the current one.
+    Land { position: Option<[i32; 2]> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum MissionResult {
+    Accepted,
+    /// The last item was accepted and the mission is stored.
+    Stored,
+    /// The mission was deleted.
+    Cleared,
+    /// Items need to be sent in order, starting at index 0.
+    OutOfSequence,
+    TooManyItems,
+    Armed,
+    StorageError,
+}
+
+/// Action on leaving the geofence, active until the flight mode is changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum FenceAction {
+    /// Only reports the breach.
+    Warn,
+    /// Holds the position and the altitude.
+    Hold,
+    #[default]
+    ReturnToHome,
+    /// Descends at the current position.
+    Land,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum FenceBreach {
+    Altitude,
+    Distance,
+    Polygon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum FenceResult {
+    Accepted,
+    /// The fence is complete and stored.
+    Stored,
+    /// Vertices need to be sent in order, after `SetFence`.
+    OutOfSequence,
+    TooManyVertices,
+    /// A polygon needs at least 3 vertices.
+    TooFewVertices,
+    Armed,
+    StorageError,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum AttitudeEstimator {
+    #[default]
+    Fusion,
+    Mahony,
+    Madgwick,
+    /// Error-state Kalman filter.
+    Eskf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum GpsFix {
+    NoFix,
+    DeadReckoning,
+    Fix2d,
+    Fix3d,
+    TimeOnly,
+}
+
+/// Cause of the last MCU reset.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, defmt::Format)]
+pub enum ResetReason {
+    PowerOn,
+    Brownout,
+    /// Reset pin (e.g., the reset button or a debugger).
+    Pin,
+    Software,
+    IndependentWatchdog,
+    WindowWatchdog,
+    LowPower,
+    Unknown,
 }
 
 const HEADER_LEN: usize = 1;
@@ -57,6 +485,9 @@ mod tests {
             pitch: 1.0,
             yaw: -0.1,
             thrust: 0.0,
+            mode: FlightMode::AltitudeHold,
+            armed: true,
+            forward: false,
         };
         let mut buf = [0; 255];
         encode(&msg, &mut buf).unwrap();
@@ -74,4 +505,99 @@ mod tests {
         let msg_decoded = decode(&buf).unwrap();
         assert_eq!(msg, msg_decoded)
     }
+
+    #[test]
+    fn encode_decode_calibration_status() {
+        let msg = Message::CalibrationStatus {
+            step: CalibrationStep::Accel {
+                orientation: AccelOrientation::NoseDown,
+            },
+            result: CalibrationResult::WrongOrientation,
+        };
+        let mut buf = [0; 255];
+        encode(&msg, &mut buf).unwrap();
+        let msg_decoded = decode(&buf).unwrap();
+        assert_eq!(msg, msg_decoded)
+    }
+
+    #[test]
+    fn encode_decode_esc_command() {
+        let msg = Message::EscCommand {
+            motor: Some(2),
+            command: EscCommand::SpinDirectionReversed,
+        };
+        let mut buf = [0; 255];
+        encode(&msg, &mut buf).unwrap();
+        let msg_decoded = decode(&buf).unwrap();
+        assert_eq!(msg, msg_decoded)
+    }
+
+    #[test]
+    fn encode_decode_set_servo() {
+        let msg = Message::SetServo {
+            output: 2,
+            setting: ServoSetting::Endpoints {
+                min: -0.8,
+                max: 0.9,
+            },
+        };
+        let mut buf = [0; 255];
+        encode(&msg, &mut buf).unwrap();
+        let msg_decoded = decode(&buf).unwrap();
+        assert_eq!(msg, msg_decoded)
+    }
+
+    #[test]
+    fn encode_decode_set_output() {
+        let msg = Message::SetOutput {
+            setting: OutputSetting::Endpoints {
+                motor: 3,
+                min: 0.05,
+                max: 0.95,
+            },
+        };
+        let mut buf = [0; 255];
+        encode(&msg, &mut buf).unwrap();
+        let msg_decoded = decode(&buf).unwrap();
+        assert_eq!(msg, msg_decoded)
+    }
+
+    #[test]
+    fn encode_decode_gps_data() {
+        let msg = Message::GpsData {
+            fix: GpsFix::Fix3d,
+            latitude: 472_852_395,
+            longitude: -85_652_537,
+            altitude: 499.6,
+            velocity: [1.5, -0.5],
+            vertical_velocity: None,
+            horizontal_accuracy: 2.1,
+            vertical_accuracy: 3.4,
+            speed_accuracy: Some(0.3),
+            satellites: 12,
+        };
+        let mut buf = [0; 255];
+        encode(&msg, &mut buf).unwrap();
+        let msg_decoded = decode(&buf).unwrap();
+        assert_eq!(msg, msg_decoded)
+    }
+
+    #[test]
+    fn encode_decode_mission_item() {
+        let msg = Message::MissionItem {
+            index: 3,
+            count: 8,
+            item: MissionItem::Loiter {
+                latitude: 472_852_395,
+                longitude: -85_652_537,
+                altitude: 40.0,
+                radius: 80.0,
+                time: 30.0,
+            },
+        };
+        let mut buf = [0; 64];
+        encode(&msg, &mut buf).unwrap();
+        let msg_decoded = decode(&buf).unwrap();
+        assert_eq!(msg, msg_decoded)
+    }
 }
@@ -1,14 +1,38 @@
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 
 use anyhow::Result;
 use anyhow::anyhow;
 use clap::Parser;
 use futures_util::StreamExt;
+use protocol::AccelOrientation;
+use protocol::AttitudeEstimator;
+use protocol::CalibrationStep;
+use protocol::DShotSpeed;
+use protocol::EscCalibrationStep;
+use protocol::EscCommand;
+use protocol::EscProtocol;
+use protocol::FailsafeAction;
+use protocol::FenceAction;
+use protocol::FenceResult;
 use protocol::Message;
+use protocol::MissionItem;
+use protocol::MissionResult;
+use protocol::MotorTestCommand;
+use protocol::MotorTestResult;
+use protocol::OutputSetting;
+use protocol::ServoFunction;
+use protocol::ServoSetting;
 use protocol::encode;
 use rustyline::error::ReadlineError;
 use tokio::fs::File;
+use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tokio::sync::mpsc;
 use tokio_serial::SerialPortBuilderExt;
 use tokio_util::codec::FramedRead;
 
@@ -17,6 +41,11 @@ mod decoder;
 use decoder::FrameDecoder;
 
 const HISTORY_FILE_NAME: &str = "history.txt";
+/// Needs to be well below the motor test timeout of the vehicle.
+const KEEP_ALIVE_PERIOD: Duration = Duration::from_millis(200);
+/// Requests (e.g. mission items) are sent again if not acknowledged within this time.
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+const ACK_ATTEMPTS: u32 = 3;
 const PROMPT: &str = "\x1b[1;33mUAV REMOTE \x1b[1;34m❯❯ \x1b[0m";
 
 #[derive(Parser, Debug)]
@@ -45,6 +74,375 @@ fn parse_motor_array(s: &str) -> Result<[f32; 4]> {
         .map_err(|_| anyhow!("Expected 4 floats as input"))
 }
 
+fn parse_calibration_step(args: &[String]) -> Result<CalibrationStep> {
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+    Ok(match (arg(1), arg(2)) {
+        ("gyro", _) => CalibrationStep::Gyro,
+        ("accel", "finish") => CalibrationStep::AccelFinish,
+        ("accel", orientation) => CalibrationStep::Accel {
+            orientation: parse_accel_orientation(orientation)?,
+        },
+        ("temperature", "start") => CalibrationStep::GyroTemperatureStart,
+        ("temperature", "finish") => CalibrationStep::GyroTemperatureFinish,
+        ("mag", "start") => CalibrationStep::MagStart,
+        ("mag", "finish") => CalibrationStep::MagFinish,
+        ("reset", _) => CalibrationStep::Reset,
+        _ => {
+            return Err(anyhow!(
+                "Usage: calibrate gyro | accel <orientation|finish> | temperature <start|finish> | mag <start|finish> | reset"
+            ));
+        }
+    })
+}
+
+fn parse_accel_orientation(s: &str) -> Result<AccelOrientation> {
+    Ok(match s {
+        "level" => AccelOrientation::Level,
+        "upside-down" => AccelOrientation::UpsideDown,
+        "nose-up" => AccelOrientation::NoseUp,
+        "nose-down" => AccelOrientation::NoseDown,
+        "left-side-down" => AccelOrientation::LeftSideDown,
+        "right-side-down" => AccelOrientation::RightSideDown,
+        _ => {
+            return Err(anyhow!(
+                "Expected one of level, upside-down, nose-up, nose-down, left-side-down, right-side-down"
+            ));
+        }
+    })
+}
+
+fn parse_esc_message(args: &[String]) -> Result<Message> {
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+    let motor = |i: usize| -> Result<Option<u8>> {
+        match args.get(i) {
+            None => Ok(None),
+            Some(motor) => match motor.parse::<u8>() {
+                Ok(motor @ 1..=4) => Ok(Some(motor - 1)),
+                _ => Err(anyhow!("Expected a motor number 1 .. 4")),
+            },
+        }
+    };
+    Ok(match (arg(1), arg(2)) {
+        ("protocol", protocol) => {
+            let analog = match protocol {
+                "pwm" => Some(EscProtocol::Pwm),
+                "oneshot125" => Some(EscProtocol::OneShot125),
+                "oneshot42" => Some(EscProtocol::OneShot42),
+                "multishot" => Some(EscProtocol::Multishot),
+                _ => None,
+            };
+            let speed = match protocol {
+                "dshot150" => Some(DShotSpeed::DShot150),
+                "dshot300" => Some(DShotSpeed::DShot300),
+                "dshot600" => Some(DShotSpeed::DShot600),
+                _ => None,
+            };
+            match (analog, speed) {
+                (Some(protocol), _) => Message::SetEscProtocol {
+                    protocol,
+                    rate: match args.get(3) {
+                        Some(rate) => Some(
+                            rate.parse()
+                                .map_err(|_| anyhow!("Expected the output rate in Hz"))?,
+                        ),
+                        None => None,
+                    },
+                },
+                (None, Some(speed)) => Message::SetEscProtocol {
+                    protocol: EscProtocol::DShot {
+                        speed,
+                        bidirectional: arg(3) == "bidir",
+                    },
+                    rate: None,
+                },
+                (None, None) => {
+                    return Err(anyhow!(
+                        "Expected one of pwm, oneshot125, oneshot42, multishot, dshot150, dshot300, dshot600"
+                    ));
+                }
+            }
+        }
+        ("timeout", timeout) => Message::SetOutputTimeout {
+            timeout: timeout
+                .parse()
+                .map_err(|_| anyhow!("Expected the output timeout in ms"))?,
+        },
+        ("poles", poles) => Message::SetMotorPoles {
+            poles: poles
+                .parse()
+                .map_err(|_| anyhow!("Expected the number of motor poles"))?,
+        },
+        ("beep", _) => Message::EscCommand {
+            motor: motor(2)?,
+            command: EscCommand::Beep,
+        },
+        ("direction", "normal") => Message::EscCommand {
+            motor: motor(3)?,
+            command: EscCommand::SpinDirectionNormal,
+        },
+        ("direction", "reversed") => Message::EscCommand {
+            motor: motor(3)?,
+            command: EscCommand::SpinDirectionReversed,
+        },
+        ("save", _) => Message::EscCommand {
+            motor: motor(2)?,
+            command: EscCommand::SaveSettings,
+        },
+        ("calibrate", "start") => Message::EscCalibration {
+            step: EscCalibrationStep::Start,
+        },
+        ("calibrate", "battery") => Message::EscCalibration {
+            step: EscCalibrationStep::BatteryConnected,
+        },
+        ("calibrate", "abort") => Message::EscCalibration {
+            step: EscCalibrationStep::Abort,
+        },
+        _ => {
+            return Err(anyhow!(
+                "Usage: esc protocol <pwm|oneshot125|oneshot42|multishot> [rate] | protocol <dshot150|dshot300|dshot600> [bidir] | poles <n> | timeout <ms> | beep [motor] | direction <normal|reversed> [motor] | save [motor] | calibrate <start|battery|abort>"
+            ));
+        }
+    })
+}
+
+fn parse_servo_message(args: &[String]) -> Result<Message> {
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+    let value = |i: usize| -> Result<f32> {
+        arg(i)
+            .parse::<f32>()
+            .map_err(|_| anyhow!("Expected a number, positions are [-1.0 .. 1.0]"))
+    };
+    let output = match arg(1).parse::<u8>() {
+        Ok(output @ 1..=6) => output - 1,
+        _ => return Err(anyhow!("Expected a servo output 1 .. 6")),
+    };
+    let setting = match arg(2) {
+        "function" => ServoSetting::Function(match arg(3) {
+            "tilt-left" => Some(ServoFunction::TiltLeft),
+            "tilt-right" => Some(ServoFunction::TiltRight),
+            "elevator" => Some(ServoFunction::Elevator),
+            "rudder" => Some(ServoFunction::Rudder),
+            "aileron-left" => Some(ServoFunction::AileronLeft),
+            "aileron-right" => Some(ServoFunction::AileronRight),
+            "elevon-left" => Some(ServoFunction::ElevonLeft),
+            "elevon-right" => Some(ServoFunction::ElevonRight),
+            "none" => None,
+            _ => {
+                return Err(anyhow!(
+                    "Expected one of tilt-left, tilt-right, elevator, rudder, aileron-left, aileron-right, elevon-left, elevon-right, none"
+                ));
+            }
+        }),
+        "trim" => ServoSetting::Trim(value(3)?),
+        "endpoints" => ServoSetting::Endpoints {
+            min: value(3)?,
+            max: value(4)?,
+        },
+        "normal" => ServoSetting::Reversed(false),
+        "reversed" => ServoSetting::Reversed(true),
+        "rate" => ServoSetting::Rate(match arg(3) {
+            "none" => None,
+            _ => Some(value(3)?),
+        }),
+        _ => {
+            return Err(anyhow!(
+                "Usage: servo <output> function <function|none> | trim <position> | endpoints <min> <max> | normal | reversed | rate <1/s|none>"
+            ));
+        }
+    };
+    Ok(Message::SetServo { output, setting })
+}
+
+fn parse_output_message(args: &[String]) -> Result<Message> {
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+    let value = |i: usize| -> Result<f32> {
+        arg(i)
+            .parse::<f32>()
+            .map_err(|_| anyhow!("Expected a number, outputs are [0.0 .. 1.0]"))
+    };
+    let setting = match arg(1) {
+        "idle" => OutputSetting::Idle(value(2)?),
+        "endpoints" => OutputSetting::Endpoints {
+            motor: match arg(2).parse::<u8>() {
+                Ok(motor @ 1..=4) => motor - 1,
+                _ => return Err(anyhow!("Expected a motor 1 .. 4")),
+            },
+            min: value(3)?,
+            max: value(4)?,
+        },
+        "linearize" => OutputSetting::Linearize(match arg(2) {
+            "on" => true,
+            "off" => false,
+            _ => return Err(anyhow!("Expected on or off")),
+        }),
+        _ => {
+            return Err(anyhow!(
+                "Usage: output idle <output> | endpoints <motor> <min> <max> | linearize <on|off>"
+            ));
+        }
+    };
+    Ok(Message::SetOutput { setting })
+}
+
+fn parse_motor_test(args: &[String]) -> Result<Message> {
+    let arg = |i: usize| args.get(i).map(String::as_str).unwrap_or_default();
+    let thrust = || -> Result<f32> {
+        arg(3)
+            .parse::<f32>()
+            .map_err(|_| anyhow!("Expected the thrust [0.0 .. 1.0]"))
+    };
+    Ok(match (arg(1), arg(2)) {
+        ("test", "start") => Message::MotorTest {
+            command: MotorTestCommand::Start,
+        },
+        ("test", "stop") => Message::MotorTest {
+            command: MotorTestCommand::Stop,
+        },
+        ("test", "all") => Message::MotorDebug {
+            thrust: [thrust()?; 4],
+        },
+        ("test", motor) => match motor.parse::<usize>() {
+            Ok(motor @ 1..=4) => {
+                let mut motors = [0.0; 4];
+                motors[motor - 1] = thrust()?;
+                Message::MotorDebug { thrust: motors }
+            }
+            _ => return Err(anyhow!("Expected a motor number 1 .. 4")),
+        },
+        _ => {
+            return Err(anyhow!(
+                "Usage: motor test start | stop | all <thrust> | <motor> <thrust>"
+            ));
+        }
+    })
+}
+
+/// Sends `msg` until `ack` accepts a reply, which is returned.
+async fn request<A, R>(
+    writer: &Mutex<impl AsyncWrite + Unpin>,
+    acks: &mut mpsc::UnboundedReceiver<A>,
+    msg: &Message,
+    ack: impl Fn(A) -> Option<R>,
+) -> Result<R> {
+    // Drop replies to earlier, aborted requests.
+    while acks.try_recv().is_ok() {}
+    for _ in 0..ACK_ATTEMPTS {
+        send(&mut *writer.lock().await, msg).await?;
+        let reply = tokio::time::timeout(ACK_TIMEOUT, async {
+            while let Some(reply) = acks.recv().await {
+                if let Some(result) = ack(reply) {
+                    return Some(result);
+                }
+            }
+            None
+        })
+        .await;
+        match reply {
+            Ok(Some(result)) => return Ok(result),
+            Ok(None) => return Err(anyhow!("Connection closed")),
+            Err(_) => eprintln!("No acknowledgement, retrying"),
+        }
+    }
+    Err(anyhow!("No acknowledgement"))
+}
+
+/// Sends the items one by one, each one after the previous was acknowledged.
+async fn upload_mission(
+    writer: &Mutex<impl AsyncWrite + Unpin>,
+    acks: &mut mpsc::UnboundedReceiver<(u16, MissionResult)>,
+    items: &[MissionItem],
+) -> Result<()> {
+    let count = u16::try_from(items.len()).map_err(|_| anyhow!("Too many mission items"))?;
+    if count == 0 {
+        return Err(anyhow!("Empty mission, use 'mission clear' instead"));
+    }
+    for (index, item) in (0..count).zip(items) {
+        let msg = Message::MissionItem {
+            index,
+            count,
+            item: *item,
+        };
+        let result = request(writer, acks, &msg, |(ack_index, result)| {
+            (ack_index == index).then_some(result)
+        })
+        .await?;
+        match result {
+            MissionResult::Accepted | MissionResult::Stored => {}
+            result => return Err(anyhow!("Mission item {index} rejected: {result:?}")),
+        }
+    }
+    Ok(())
+}
+
+/// `args` are `fence set <max altitude|none> <max distance|none> <action> [polygon file]`.
+async fn upload_fence(
+    writer: &Mutex<impl AsyncWrite + Unpin>,
+    acks: &mut mpsc::UnboundedReceiver<(Option<u8>, FenceResult)>,
+    args: &[String],
+) -> Result<FenceResult> {
+    let limit = |i: usize| -> Result<Option<f32>> {
+        match args.get(i).map(String::as_str) {
+            Some("none") => Ok(None),
+            Some(limit) => {
+                Ok(Some(limit.parse().map_err(|_| {
+                    anyhow!("Expected a limit in meters or 'none'")
+                })?))
+            }
+            None => Err(anyhow!(
+                "Usage: fence set <max altitude|none> <max distance|none> <warn|hold|rth|land> [polygon file, JSON list of [latitude, longitude] in 1e-7 deg]"
+            )),
+        }
+    };
+    let max_altitude = limit(2)?;
+    let max_distance = limit(3)?;
+    let action = match args.get(4).map(String::as_str) {
+        Some("warn") => FenceAction::Warn,
+        Some("hold") => FenceAction::Hold,
+        Some("rth") => FenceAction::ReturnToHome,
+        Some("land") => FenceAction::Land,
+        _ => return Err(anyhow!("Expected one of warn, hold, rth, land")),
+    };
+    let polygon: Vec<[i32; 2]> = match args.get(5) {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => Vec::new(),
+    };
+    let vertices = u8::try_from(polygon.len()).map_err(|_| anyhow!("Too many vertices"))?;
+
+    let msg = Message::SetFence {
+        max_altitude,
+        max_distance,
+        vertices,
+        action,
+    };
+    let mut result = request(writer, acks, &msg, |(vertex, result)| {
+        vertex.is_none().then_some(result)
+    })
+    .await?;
+    for (index, [latitude, longitude]) in (0..vertices).zip(polygon) {
+        if result != FenceResult::Accepted {
+            break;
+        }
+        let msg = Message::FenceVertex {
+            index,
+            latitude,
+            longitude,
+        };
+        result = request(writer, acks, &msg, |(vertex, result)| {
+            (vertex == Some(index)).then_some(result)
+        })
+        .await?;
+    }
+    Ok(result)
+}
+
+async fn send(writer: &mut (impl AsyncWrite + Unpin), msg: &Message) -> Result<()> {
+    let mut buf: [u8; 64] = [0; 64];
+    let len = encode(msg, &mut buf)?;
+    writer.write_all(&buf[..len]).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let config = Config::parse();
@@ -59,8 +457,33 @@ async fn main() -> Result<()> {
         .stop_bits(tokio_serial::StopBits::One)
         .flow_control(tokio_serial::FlowControl::None)
         .open_native_async()?;
-    let (reader, mut writer) = tokio::io::split(port);
+    let (reader, writer) = tokio::io::split(port);
+    let writer = Arc::new(Mutex::new(writer));
     let mut decoder = FramedRead::new(reader, FrameDecoder {});
+    let motor_test_active = Arc::new(AtomicBool::new(false));
+
+    // Keeps the motor test running until it is stopped.
+    {
+        let writer = writer.clone();
+        let motor_test_active = motor_test_active.clone();
+        tokio::spawn(async move {
+            let keep_alive = Message::MotorTest {
+                command: MotorTestCommand::KeepAlive,
+            };
+            loop {
+                tokio::time::sleep(KEEP_ALIVE_PERIOD).await;
+                if motor_test_active.load(Ordering::Relaxed)
+                    && let Err(e) = send(&mut *writer.lock().await, &keep_alive).await
+                {
+                    eprintln!("Failed to send keep-alive: {e}");
+                }
+            }
+        });
+    }
+
+    let motor_test_status = motor_test_active.clone();
+    let (mission_acks, mut mission_ack_receiver) = mpsc::unbounded_channel();
+    let (fence_acks, mut fence_ack_receiver) = mpsc::unbounded_channel();
     tokio::spawn(async move {
         let mut imu_data_file = match config.imu_data_path {
             Some(imu_data_path) => Some(File::create(imu_data_path).await.unwrap()),
@@ -71,6 +494,34 @@ async fn main() -> Result<()> {
             let line = line.unwrap(); // TODO
             let msg = protocol::decode(&line).unwrap();
 
+            match &msg {
+                Message::CalibrationStatus { step, result } => {
+                    println!("Calibration {step:?}: {result:?}");
+                }
+                Message::Boot { reset_reason } => {
+                    println!("Vehicle started, last reset: {reset_reason:?}");
+                }
+                Message::EscCalibrationStatus { result } => {
+                    println!("ESC calibration: {result:?}");
+                }
+                Message::MotorTestStatus { result } => {
+                    motor_test_status.store(*result == MotorTestResult::Started, Ordering::Relaxed);
+                    println!("Motor test: {result:?}");
+                }
+                Message::MissionAck { index, result } => {
+                    // Only fails once the command loop has ended.
+                    let _ = mission_acks.send((*index, *result));
+                }
+                Message::FenceAck { vertex, result } => {
+                    let _ = fence_acks.send((*vertex, *result));
+                }
+                Message::FenceStatus { breach } => match breach {
+                    Some(breach) => println!("\x1b[1;31mGeofence breach: {breach:?}\x1b[0m"),
+                    None => println!("Inside the geofence"),
+                },
+                _ => {}
+            }
+
             if let Some(imu_data_file) = imu_data_file.as_mut() {
                 let mut data = serde_json::to_vec(&msg).unwrap();
                 data.push(b'\n');
@@ -106,11 +557,160 @@ async fn main() -> Result<()> {
                 "motors" => {
                     let thrust = parse_motor_array(&args[1])?;
                     let cmd = Message::MotorDebug { thrust: thrust };
-                    let mut buf: [u8; 32] = [0; 32];
-                    let len = encode(&cmd, &mut buf)?;
-                    writer.write_all(&buf[..len]).await?;
-                    writer.flush().await?;
+                    send(&mut *writer.lock().await, &cmd).await?;
+                }
+                "motor" => match parse_motor_test(&args) {
+                    Ok(msg) => {
+                        if let Message::MotorTest { command } = msg {
+                            motor_test_active
+                                .store(command == MotorTestCommand::Start, Ordering::Relaxed);
+                        }
+                        send(&mut *writer.lock().await, &msg).await?
+                    }
+                    Err(e) => eprintln!("{e}"),
+                },
+                "declination" => match args.get(1).map(|v| v.parse::<f32>()) {
+                    Some(Ok(declination)) => {
+                        send(
+                            &mut *writer.lock().await,
+                            &Message::SetDeclination { declination },
+                        )
+                        .await?
+                    }
+                    _ => eprintln!("Usage: declination <degrees, positive east>"),
+                },
+                "estimator" => {
+                    let estimator = match args.get(1).map(String::as_str) {
+                        Some("fusion") => Some(AttitudeEstimator::Fusion),
+                        Some("mahony") => Some(AttitudeEstimator::Mahony),
+                        Some("madgwick") => Some(AttitudeEstimator::Madgwick),
+                        Some("eskf") => Some(AttitudeEstimator::Eskf),
+                        _ => None,
+                    };
+                    match estimator {
+                        Some(estimator) => {
+                            send(
+                                &mut *writer.lock().await,
+                                &Message::SetAttitudeEstimator { estimator },
+                            )
+                            .await?
+                        }
+                        None => eprintln!("Usage: estimator <fusion|mahony|madgwick|eskf>"),
+                    }
+                }
+                "failsafe" => {
+                    let action = match args.get(1).map(String::as_str) {
+                        Some("land") => Some(FailsafeAction::Land),
+                        Some("rth") => Some(FailsafeAction::ReturnToHome),
+                        _ => None,
+                    };
+                    match action {
+                        Some(action) => {
+                            send(
+                                &mut *writer.lock().await,
+                                &Message::SetFailsafeAction { action },
+                            )
+                            .await?
+                        }
+                        None => eprintln!("Usage: failsafe <land|rth>"),
+                    }
+                }
+                "mission" => match (args.get(1).map(String::as_str), args.get(2)) {
+                    (Some("upload"), Some(path)) => {
+                        let items = std::fs::read_to_string(path)
+                            .map_err(anyhow::Error::from)
+                            .and_then(|json| {
+                                serde_json::from_str::<Vec<MissionItem>>(&json)
+                                    .map_err(anyhow::Error::from)
+                            });
+                        match items {
+                            Ok(items) => {
+                                match upload_mission(&writer, &mut mission_ack_receiver, &items)
+                                    .await
+                                {
+                                    Ok(()) => println!("Mission stored ({} items)", items.len()),
+                                    Err(e) => eprintln!("Mission upload failed: {e}"),
+                                }
+                            }
+                            Err(e) => eprintln!("Failed to read mission '{path}': {e}"),
+                        }
+                    }
+                    (Some("clear"), _) => {
+                        match request(
+                            &writer,
+                            &mut mission_ack_receiver,
+                            &Message::ClearMission,
+                            |(_, result)| Some(result),
+                        )
+                        .await
+                        {
+                            Ok(result) => println!("Clear mission: {result:?}"),
+                            Err(e) => eprintln!("Clearing the mission failed: {e}"),
+                        }
+                    }
+                    _ => eprintln!(
+                        "Usage: mission upload <file, JSON list of mission items> | clear"
+                    ),
+                },
+                "fence" => {
+                    let result = match args.get(1).map(String::as_str) {
+                        Some("set") => upload_fence(&writer, &mut fence_ack_receiver, &args).await,
+                        Some("clear") => {
+                            let msg = Message::SetFence {
+                                max_altitude: None,
+                                max_distance: None,
+                                vertices: 0,
+                                action: FenceAction::default(),
+                            };
+                            request(&writer, &mut fence_ack_receiver, &msg, |(_, result)| {
+                                Some(result)
+                            })
+                            .await
+                        }
+                        _ => Err(anyhow!("Usage: fence set ... | clear")),
+                    };
+                    match result {
+                        Ok(result) => println!("Geofence: {result:?}"),
+                        Err(e) => eprintln!("{e}"),
+                    }
                 }
+                "calibrate" => match parse_calibration_step(&args) {
+                    Ok(step) => {
+                        send(&mut *writer.lock().await, &Message::Calibrate { step }).await?
+                    }
+                    Err(e) => eprintln!("{e}"),
+                },
+                "servo" => match parse_servo_message(&args) {
+                    Ok(msg) => send(&mut *writer.lock().await, &msg).await?,
+                    Err(e) => eprintln!("{e}"),
+                },
+                "output" => match parse_output_message(&args) {
+                    Ok(msg) => send(&mut *writer.lock().await, &msg).await?,
+                    Err(e) => eprintln!("{e}"),
+                },
+                "esc" => match parse_esc_message(&args) {
+                    Ok(
+                        msg @ Message::EscCalibration {
+                            step: EscCalibrationStep::Start,
+                        },
+                    ) => {
+                        println!(
+                            "\x1b[1;31mESC calibration outputs MAX THROTTLE on all motors!\x1b[0m"
+                        );
+                        println!("Remove all props and disconnect the battery.");
+                        match rl.readline("Type 'props off' to continue: ") {
+                            Ok(confirmation) if confirmation.trim() == "props off" => {
+                                send(&mut *writer.lock().await, &msg).await?;
+                                println!(
+                                    "Connect the battery, wait for the ESCs to beep, then run 'esc calibrate battery'"
+                                );
+                            }
+                            _ => eprintln!("ESC calibration cancelled"),
+                        }
+                    }
+                    Ok(msg) => send(&mut *writer.lock().await, &msg).await?,
+                    Err(e) => eprintln!("{e}"),
+                },
                 _ => {}
             }
         }
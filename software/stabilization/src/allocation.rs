@@ -0,0 +1,435 @@
+//! Control allocation: distributes the desired torques and forces on the motors and the control
+//! surfaces.
+//!
+//! The effectiveness of the actuators depends on the rotor tilt and the airspeed, so the weighted
+//! pseudo-inverse of the effectiveness matrix is recomputed whenever these change. Saturated
+//! actuators are fixed at their limit and the rest of the demand is redistributed on the others.
+//!
+//! The tilt servos are not actuators of the allocation: thrust times the sine of the tilt makes
+//! their effect nonlinear, and the tilt follows the transition schedule rather than the torque
+//! demand. The tilt is the operating point instead, the differential tilt for yaw is added by the
+//! yaw mixer.
+
+use nalgebra::SMatrix;
+use nalgebra::SVector;
+
+/// Roll, pitch and yaw torque, forward and upward force.
+const AXES: usize = 5;
+/// Four motors, aileron, elevator and rudder.
+const ACTUATORS: usize = 7;
+/// Singular values below this are ignored by the pseudo-inverse, so that uncontrollable axes
+/// (e.g. forward force while the rotors are vertical) are left out.
+const SINGULAR_VALUE_LIMIT: f32 = 1e-4;
+/// Tilt and airspeed changes below these do not trigger a recomputation.
+const TILT_TOLERANCE: f32 = 0.005;
+const AIRSPEED_TOLERANCE: f32 = 0.1; // [m/s]
+
+type Effectiveness = SMatrix<f32, AXES, ACTUATORS>;
+type Allocation = SMatrix<f32, ACTUATORS, AXES>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MotorGeometry {
+    /// Position relative to the center of gravity, `[forward, right]` [m].
+    pub position: [f32; 2],
+    /// Direction of the reaction torque, 1.0 for a propeller turning counter-clockwise seen
+    /// from above (yaws the vehicle right), -1.0 for clockwise.
+    pub direction: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorConfig {
+    pub motors: [MotorGeometry; 4],
+    /// Thrust of one motor at full command [N].
+    pub max_thrust: f32,
+    /// Reaction torque per thrust [m].
+    pub torque_ratio: f32,
+    /// Aileron, elevator and rudder torque at full deflection and the reference airspeed [Nm].
+    pub surface_torque: [f32; 3],
+    pub reference_airspeed: f32, // [m/s]
+    /// Cost of using the motors and the surfaces, higher values make an actuator less used.
+    pub motor_weight: f32,
+    pub surface_weight: f32,
+    /// Maximum change of the motor and surface commands [1/s], `None` for no limit.
+    pub motor_rate: Option<f32>,
+    pub surface_rate: Option<f32>,
+}
+
+impl Default for AllocatorConfig {
+    fn default() -> Self {
+        let motor = |forward: f32, right: f32, direction: f32| MotorGeometry {
+            position: [forward, right],
+            direction,
+        };
+        Self {
+            // Plus layout of the hover controller and the yaw mixer: right, left, front, rear.
+            motors: [
+                motor(0.0, 0.25, 1.0),
+                motor(0.0, -0.25, 1.0),
+                motor(0.25, 0.0, -1.0),
+                motor(-0.25, 0.0, -1.0),
+            ],
+            max_thrust: 8.0,
+            torque_ratio: 0.02,
+            surface_torque: [1.0, 1.5, 0.5],
+            reference_airspeed: 15.0,
+            motor_weight: 1.0,
+            surface_weight: 0.1,
+            motor_rate: None,
+            surface_rate: Some(5.0),
+        }
+    }
+}
+
+/// Desired torques and forces in the body frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Demand {
+    /// Roll (right wing down), pitch (nose up) and yaw (nose right) torque [Nm].
+    pub torque: [f32; 3],
+    /// Forward force [N].
+    pub forward: f32,
+    /// Upward force [N].
+    pub up: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ActuatorCommands {
+    /// Motor thrust `[0.0 .. 1.0]`.
+    pub motors: [f32; 4],
+    /// Deflections `[-1.0 .. 1.0]`, same conventions as the torques.
+    pub aileron: f32,
+    pub elevator: f32,
+    pub rudder: f32,
+}
+
+impl ActuatorCommands {
+    fn from_vector(u: &SVector<f32, ACTUATORS>) -> Self {
+        Self {
+            motors: [u[0], u[1], u[2], u[3]],
+            aileron: u[4],
+            elevator: u[5],
+            rudder: u[6],
+        }
+    }
+
+    fn to_vector(self) -> SVector<f32, ACTUATORS> {
+        let [m0, m1, m2, m3] = self.motors;
+        SVector::from([m0, m1, m2, m3, self.aileron, self.elevator, self.rudder])
+    }
+}
+
+pub struct ControlAllocator {
+    config: AllocatorConfig,
+    /// Tilt and airspeed the matrices were computed for.
+    operating_point: Option<(f32, f32)>,
+    effectiveness: Effectiveness,
+    allocation: Allocation,
+    commands: ActuatorCommands,
+}
+
+impl ControlAllocator {
+    pub fn new(config: AllocatorConfig) -> Self {
+        Self {
+            config,
+            operating_point: None,
+            effectiveness: Effectiveness::zeros(),
+            allocation: Allocation::zeros(),
+            commands: Default::default(),
+        }
+    }
+
+    /// Effect of the actuators on the torques and forces at the given rotor tilt `[0.0 .. 1.0]`
+    /// (0 for vertical) and airspeed [m/s].
+    pub fn effectiveness(&self, tilt: f32, airspeed: f32) -> SMatrix<f32, 5, 7> {
+        let config = &self.config;
+        let angle = tilt.clamp(0.0, 1.0) * core::f32::consts::FRAC_PI_2;
+        let (sin, cos) = (libm::sinf(angle), libm::cosf(angle));
+        let mut b = Effectiveness::zeros();
+        for (i, motor) in config.motors.iter().enumerate() {
+            let [x, y] = motor.position;
+            let up = config.max_thrust * cos;
+            let forward = config.max_thrust * sin;
+            let reaction = config.max_thrust * config.torque_ratio * motor.direction;
+            b[(0, i)] = -y * up + reaction * sin;
+            b[(1, i)] = x * up;
+            b[(2, i)] = -y * forward + reaction * cos;
+            b[(3, i)] = forward;
+            b[(4, i)] = up;
+        }
+        let ratio = airspeed / config.reference_airspeed;
+        let pressure = ratio * ratio;
+        for (axis, torque) in config.surface_torque.iter().enumerate() {
+            b[(axis, 4 + axis)] = torque * pressure;
+        }
+        b
+    }
+
+    /// Torques and forces the actuator commands produce at the given rotor tilt and airspeed.
+    pub fn demand(&self, commands: &ActuatorCommands, tilt: f32, airspeed: f32) -> Demand {
+        let v = self.effectiveness(tilt, airspeed) * commands.to_vector();
+        Demand {
+            torque: [v[0], v[1], v[2]],
+            forward: v[3],
+            up: v[4],
+        }
+    }
+
+    /// Returns the actuator commands for the demand, within the actuator limits and rates.
+    /// Axes which cannot be achieved are approximated in the least-squares sense, the forces
+    /// are reduced first.
+    pub fn update(
+        &mut self,
+        demand: &Demand,
+        tilt: f32,
+        airspeed: f32,
+        dt: f32,
+    ) -> ActuatorCommands {
+        let recompute = match self.operating_point {
+            Some((last_tilt, last_airspeed)) => {
+                (tilt - last_tilt).abs() > TILT_TOLERANCE
+                    || (airspeed - last_airspeed).abs() > AIRSPEED_TOLERANCE
+            }
+            None => true,
+        };
+        if recompute {
+            self.effectiveness = self.effectiveness(tilt, airspeed);
+            self.allocation = self.pseudo_inverse(&[true; ACTUATORS]);
+            self.operating_point = Some((tilt, airspeed));
+        }
+
+        let config = &self.config;
+        let previous = self.commands.to_vector();
+        let mut lower = SVector::<f32, ACTUATORS>::zeros();
+        let mut upper = SVector::<f32, ACTUATORS>::zeros();
+        for i in 0..ACTUATORS {
+            let (min, max, rate) = if i < 4 {
+                (0.0, 1.0, config.motor_rate)
+            } else {
+                (-1.0, 1.0, config.surface_rate)
+            };
+            (lower[i], upper[i]) = match rate {
+                Some(rate) => (
+                    (previous[i] - rate * dt).clamp(min, max),
+                    (previous[i] + rate * dt).clamp(min, max),
+                ),
+                None => (min, max),
+            };
+        }
+
+        // The torques have priority: if the forces do not fit within the limits together with
+        // them, the forces are scaled down.
+        let [roll, pitch, yaw] = demand.torque;
+        let torque = SVector::from([roll, pitch, yaw, 0.0, 0.0]);
+        let force = SVector::from([0.0, 0.0, 0.0, demand.forward, demand.up]);
+        let (torque_part, force_part) = (self.allocation * torque, self.allocation * force);
+        let (mut min_scale, mut max_scale) = (0.0f32, 1.0f32);
+        for i in 0..ACTUATORS {
+            if force_part[i].abs() > f32::EPSILON {
+                let a = (lower[i] - torque_part[i]) / force_part[i];
+                let b = (upper[i] - torque_part[i]) / force_part[i];
+                min_scale = min_scale.max(a.min(b));
+                max_scale = max_scale.min(a.max(b));
+            }
+        }
+        let v = if min_scale <= max_scale {
+            torque + force * max_scale
+        } else {
+            torque + force
+        };
+        let mut free = [true; ACTUATORS];
+        let mut u = SVector::<f32, ACTUATORS>::zeros();
+        for _ in 0..ACTUATORS {
+            let allocation = if free.iter().all(|free| *free) {
+                self.allocation
+            } else {
+                self.pseudo_inverse(&free)
+            };
+            let fixed = u.zip_map(&SVector::from(free), |u, free| if free { 0.0 } else { u });
+            let unfixed = allocation * (v - self.effectiveness * fixed);
+            let mut saturated = false;
+            for i in 0..ACTUATORS {
+                if !free[i] {
+                    continue;
+                }
+                u[i] = unfixed[i];
+                if u[i] < lower[i] || u[i] > upper[i] {
+                    u[i] = u[i].clamp(lower[i], upper[i]);
+                    free[i] = false;
+                    saturated = true;
+                }
+            }
+            if !saturated || !free.contains(&true) {
+                break;
+            }
+        }
+        // Limits of actuators that became effective only after they were fixed.
+        for i in 0..ACTUATORS {
+            u[i] = u[i].clamp(lower[i], upper[i]);
+        }
+        self.commands = ActuatorCommands::from_vector(&u);
+        self.commands
+    }
+
+    /// Weighted pseudo-inverse using only the `free` actuators.
+    fn pseudo_inverse(&self, free: &[bool; ACTUATORS]) -> Allocation {
+        let mut scale = SVector::<f32, ACTUATORS>::zeros();
+        for (i, free) in free.iter().enumerate() {
+            if *free {
+                let weight = if i < 4 {
+                    self.config.motor_weight
+                } else {
+                    self.config.surface_weight
+                };
+                scale[i] = 1.0 / libm::sqrtf(weight);
+            }
+        }
+        let scaled = Effectiveness::from_fn(|axis, i| self.effectiveness[(axis, i)] * scale[i]);
+        match scaled.pseudo_inverse(SINGULAR_VALUE_LIMIT) {
+            Ok(inverse) => Allocation::from_fn(|i, axis| scale[i] * inverse[(i, axis)]),
+            Err(_) => Allocation::zeros(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    fn achieved(allocator: &ControlAllocator, commands: &ActuatorCommands) -> [f32; 5] {
+        (allocator.effectiveness * commands.to_vector()).into()
+    }
+
+    fn assert_close(actual: [f32; 5], expected: [f32; 5]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 0.01,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn hover() {
+        let mut allocator = ControlAllocator::new(AllocatorConfig::default());
+        let hover = Demand {
+            up: 16.0,
+            ..Default::default()
+        };
+        let commands = allocator.update(&hover, 0.0, 0.0, DT);
+        for motor in commands.motors {
+            assert!((motor - 0.5).abs() < 1e-3);
+        }
+        assert_eq!(commands.aileron, 0.0);
+
+        // Roll right: the left motor pushes harder.
+        let roll = Demand {
+            torque: [0.4, 0.0, 0.0],
+            up: 16.0,
+            ..Default::default()
+        };
+        let commands = allocator.update(&roll, 0.0, 0.0, DT);
+        assert!(commands.motors[1] > commands.motors[0]);
+        assert!((commands.motors[2] - commands.motors[3]).abs() < 1e-4);
+        assert_close(achieved(&allocator, &commands), [0.4, 0.0, 0.0, 0.0, 16.0]);
+
+        // Yaw right: the counter-clockwise propellers push harder.
+        let yaw = Demand {
+            torque: [0.0, 0.0, 0.1],
+            up: 16.0,
+            ..Default::default()
+        };
+        let commands = allocator.update(&yaw, 0.0, 0.0, DT);
+        assert!(commands.motors[0] > commands.motors[2]);
+        assert!(commands.motors[1] > commands.motors[3]);
+        assert_close(achieved(&allocator, &commands), [0.0, 0.0, 0.1, 0.0, 16.0]);
+    }
+
+    #[test]
+    fn demand_of_motor_commands() {
+        let mut allocator = ControlAllocator::new(AllocatorConfig::default());
+        // Right motor low, front motor high: rolls right and pitches up.
+        let motors = ActuatorCommands {
+            motors: [0.4, 0.5, 0.6, 0.5],
+            ..Default::default()
+        };
+        let demand = allocator.demand(&motors, 0.0, 0.0);
+        assert!((demand.torque[0] - 0.2).abs() < 1e-4);
+        assert!((demand.torque[1] - 0.2).abs() < 1e-4);
+        assert!((demand.up - 16.0).abs() < 1e-4);
+        // Without airspeed, the motors are the only way to achieve it.
+        let commands = allocator.update(&demand, 0.0, 0.0, DT);
+        for (command, motor) in commands.motors.iter().zip(motors.motors) {
+            assert!((command - motor).abs() < 1e-4);
+        }
+
+        // With airspeed, the surfaces take over most of the torques.
+        let commands = allocator.update(&demand, 0.0, 15.0, 1.0);
+        let [roll, pitch, yaw] = demand.torque;
+        assert_close(
+            achieved(&allocator, &commands),
+            [roll, pitch, yaw, 0.0, 16.0],
+        );
+        assert!(commands.aileron > 0.0 && commands.elevator > 0.0);
+        assert!((commands.motors[0] - commands.motors[1]).abs() < 0.1);
+    }
+
+    #[test]
+    fn forward_flight_prefers_surfaces() {
+        let mut allocator = ControlAllocator::new(AllocatorConfig::default());
+        let demand = Demand {
+            torque: [0.2, 0.3, 0.1],
+            forward: 6.0,
+            ..Default::default()
+        };
+        let commands = allocator.update(&demand, 1.0, 15.0, 1.0);
+        assert_close(achieved(&allocator, &commands), [0.2, 0.3, 0.1, 6.0, 0.0]);
+        assert!(commands.elevator > 0.1);
+        assert!(commands.aileron > 0.1);
+        // The vertical rotors do not contribute to the forward force.
+        let commands = allocator.update(&demand, 0.0, 0.0, 1.0);
+        assert_eq!(achieved(&allocator, &commands)[3], 0.0);
+    }
+
+    #[test]
+    fn limits_and_rates() {
+        let mut allocator = ControlAllocator::new(AllocatorConfig::default());
+        // More than the motors can deliver, roll is kept by saturating the other motors.
+        let demand = Demand {
+            torque: [0.8, 0.0, 0.0],
+            up: 40.0,
+            ..Default::default()
+        };
+        let commands = allocator.update(&demand, 0.0, 0.0, DT);
+        for motor in commands.motors {
+            assert!((0.0..=1.0).contains(&motor));
+        }
+        assert!(achieved(&allocator, &commands)[0] > 0.7);
+
+        // The elevator moves at most 5/s.
+        let demand = Demand {
+            torque: [0.0, 1.5, 0.0],
+            forward: 4.0,
+            ..Default::default()
+        };
+        let commands = allocator.update(&demand, 1.0, 15.0, DT);
+        assert!((commands.elevator - 5.0 * DT).abs() < 1e-6);
+    }
+
+    #[test]
+    fn recomputed_when_tilting() {
+        let mut allocator = ControlAllocator::new(AllocatorConfig::default());
+        let demand = Demand {
+            up: 8.0,
+            forward: 8.0,
+            ..Default::default()
+        };
+        allocator.update(&demand, 0.0, 0.0, DT);
+        let vertical = allocator.allocation;
+        allocator.update(&demand, 0.001, 0.0, DT);
+        assert_eq!(allocator.allocation, vertical);
+        let commands = allocator.update(&demand, 0.5, 0.0, DT);
+        assert_ne!(allocator.allocation, vertical);
+        assert_close(achieved(&allocator, &commands), [0.0, 0.0, 0.0, 8.0, 8.0]);
+    }
+}
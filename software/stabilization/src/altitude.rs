@@ -0,0 +1,229 @@
+//! Vertical state estimation from barometric altitude and earth-frame vertical acceleration.
+//!
+//! Kalman filter with the state `[altitude, vertical speed, accelerometer bias]`. The vertical
+//! acceleration drives the prediction, the barometer corrects it.
+
+use nalgebra::Matrix3;
+use nalgebra::RowVector3;
+use nalgebra::Vector3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AltitudeEstimatorConfig {
+    /// Noise of the vertical acceleration [m/s^2].
+    pub accel_noise: f32,
+    /// Random walk of the accelerometer bias [m/s^3].
+    pub accel_bias_noise: f32,
+    /// Noise of the barometric altitude [m].
+    pub baro_noise: f32,
+    /// Barometer readings further than this many standard deviations from the prediction
+    /// are rejected.
+    pub innovation_gate: Option<f32>,
+    /// Prop-wash rejection: the barometer noise is scaled by `1 + thrust_noise_gain * thrust`.
+    pub thrust_noise_gain: f32,
+    /// Ground effect rejection: while in ground effect, barometer readings up to this far
+    /// below the estimate are ignored [m].
+    pub ground_effect_dead_zone: f32,
+    /// Ground effect is only considered below this height above ground [m].
+    pub ground_effect_height: f32,
+}
+
+impl Default for AltitudeEstimatorConfig {
+    fn default() -> Self {
+        Self {
+            accel_noise: 0.5,
+            accel_bias_noise: 0.01,
+            baro_noise: 0.5,
+            innovation_gate: Some(5.0),
+            thrust_noise_gain: 2.0,
+            ground_effect_dead_zone: 0.5,
+            ground_effect_height: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AltitudeEstimate {
+    pub altitude: f32,           // [m], pressure altitude
+    pub height: f32,             // [m], above the ground reference
+    pub vertical_speed: f32,     // [m/s], positive up
+    pub altitude_std: f32,       // [m]
+    pub vertical_speed_std: f32, // [m/s]
+}
+
+pub struct AltitudeEstimator {
+    config: AltitudeEstimatorConfig,
+    x: Vector3<f32>,
+    p: Matrix3<f32>,
+    initialized: bool,
+    ground_altitude: f32,
+    ground_effect: bool,
+}
+
+impl AltitudeEstimator {
+    pub fn new(config: AltitudeEstimatorConfig) -> Self {
+        Self {
+            config,
+            x: Vector3::zeros(),
+            p: Matrix3::identity(),
+            initialized: false,
+            ground_altitude: 0.0,
+            ground_effect: false,
+        }
+    }
+
+    /// Restarts the filter at the given altitude, which also becomes the ground reference.
+    pub fn reset(&mut self, altitude: f32) {
+        self.x = Vector3::new(altitude, 0.0, 0.0);
+        self.p = Matrix3::from_diagonal(&Vector3::new(
+            self.config.baro_noise * self.config.baro_noise,
+            0.1,
+            0.1,
+        ));
+        self.ground_altitude = altitude;
+        self.initialized = true;
+    }
+
+    /// Uses the current altitude estimate as ground reference (e.g., when arming).
+    pub fn set_ground_reference(&mut self) {
+        self.ground_altitude = self.x[0];
+    }
+
+    /// Marks that the vehicle might be in ground effect (e.g., during takeoff and landing).
+    pub fn set_ground_effect(&mut self, active: bool) {
+        self.ground_effect = active;
+    }
+
+    /// `vertical_acceleration` is the earth-frame acceleration without gravity [m/s^2, up].
+    pub fn predict(&mut self, vertical_acceleration: f32, dt: f32) {
+        if !self.initialized {
+            return;
+        }
+        let f = Matrix3::new(1.0, dt, -0.5 * dt * dt, 0.0, 1.0, -dt, 0.0, 0.0, 1.0);
+        let b = Vector3::new(0.5 * dt * dt, dt, 0.0);
+        self.x = f * self.x + b * vertical_acceleration;
+
+        let accel_var = self.config.accel_noise * self.config.accel_noise;
+        let bias_var = self.config.accel_bias_noise * self.config.accel_bias_noise * dt;
+        let q = b * b.transpose() * accel_var
+            + Matrix3::from_diagonal(&Vector3::new(0.0, 0.0, bias_var));
+        self.p = f * self.p * f.transpose() + q;
+    }
+
+    /// Fuses a barometric altitude [m]. `thrust` is the mean motor thrust `[0.0 .. 1.0]`,
+    /// used for prop-wash rejection. Returns whether the reading was accepted.
+    pub fn update_baro(&mut self, altitude: f32, thrust: f32) -> bool {
+        if !self.initialized {
+            self.reset(altitude);
+            return true;
+        }
+        let h = RowVector3::new(1.0, 0.0, 0.0);
+        let noise = self.config.baro_noise * (1.0 + self.config.thrust_noise_gain * thrust);
+        let s = self.p[(0, 0)] + noise * noise;
+        let mut innovation = altitude - self.x[0];
+
+        if let Some(gate) = self.config.innovation_gate
+            && innovation * innovation > gate * gate * s
+        {
+            return false;
+        }
+        if self.ground_effect
+            && self.x[0] - self.ground_altitude < self.config.ground_effect_height
+            && innovation < 0.0
+        {
+            // Downwash near the ground increases the static pressure, the barometer then
+            // reads too low.
+            innovation = (innovation + self.config.ground_effect_dead_zone).min(0.0);
+        }
+
+        let k = self.p * h.transpose() / s;
+        self.x += k * innovation;
+        self.p = (Matrix3::identity() - k * h) * self.p;
+        true
+    }
+
+    pub fn estimate(&self) -> AltitudeEstimate {
+        AltitudeEstimate {
+            altitude: self.x[0],
+            height: self.x[0] - self.ground_altitude,
+            vertical_speed: self.x[1],
+            altitude_std: libm::sqrtf(self.p[(0, 0)]),
+            vertical_speed_std: libm::sqrtf(self.p[(1, 1)]),
+        }
+    }
+
+    pub fn initialized(&self) -> bool {
+        self.initialized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    fn run(estimator: &mut AltitudeEstimator, seconds: f32, mut f: impl FnMut(f32) -> (f32, f32)) {
+        let steps = (seconds / DT) as u32;
+        for i in 0..steps {
+            let (accel, baro) = f(i as f32 * DT);
+            estimator.predict(accel, DT);
+            estimator.update_baro(baro, 0.0);
+        }
+    }
+
+    #[test]
+    fn follows_climb() {
+        let mut estimator = AltitudeEstimator::new(AltitudeEstimatorConfig::default());
+        estimator.update_baro(100.0, 0.0);
+        run(&mut estimator, 20.0, |t| (0.0, 100.0 + t));
+        let estimate = estimator.estimate();
+        assert!((estimate.vertical_speed - 1.0).abs() < 0.05);
+        assert!((estimate.height - 20.0).abs() < 0.2);
+    }
+
+    #[test]
+    fn estimates_accel_bias() {
+        let mut estimator = AltitudeEstimator::new(AltitudeEstimatorConfig::default());
+        estimator.update_baro(0.0, 0.0);
+        run(&mut estimator, 60.0, |_| (0.3, 0.0));
+        let estimate = estimator.estimate();
+        assert!(estimate.vertical_speed.abs() < 0.05);
+        assert!(estimate.altitude.abs() < 0.1);
+        assert!((estimator.x[2] - 0.3).abs() < 0.05);
+    }
+
+    #[test]
+    fn rejects_outliers() {
+        let mut estimator = AltitudeEstimator::new(AltitudeEstimatorConfig::default());
+        estimator.update_baro(0.0, 0.0);
+        run(&mut estimator, 5.0, |_| (0.0, 0.0));
+        assert!(!estimator.update_baro(50.0, 0.0));
+        assert!(estimator.estimate().altitude.abs() < 0.01);
+    }
+
+    #[test]
+    fn ground_effect_dead_zone() {
+        let mut estimator = AltitudeEstimator::new(AltitudeEstimatorConfig::default());
+        estimator.update_baro(0.0, 0.0);
+        estimator.set_ground_effect(true);
+        run(&mut estimator, 5.0, |_| (0.0, -0.4));
+        assert!(estimator.estimate().altitude.abs() < 0.01);
+
+        estimator.set_ground_effect(false);
+        run(&mut estimator, 5.0, |_| (0.0, -0.4));
+        assert!((estimator.estimate().altitude + 0.4).abs() < 0.05);
+    }
+
+    #[test]
+    fn thrust_reduces_baro_trust() {
+        let mut idle = AltitudeEstimator::new(AltitudeEstimatorConfig::default());
+        let mut full = AltitudeEstimator::new(AltitudeEstimatorConfig::default());
+        for estimator in [&mut idle, &mut full] {
+            estimator.update_baro(0.0, 0.0);
+            estimator.predict(0.0, DT);
+        }
+        idle.update_baro(1.0, 0.0);
+        full.update_baro(1.0, 1.0);
+        assert!(full.estimate().altitude < idle.estimate().altitude);
+    }
+}
@@ -0,0 +1,380 @@
+//! Collective thrust control. In altitude hold, the throttle stick commands a climb rate and
+//! an altitude/vertical speed cascade holds the height.
+
+use crate::altitude::AltitudeEstimate;
+
+/// Above this height, the vehicle is considered airborne for learning the hover thrust [m].
+const AIRBORNE_HEIGHT: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleMode {
+    Manual,
+    AltitudeHold,
+    /// Like `AltitudeHold`, but the climb rate is commanded by `set_climb_rate` instead of the
+    /// stick.
+    Auto,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AltitudeHoldConfig {
+    /// Stick deadband around the centre, in which the altitude is held `[0.0 .. 0.5]`.
+    pub deadband: f32,
+    pub max_climb_rate: f32,   // [m/s]
+    pub max_descent_rate: f32, // [m/s]
+    /// Limits how fast the climb rate setpoint changes [m/s^2].
+    pub max_vertical_acceleration: f32,
+    /// Climb rate per meter of altitude error [1/s].
+    pub altitude_gain: f32,
+    /// Thrust per m/s of vertical speed error.
+    pub velocity_p: f32,
+    /// Thrust per meter of integrated vertical speed error.
+    pub velocity_i: f32,
+    pub min_thrust: f32,
+    pub max_thrust: f32,
+    /// Initial guess of the hover thrust, refined while flying.
+    pub hover_thrust: f32,
+    /// Rate at which the hover thrust estimate follows the actual thrust [1/s].
+    pub hover_learning_rate: f32,
+    /// Time to fade from the last altitude hold thrust to the stick when switching to manual [s].
+    pub handover_time: f32,
+}
+
+impl Default for AltitudeHoldConfig {
+    fn default() -> Self {
+        Self {
+            deadband: 0.1,
+            max_climb_rate: 2.0,
+            max_descent_rate: 1.0,
+            max_vertical_acceleration: 2.0,
+            altitude_gain: 1.0,
+            velocity_p: 0.15,
+            velocity_i: 0.05,
+            min_thrust: 0.1,
+            max_thrust: 0.9,
+            hover_thrust: 0.5,
+            hover_learning_rate: 0.1,
+            handover_time: 1.0,
+        }
+    }
+}
+
+pub struct ThrottleController {
+    config: AltitudeHoldConfig,
+    mode: ThrottleMode,
+    hover_thrust: f32,
+    integrator: f32,
+    target_altitude: f32,
+    velocity_setpoint: f32,
+    climb_rate_command: f32,
+    thrust: f32,
+    handover_offset: f32,
+    handover_remaining: f32,
+}
+
+impl ThrottleController {
+    pub fn new(config: AltitudeHoldConfig) -> Self {
+        Self {
+            config,
+            mode: ThrottleMode::Manual,
+            hover_thrust: config.hover_thrust,
+            integrator: 0.0,
+            target_altitude: 0.0,
+            velocity_setpoint: 0.0,
+            climb_rate_command: 0.0,
+            thrust: 0.0,
+            handover_offset: 0.0,
+            handover_remaining: 0.0,
+        }
+    }
+
+    /// Active mode, falls back to `Manual` if there is no altitude estimate.
+    pub fn mode(&self) -> ThrottleMode {
+        self.mode
+    }
+
+    pub fn hover_thrust(&self) -> f32 {
+        self.hover_thrust
+    }
+
+    pub fn target_altitude(&self) -> Option<f32> {
+        match self.mode {
+            ThrottleMode::AltitudeHold | ThrottleMode::Auto => Some(self.target_altitude),
+            ThrottleMode::Manual => None,
+        }
+    }
+
+    /// Starts over in `Manual`, e.g. at arming. The learned hover thrust is kept.
+    pub fn reset(&mut self) {
+        *self = Self {
+            hover_thrust: self.hover_thrust,
+            ..Self::new(self.config)
+        };
+    }
+
+    /// Climb rate [m/s] in `Auto`, zero holds the altitude.
+    pub fn set_climb_rate(&mut self, climb_rate: f32) {
+        self.climb_rate_command =
+            climb_rate.clamp(-self.config.max_descent_rate, self.config.max_climb_rate);
+    }
+
+    /// Returns the collective thrust `[0.0 .. 1.0]` for the throttle `stick` `[0.0 .. 1.0]`.
+    pub fn update(
+        &mut self,
+        mode: ThrottleMode,
+        stick: f32,
+        estimate: Option<&AltitudeEstimate>,
+        dt: f32,
+    ) -> f32 {
+        let mode = match estimate {
+            Some(_) => mode,
+            None => ThrottleMode::Manual,
+        };
+        if mode != self.mode {
+            match (mode, estimate) {
+                (ThrottleMode::AltitudeHold | ThrottleMode::Auto, Some(estimate)) => {
+                    self.engage(estimate)
+                }
+                _ => {
+                    self.handover_offset = self.thrust - stick;
+                    self.handover_remaining = self.config.handover_time;
+                }
+            }
+            self.mode = mode;
+        }
+
+        self.thrust = match (self.mode, estimate) {
+            (ThrottleMode::AltitudeHold | ThrottleMode::Auto, Some(estimate)) => {
+                self.hold(stick, estimate, dt)
+            }
+            _ => self.manual(stick, estimate, dt),
+        };
+        self.thrust
+    }
+
+    /// Takes over the current altitude and thrust, so that engaging does not cause a jump.
+    fn engage(&mut self, estimate: &AltitudeEstimate) {
+        self.target_altitude = estimate.altitude;
+        self.velocity_setpoint = estimate.vertical_speed;
+        self.integrator = self.thrust - self.hover_thrust;
+        self.handover_remaining = 0.0;
+    }
+
+    fn manual(&mut self, stick: f32, estimate: Option<&AltitudeEstimate>, dt: f32) -> f32 {
+        let mut thrust = stick;
+        if self.handover_remaining > 0.0 {
+            self.handover_remaining = (self.handover_remaining - dt).max(0.0);
+            thrust += self.handover_offset * self.handover_remaining / self.config.handover_time;
+        }
+        // Learn the hover thrust while the pilot is hovering manually.
+        if let Some(estimate) = estimate
+            && estimate.height > AIRBORNE_HEIGHT
+            && libm::fabsf(estimate.vertical_speed) < 0.2
+        {
+            self.learn(thrust - self.hover_thrust, dt);
+        }
+        thrust
+    }
+
+    fn hold(&mut self, stick: f32, estimate: &AltitudeEstimate, dt: f32) -> f32 {
+        let config = &self.config;
+        let climb_rate = match self.mode {
+            ThrottleMode::Auto => self.climb_rate_command,
+            _ => self.climb_rate(stick),
+        };
+        let desired = if climb_rate == 0.0 {
+            (config.altitude_gain * (self.target_altitude - estimate.altitude))
+                .clamp(-config.max_descent_rate, config.max_climb_rate)
+        } else {
+            self.target_altitude = estimate.altitude;
+            climb_rate
+        };
+        let max_step = config.max_vertical_acceleration * dt;
+        self.velocity_setpoint += (desired - self.velocity_setpoint).clamp(-max_step, max_step);
+
+        let error = self.velocity_setpoint - estimate.vertical_speed;
+        let unclamped = self.hover_thrust + config.velocity_p * error + self.integrator;
+        let thrust = unclamped.clamp(config.min_thrust, config.max_thrust);
+        // Anti-windup: only integrate if this does not drive the output further into saturation.
+        if unclamped == thrust
+            || (unclamped > config.max_thrust && error < 0.0)
+            || (unclamped < config.min_thrust && error > 0.0)
+        {
+            self.integrator += config.velocity_i * error * dt;
+        }
+
+        // Slowly move the steady-state part of the integrator into the hover thrust, only in the
+        // air, on the ground the thrust says nothing about it.
+        if estimate.height > AIRBORNE_HEIGHT {
+            self.integrator -= self.learn(self.integrator, dt);
+        }
+
+        thrust
+    }
+
+    /// Moves the hover thrust towards `error` above it, returns the change.
+    fn learn(&mut self, error: f32, dt: f32) -> f32 {
+        let hover_thrust = (self.hover_thrust + error * self.config.hover_learning_rate * dt)
+            .clamp(self.config.min_thrust, self.config.max_thrust);
+        let change = hover_thrust - self.hover_thrust;
+        self.hover_thrust = hover_thrust;
+        change
+    }
+
+    /// Maps the stick to a climb rate [m/s], zero within the centre deadband.
+    fn climb_rate(&self, stick: f32) -> f32 {
+        let deflection = stick.clamp(0.0, 1.0) - 0.5;
+        let range = 0.5 - self.config.deadband;
+        if deflection > self.config.deadband {
+            (deflection - self.config.deadband) / range * self.config.max_climb_rate
+        } else if deflection < -self.config.deadband {
+            (deflection + self.config.deadband) / range * self.config.max_descent_rate
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::STANDARD_GRAVITY;
+
+    const DT: f32 = 0.02;
+
+    /// Vertical acceleration of a vehicle that hovers at `hover_thrust`.
+    fn simulated_vertical_acceleration(thrust: f32, hover_thrust: f32) -> f32 {
+        (thrust / hover_thrust - 1.0) * STANDARD_GRAVITY
+    }
+
+    fn estimate(altitude: f32, vertical_speed: f32) -> AltitudeEstimate {
+        AltitudeEstimate {
+            altitude,
+            height: altitude,
+            vertical_speed,
+            altitude_std: 0.1,
+            vertical_speed_std: 0.1,
+        }
+    }
+
+    #[test]
+    fn stick_deadband() {
+        let controller = ThrottleController::new(AltitudeHoldConfig::default());
+        assert_eq!(controller.climb_rate(0.5), 0.0);
+        assert_eq!(controller.climb_rate(0.59), 0.0);
+        assert_eq!(controller.climb_rate(1.0), 2.0);
+        assert_eq!(controller.climb_rate(0.0), -1.0);
+        assert!((controller.climb_rate(0.8) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn engage_without_jump() {
+        let mut controller = ThrottleController::new(AltitudeHoldConfig::default());
+        let e = estimate(10.0, 0.0);
+        controller.update(ThrottleMode::Manual, 0.62, Some(&e), DT);
+        let thrust = controller.update(ThrottleMode::AltitudeHold, 0.5, Some(&e), DT);
+        assert!((thrust - 0.62).abs() < 1e-3);
+        assert_eq!(controller.target_altitude(), Some(10.0));
+    }
+
+    #[test]
+    fn falls_back_to_manual_without_estimate() {
+        let mut controller = ThrottleController::new(AltitudeHoldConfig::default());
+        let thrust = controller.update(ThrottleMode::AltitudeHold, 0.3, None, DT);
+        assert_eq!(thrust, 0.3);
+        assert_eq!(controller.mode(), ThrottleMode::Manual);
+    }
+
+    #[test]
+    fn holds_altitude_and_learns_hover_thrust() {
+        let hover = 0.42;
+        let mut controller = ThrottleController::new(AltitudeHoldConfig::default());
+        let (mut altitude, mut speed) = (5.0, 0.0);
+        for _ in 0..(60.0 / DT) as u32 {
+            let e = estimate(altitude, speed);
+            let thrust = controller.update(ThrottleMode::AltitudeHold, 0.5, Some(&e), DT);
+            speed += simulated_vertical_acceleration(thrust, hover) * DT;
+            altitude += speed * DT;
+        }
+        assert!((altitude - 5.0).abs() < 0.1);
+        assert!((controller.hover_thrust() - hover).abs() < 0.02);
+    }
+
+    #[test]
+    fn keeps_hover_thrust_on_the_ground() {
+        let config = AltitudeHoldConfig::default();
+        let mut controller = ThrottleController::new(config);
+        let ground = estimate(0.0, 0.0);
+        controller.update(ThrottleMode::Manual, 0.0, Some(&ground), DT);
+        for _ in 0..(60.0 / DT) as u32 {
+            let thrust = controller.update(ThrottleMode::AltitudeHold, 0.0, Some(&ground), DT);
+            assert_eq!(thrust, config.min_thrust);
+        }
+        assert_eq!(controller.hover_thrust(), config.hover_thrust);
+
+        // At arming, altitude hold engages again, from the thrust of the manual mode.
+        controller.reset();
+        assert_eq!(controller.mode(), ThrottleMode::Manual);
+        assert_eq!(controller.hover_thrust(), config.hover_thrust);
+        controller.update(ThrottleMode::Manual, 0.5, Some(&ground), DT);
+        let thrust = controller.update(ThrottleMode::AltitudeHold, 0.5, Some(&ground), DT);
+        assert!((thrust - 0.5).abs() < 1e-3);
+    }
+
+    #[test]
+    fn climbs_with_stick() {
+        let hover = 0.5;
+        let mut controller = ThrottleController::new(AltitudeHoldConfig::default());
+        let (mut altitude, mut speed) = (5.0, 0.0);
+        for _ in 0..(10.0 / DT) as u32 {
+            let e = estimate(altitude, speed);
+            let thrust = controller.update(ThrottleMode::AltitudeHold, 1.0, Some(&e), DT);
+            speed += simulated_vertical_acceleration(thrust, hover) * DT;
+            altitude += speed * DT;
+        }
+        assert!((speed - 2.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn auto_ignores_stick() {
+        let hover = 0.5;
+        let mut controller = ThrottleController::new(AltitudeHoldConfig::default());
+        controller.update(ThrottleMode::Manual, hover, Some(&estimate(20.0, 0.0)), DT);
+        controller.set_climb_rate(-5.0);
+        let (mut altitude, mut speed) = (20.0, 0.0);
+        for _ in 0..(10.0 / DT) as u32 {
+            let e = estimate(altitude, speed);
+            let thrust = controller.update(ThrottleMode::Auto, 1.0, Some(&e), DT);
+            speed += simulated_vertical_acceleration(thrust, hover) * DT;
+            altitude += speed * DT;
+        }
+        // Limited to the maximum descent rate.
+        assert!((speed + 1.0).abs() < 0.1);
+
+        controller.set_climb_rate(0.0);
+        for _ in 0..(10.0 / DT) as u32 {
+            let e = estimate(altitude, speed);
+            let thrust = controller.update(ThrottleMode::Auto, 0.0, Some(&e), DT);
+            speed += simulated_vertical_acceleration(thrust, hover) * DT;
+            altitude += speed * DT;
+        }
+        assert!(speed.abs() < 0.05);
+        assert!((controller.target_altitude().unwrap() - altitude).abs() < 0.1);
+    }
+
+    #[test]
+    fn smooth_handover_to_manual() {
+        let config = AltitudeHoldConfig::default();
+        let mut controller = ThrottleController::new(config);
+        let e = estimate(10.0, 0.0);
+        controller.update(ThrottleMode::Manual, 0.5, Some(&e), DT);
+        controller.update(ThrottleMode::AltitudeHold, 0.5, Some(&e), DT);
+        let held = controller.update(ThrottleMode::AltitudeHold, 0.5, Some(&e), DT);
+        let first = controller.update(ThrottleMode::Manual, 0.2, Some(&e), DT);
+        assert!((first - held).abs() < 0.02);
+        let mut thrust = first;
+        for _ in 0..(config.handover_time / DT) as u32 {
+            thrust = controller.update(ThrottleMode::Manual, 0.2, Some(&e), DT);
+        }
+        assert_eq!(thrust, 0.2);
+    }
+}
@@ -0,0 +1,293 @@
+//! Attitude estimation from the gyroscope, the accelerometer and optionally the magnetometer.
+//!
+//! Inputs are in the units of the IMU driver (gyro in dps, acceleration in g). The earth frame is
+//! north-west-up, the quaternion rotates body-frame vectors into the earth frame.
+
+use fusion_ahrs::Ahrs;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+
+use crate::STANDARD_GRAVITY;
+use crate::eskf::Eskf;
+use crate::eskf::EskfConfig;
+use crate::madgwick::Madgwick;
+use crate::madgwick::MadgwickConfig;
+use crate::mahony::Mahony;
+use crate::mahony::MahonyConfig;
+
+/// Time after start or reset until the estimate is considered converged [s].
+pub const CONVERGENCE_TIME: f32 = 3.0;
+/// The accelerometer is only used as gravity reference if its magnitude is within this distance
+/// of 1 g.
+const ACCELERATION_REJECTION: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttitudeBackend {
+    Fusion,
+    Mahony,
+    Madgwick,
+    /// Error-state Kalman filter.
+    Eskf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttitudeEstimate {
+    pub quaternion: UnitQuaternion<f32>,
+    /// Roll, pitch and yaw [rad].
+    pub euler: [f32; 3],
+    /// Body rates with the estimated gyro bias removed [dps].
+    pub rates: [f32; 3],
+    /// Earth-frame acceleration without gravity [m/s^2].
+    pub earth_acceleration: [f32; 3],
+    /// The initial convergence after start or reset is complete.
+    pub converged: bool,
+    /// The accelerometer was not used as gravity reference, because the vehicle accelerates.
+    pub accelerating: bool,
+    /// The magnetometer was used as heading reference.
+    pub magnetometer: bool,
+}
+
+impl Default for AttitudeEstimate {
+    fn default() -> Self {
+        Self {
+            quaternion: UnitQuaternion::identity(),
+            euler: [0.0; 3],
+            rates: [0.0; 3],
+            earth_acceleration: [0.0; 3],
+            converged: false,
+            accelerating: false,
+            magnetometer: false,
+        }
+    }
+}
+
+impl AttitudeEstimate {
+    pub(crate) fn new(
+        quaternion: UnitQuaternion<f32>,
+        rates: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        elapsed: f32,
+    ) -> Self {
+        let (roll, pitch, yaw) = quaternion.euler_angles();
+        let earth = quaternion * Vector3::from(accel) - Vector3::z();
+        Self {
+            quaternion,
+            euler: [roll, pitch, yaw],
+            rates,
+            earth_acceleration: (earth * STANDARD_GRAVITY).into(),
+            converged: elapsed >= CONVERGENCE_TIME,
+            accelerating: accelerating(accel),
+            magnetometer: mag.is_some(),
+        }
+    }
+}
+
+pub trait AttitudeEstimator {
+    /// `mag` is the calibrated magnetometer reading, `None` if there is no magnetometer or it
+    /// should not be trusted.
+    fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        dt: f32,
+    ) -> AttitudeEstimate;
+
+    /// Restarts the estimation from level attitude.
+    fn reset(&mut self);
+}
+
+/// Whether the acceleration deviates too far from gravity to serve as reference.
+pub(crate) fn accelerating(accel: [f32; 3]) -> bool {
+    (Vector3::from(accel).norm() - 1.0).abs() > ACCELERATION_REJECTION
+}
+
+/// Sum of the rotations from the measured to the estimated direction of gravity and of the
+/// horizontal magnetic field, as body-frame rotation vector. Rotating by it moves the estimate
+/// towards the measurements. The magnetometer only corrects the heading.
+pub(crate) fn reference_error(
+    quaternion: &UnitQuaternion<f32>,
+    accel: [f32; 3],
+    mag: Option<[f32; 3]>,
+) -> Vector3<f32> {
+    let mut error = Vector3::zeros();
+    if !accelerating(accel)
+        && let Some(measured) = Vector3::from(accel).try_normalize(f32::EPSILON)
+    {
+        let estimated = quaternion.inverse_transform_vector(&Vector3::z());
+        error += measured.cross(&estimated);
+    }
+    if let Some(mag) = mag
+        && let Some(measured) = Vector3::from(mag).try_normalize(f32::EPSILON)
+    {
+        // Reference field pointing north with the measured inclination.
+        let earth = quaternion.transform_vector(&measured);
+        let reference = Vector3::new(libm::hypotf(earth.x, earth.y), 0.0, earth.z);
+        let estimated = quaternion.inverse_transform_vector(&reference);
+        let up = quaternion.inverse_transform_vector(&Vector3::z());
+        error += up * up.dot(&measured.cross(&estimated));
+    }
+    error
+}
+
+/// Wrapper around the Fusion AHRS library.
+pub struct FusionEstimator {
+    ahrs: Ahrs,
+    elapsed: f32,
+}
+
+impl FusionEstimator {
+    pub fn new() -> Self {
+        Self {
+            ahrs: Ahrs::new(),
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl Default for FusionEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AttitudeEstimator for FusionEstimator {
+    fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        dt: f32,
+    ) -> AttitudeEstimate {
+        match mag {
+            Some(mag) => self.ahrs.update(
+                Vector3::from(gyro),
+                Vector3::from(accel),
+                Vector3::from(mag),
+                dt,
+            ),
+            None => self
+                .ahrs
+                .update_no_magnetometer(Vector3::from(gyro), Vector3::from(accel), dt),
+        }
+        self.elapsed += dt;
+        AttitudeEstimate::new(self.ahrs.quaternion(), gyro, accel, mag, self.elapsed)
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+/// Estimator with a backend selectable at runtime.
+pub enum Estimator {
+    Fusion(FusionEstimator),
+    Mahony(Mahony),
+    Madgwick(Madgwick),
+    Eskf(Eskf),
+}
+
+impl Estimator {
+    pub fn new(backend: AttitudeBackend) -> Self {
+        match backend {
+            AttitudeBackend::Fusion => Self::Fusion(FusionEstimator::new()),
+            AttitudeBackend::Mahony => Self::Mahony(Mahony::new(MahonyConfig::default())),
+            AttitudeBackend::Madgwick => Self::Madgwick(Madgwick::new(MadgwickConfig::default())),
+            AttitudeBackend::Eskf => Self::Eskf(Eskf::new(EskfConfig::default())),
+        }
+    }
+
+    pub fn backend(&self) -> AttitudeBackend {
+        match self {
+            Self::Fusion(_) => AttitudeBackend::Fusion,
+            Self::Mahony(_) => AttitudeBackend::Mahony,
+            Self::Madgwick(_) => AttitudeBackend::Madgwick,
+            Self::Eskf(_) => AttitudeBackend::Eskf,
+        }
+    }
+}
+
+impl AttitudeEstimator for Estimator {
+    fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        dt: f32,
+    ) -> AttitudeEstimate {
+        match self {
+            Self::Fusion(estimator) => estimator.update(gyro, accel, mag, dt),
+            Self::Mahony(estimator) => estimator.update(gyro, accel, mag, dt),
+            Self::Madgwick(estimator) => estimator.update(gyro, accel, mag, dt),
+            Self::Eskf(estimator) => estimator.update(gyro, accel, mag, dt),
+        }
+    }
+
+    fn reset(&mut self) {
+        match self {
+            Self::Fusion(estimator) => estimator.reset(),
+            Self::Mahony(estimator) => estimator.reset(),
+            Self::Madgwick(estimator) => estimator.reset(),
+            Self::Eskf(estimator) => estimator.reset(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub const DT: f32 = 0.005;
+
+    /// Earth field with 60 degrees inclination.
+    pub const EARTH_FIELD: [f32; 3] = [0.5, 0.0, -0.866];
+
+    /// Ideal sensor readings for a vehicle at rest with the given attitude.
+    pub fn readings(attitude: &UnitQuaternion<f32>) -> ([f32; 3], [f32; 3]) {
+        let accel = attitude.inverse_transform_vector(&Vector3::z());
+        let mag = attitude.inverse_transform_vector(&Vector3::from(EARTH_FIELD));
+        (accel.into(), mag.into())
+    }
+
+    pub fn run(
+        estimator: &mut impl AttitudeEstimator,
+        gyro: [f32; 3],
+        attitude: &UnitQuaternion<f32>,
+        seconds: f32,
+    ) -> AttitudeEstimate {
+        let (accel, mag) = readings(attitude);
+        let mut estimate = AttitudeEstimate::default();
+        for _ in 0..(seconds / DT) as u32 {
+            estimate = estimator.update(gyro, accel, Some(mag), DT);
+        }
+        estimate
+    }
+
+    #[test]
+    fn estimate() {
+        let attitude = UnitQuaternion::from_euler_angles(0.0, 0.0, 1.0);
+        let estimate = AttitudeEstimate::new(attitude, [1.0, 2.0, 3.0], [0.0, 0.0, 1.2], None, 5.0);
+        assert!((estimate.euler[2] - 1.0).abs() < 1e-6);
+        assert!((estimate.earth_acceleration[2] - 0.2 * STANDARD_GRAVITY).abs() < 1e-4);
+        assert!(estimate.converged);
+        assert!(estimate.accelerating);
+        assert!(!estimate.magnetometer);
+    }
+
+    #[test]
+    fn reference_error_direction() {
+        // Level estimate of a vehicle rolled right: the correction rolls right as well.
+        let attitude = UnitQuaternion::from_euler_angles(0.3, 0.0, 0.0);
+        let (accel, _) = readings(&attitude);
+        let error = reference_error(&UnitQuaternion::identity(), accel, None);
+        assert!(error.x > 0.0 && error.y.abs() < 1e-6);
+
+        // The magnetometer only corrects the yaw.
+        let attitude = UnitQuaternion::from_euler_angles(0.0, 0.0, 0.3);
+        let (accel, mag) = readings(&attitude);
+        let error = reference_error(&UnitQuaternion::identity(), accel, Some(mag));
+        assert!(error.z > 0.0);
+        assert!(error.x.abs() < 1e-6 && error.y.abs() < 1e-6);
+    }
+}
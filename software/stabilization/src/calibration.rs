@@ -0,0 +1,448 @@
+//! IMU calibration: gyro bias, accelerometer offset/scale and gyro temperature drift.
+//!
+//! All values are in the units of the IMU driver (gyro in dps, acceleration in g).
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// The vehicle moved while samples were collected.
+    Motion,
+    /// The measured gravity vector does not match the requested orientation.
+    WrongOrientation,
+    /// Not all required samples or orientations have been collected yet.
+    Incomplete,
+    /// The temperature did not change enough to fit a drift model.
+    TemperatureRange,
+    /// The IMU does not report its temperature, so no drift model can be fitted.
+    NoTemperature,
+    /// The samples do not describe a valid model (e.g., not rotated in all directions).
+    Fit,
+}
+
+/// Linear model of the gyro bias over temperature.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GyroTemperatureModel {
+    /// Bias change per degree celsius.
+    pub slope: [f32; 3],
+    /// Temperature at which `ImuCalibration::gyro_bias` was measured.
+    pub reference_temperature: f32,
+}
+
+/// Calibration coefficients, stored persistently by the firmware.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ImuCalibration {
+    pub gyro_bias: [f32; 3],
+    pub gyro_temperature: Option<GyroTemperatureModel>,
+    pub accel_offset: [f32; 3],
+    pub accel_scale: [f32; 3],
+}
+
+impl Default for ImuCalibration {
+    fn default() -> Self {
+        Self {
+            gyro_bias: [0.0; 3],
+            gyro_temperature: None,
+            accel_offset: [0.0; 3],
+            accel_scale: [1.0; 3],
+        }
+    }
+}
+
+impl ImuCalibration {
+    /// Returns corrected `(gyro, accel)` readings.
+    pub fn apply(
+        &self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        temperature: Option<f32>,
+    ) -> ([f32; 3], [f32; 3]) {
+        let bias = self.gyro_bias_at(temperature);
+        let mut gyro_out = [0.0; 3];
+        let mut accel_out = [0.0; 3];
+        for i in 0..3 {
+            gyro_out[i] = gyro[i] - bias[i];
+            accel_out[i] = (accel[i] - self.accel_offset[i]) * self.accel_scale[i];
+        }
+        (gyro_out, accel_out)
+    }
+
+    /// Gyro bias at the given temperature (the plain bias if there is no drift model).
+    pub fn gyro_bias_at(&self, temperature: Option<f32>) -> [f32; 3] {
+        match (self.gyro_temperature, temperature) {
+            (Some(model), Some(temperature)) => {
+                let delta = temperature - model.reference_temperature;
+                [
+                    self.gyro_bias[0] + model.slope[0] * delta,
+                    self.gyro_bias[1] + model.slope[1] * delta,
+                    self.gyro_bias[2] + model.slope[2] * delta,
+                ]
+            }
+            _ => self.gyro_bias,
+        }
+    }
+}
+
+/// Running mean and variance of a 3-axis signal (Welford's algorithm).
+#[derive(Debug, Clone, Copy, Default)]
+struct Statistics {
+    count: u32,
+    mean: [f32; 3],
+    m2: [f32; 3],
+}
+
+impl Statistics {
+    fn add(&mut self, sample: [f32; 3]) {
+        self.count += 1;
+        for (i, value) in sample.iter().enumerate() {
+            let delta = value - self.mean[i];
+            self.mean[i] += delta / self.count as f32;
+            self.m2[i] += delta * (value - self.mean[i]);
+        }
+    }
+
+    fn max_std_dev(&self) -> f32 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        let max_m2 = self.m2.iter().fold(0.0f32, |acc, v| acc.max(*v));
+        libm::sqrtf(max_m2 / (self.count - 1) as f32)
+    }
+}
+
+/// Thresholds that decide whether the vehicle is considered stationary.
+#[derive(Debug, Clone, Copy)]
+pub struct MotionThresholds {
+    /// Maximum standard deviation of any gyro axis.
+    pub gyro_std_dev: f32,
+    /// Maximum standard deviation of any accelerometer axis.
+    pub accel_std_dev: f32,
+}
+
+impl Default for MotionThresholds {
+    fn default() -> Self {
+        Self {
+            gyro_std_dev: 0.5,
+            accel_std_dev: 0.02,
+        }
+    }
+}
+
+/// Estimates the gyro bias while the vehicle is stationary (e.g., at boot).
+pub struct GyroCalibrator {
+    samples: u32,
+    thresholds: MotionThresholds,
+    gyro: Statistics,
+    accel: Statistics,
+    temperature_sum: f32,
+    temperature_count: u32,
+}
+
+impl GyroCalibrator {
+    pub fn new(samples: u32, thresholds: MotionThresholds) -> Self {
+        Self {
+            samples,
+            thresholds,
+            gyro: Statistics::default(),
+            accel: Statistics::default(),
+            temperature_sum: 0.0,
+            temperature_count: 0,
+        }
+    }
+
+    /// Adds a raw sample, returns true once enough samples have been collected.
+    pub fn add(&mut self, gyro: [f32; 3], accel: [f32; 3], temperature: Option<f32>) -> bool {
+        self.gyro.add(gyro);
+        self.accel.add(accel);
+        if let Some(temperature) = temperature {
+            self.temperature_sum += temperature;
+            self.temperature_count += 1;
+        }
+        self.gyro.count >= self.samples
+    }
+
+    /// Writes the measured bias into `calibration`, refusing if motion was detected.
+    pub fn finish(&self, calibration: &mut ImuCalibration) -> Result<(), CalibrationError> {
+        if self.gyro.count < self.samples {
+            return Err(CalibrationError::Incomplete);
+        }
+        if self.gyro.max_std_dev() > self.thresholds.gyro_std_dev
+            || self.accel.max_std_dev() > self.thresholds.accel_std_dev
+        {
+            return Err(CalibrationError::Motion);
+        }
+        calibration.gyro_bias = self.gyro.mean;
+        if let Some(model) = calibration.gyro_temperature.as_mut()
+            && self.temperature_count > 0
+        {
+            model.reference_temperature = self.temperature_sum / self.temperature_count as f32;
+        }
+        Ok(())
+    }
+}
+
+/// The six orientations of the accelerometer calibration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelOrientation {
+    Level,
+    UpsideDown,
+    NoseUp,
+    NoseDown,
+    LeftSideDown,
+    RightSideDown,
+}
+
+impl AccelOrientation {
+    /// Axis that is aligned with gravity and the expected sign of the reading.
+    fn axis(self) -> (usize, f32) {
+        match self {
+            AccelOrientation::Level => (2, 1.0),
+            AccelOrientation::UpsideDown => (2, -1.0),
+            AccelOrientation::NoseUp => (0, 1.0),
+            AccelOrientation::NoseDown => (0, -1.0),
+            AccelOrientation::LeftSideDown => (1, -1.0),
+            AccelOrientation::RightSideDown => (1, 1.0),
+        }
+    }
+
+    fn index(self) -> usize {
+        let (axis, sign) = self.axis();
+        axis * 2 + if sign > 0.0 { 0 } else { 1 }
+    }
+}
+
+/// Six-position accelerometer offset and scale calibration.
+///
+/// The vehicle is placed on each of its six sides in turn, one `AccelOrientation` at a time.
+/// For each axis, the offset is the midpoint and the scale maps the two readings to +/- 1 g.
+pub struct AccelCalibrator {
+    samples: u32,
+    thresholds: MotionThresholds,
+    current: Statistics,
+    means: [Option<f32>; 6],
+}
+
+impl AccelCalibrator {
+    pub fn new(samples: u32, thresholds: MotionThresholds) -> Self {
+        Self {
+            samples,
+            thresholds,
+            current: Statistics::default(),
+            means: [None; 6],
+        }
+    }
+
+    /// Adds a raw sample for the current orientation, returns true once it is complete.
+    pub fn add(&mut self, accel: [f32; 3]) -> bool {
+        self.current.add(accel);
+        self.current.count >= self.samples
+    }
+
+    /// Stores the collected samples as measurement for `orientation`.
+    pub fn finish_orientation(
+        &mut self,
+        orientation: AccelOrientation,
+    ) -> Result<(), CalibrationError> {
+        let stats = core::mem::take(&mut self.current);
+        if stats.count < self.samples {
+            return Err(CalibrationError::Incomplete);
+        }
+        if stats.max_std_dev() > self.thresholds.accel_std_dev {
+            return Err(CalibrationError::Motion);
+        }
+        let (axis, sign) = orientation.axis();
+        let dominant = (0..3)
+            .max_by(|a, b| libm::fabsf(stats.mean[*a]).total_cmp(&libm::fabsf(stats.mean[*b])))
+            .unwrap();
+        if dominant != axis || stats.mean[axis] * sign < 0.5 {
+            return Err(CalibrationError::WrongOrientation);
+        }
+        self.means[orientation.index()] = Some(stats.mean[axis]);
+        Ok(())
+    }
+
+    /// Computes offset and scale once all six orientations are measured.
+    pub fn finish(&self, calibration: &mut ImuCalibration) -> Result<(), CalibrationError> {
+        let mut offset = [0.0; 3];
+        let mut scale = [1.0; 3];
+        for axis in 0..3 {
+            let (Some(positive), Some(negative)) = (self.means[axis * 2], self.means[axis * 2 + 1])
+            else {
+                return Err(CalibrationError::Incomplete);
+            };
+            offset[axis] = (positive + negative) / 2.0;
+            scale[axis] = 2.0 / (positive - negative);
+        }
+        calibration.accel_offset = offset;
+        calibration.accel_scale = scale;
+        Ok(())
+    }
+}
+
+/// Fits a linear gyro drift model from stationary samples over a temperature range.
+#[derive(Default)]
+pub struct GyroTemperatureCalibrator {
+    count: u32,
+    sum_t: f32,
+    sum_tt: f32,
+    sum_g: [f32; 3],
+    sum_tg: [f32; 3],
+    min_t: f32,
+    max_t: f32,
+}
+
+impl GyroTemperatureCalibrator {
+    /// Minimum temperature span (in degree celsius) required for a fit.
+    pub const MIN_TEMPERATURE_RANGE: f32 = 5.0;
+
+    pub fn add(&mut self, gyro: [f32; 3], temperature: f32) {
+        if self.count == 0 {
+            self.min_t = temperature;
+            self.max_t = temperature;
+        }
+        self.count += 1;
+        self.min_t = self.min_t.min(temperature);
+        self.max_t = self.max_t.max(temperature);
+        self.sum_t += temperature;
+        self.sum_tt += temperature * temperature;
+        for (i, value) in gyro.iter().enumerate() {
+            self.sum_g[i] += value;
+            self.sum_tg[i] += temperature * value;
+        }
+    }
+
+    /// Least-squares fit of bias over temperature, written into `calibration`.
+    pub fn finish(&self, calibration: &mut ImuCalibration) -> Result<(), CalibrationError> {
+        if self.max_t - self.min_t < Self::MIN_TEMPERATURE_RANGE {
+            return Err(CalibrationError::TemperatureRange);
+        }
+        let n = self.count as f32;
+        let mean_t = self.sum_t / n;
+        let var_t = self.sum_tt / n - mean_t * mean_t;
+        let mut slope = [0.0; 3];
+        let mut bias = [0.0; 3];
+        for i in 0..3 {
+            let mean_g = self.sum_g[i] / n;
+            slope[i] = (self.sum_tg[i] / n - mean_t * mean_g) / var_t;
+            bias[i] = mean_g;
+        }
+        calibration.gyro_bias = bias;
+        calibration.gyro_temperature = Some(GyroTemperatureModel {
+            slope,
+            reference_temperature: mean_t,
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gyro_bias_is_mean() {
+        let mut cal = GyroCalibrator::new(10, MotionThresholds::default());
+        for i in 0..10 {
+            let noise = if i % 2 == 0 { 0.01 } else { -0.01 };
+            cal.add([1.0 + noise, -2.0, 0.5], [0.0, 0.0, 1.0], None);
+        }
+        let mut calibration = ImuCalibration::default();
+        cal.finish(&mut calibration).unwrap();
+        assert!((calibration.gyro_bias[0] - 1.0).abs() < 1e-4);
+        assert!((calibration.gyro_bias[1] + 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn gyro_calibration_refused_on_motion() {
+        let mut cal = GyroCalibrator::new(10, MotionThresholds::default());
+        for i in 0..10 {
+            cal.add([i as f32 * 5.0, 0.0, 0.0], [0.0, 0.0, 1.0], None);
+        }
+        let mut calibration = ImuCalibration::default();
+        assert_eq!(cal.finish(&mut calibration), Err(CalibrationError::Motion));
+        assert_eq!(calibration, ImuCalibration::default());
+    }
+
+    #[test]
+    fn accel_six_position() {
+        let offset = [0.05, -0.02, 0.1];
+        let gain = [1.02, 0.98, 1.05];
+        let mut cal = AccelCalibrator::new(5, MotionThresholds::default());
+        let orientations = [
+            AccelOrientation::Level,
+            AccelOrientation::UpsideDown,
+            AccelOrientation::NoseUp,
+            AccelOrientation::NoseDown,
+            AccelOrientation::LeftSideDown,
+            AccelOrientation::RightSideDown,
+        ];
+        for orientation in orientations {
+            let (axis, sign) = orientation.axis();
+            let mut accel = offset;
+            accel[axis] += sign * gain[axis];
+            while !cal.add(accel) {}
+            cal.finish_orientation(orientation).unwrap();
+        }
+        let mut calibration = ImuCalibration::default();
+        cal.finish(&mut calibration).unwrap();
+        let raw = [offset[0], offset[1], offset[2] + gain[2]];
+        let (_, accel) = calibration.apply([0.0; 3], raw, None);
+        assert!(accel[0].abs() < 1e-4);
+        assert!(accel[1].abs() < 1e-4);
+        assert!((accel[2] - 1.0).abs() < 1e-4);
+    }
+
+    /// Readings of the named orientations, independent of `AccelOrientation::axis`. The
+    /// sensor is forward, left, up and reads +1 g on the axis pointing up.
+    #[test]
+    fn accel_named_orientations() {
+        let offset = [0.05, -0.02, 0.1];
+        let gain = [1.02, 0.98, 1.05];
+        let up = [
+            (AccelOrientation::Level, [0.0, 0.0, 1.0]),
+            (AccelOrientation::UpsideDown, [0.0, 0.0, -1.0]),
+            (AccelOrientation::NoseUp, [1.0, 0.0, 0.0]),
+            (AccelOrientation::NoseDown, [-1.0, 0.0, 0.0]),
+            (AccelOrientation::LeftSideDown, [0.0, -1.0, 0.0]),
+            (AccelOrientation::RightSideDown, [0.0, 1.0, 0.0]),
+        ];
+        let raw = |up: [f32; 3]| [0, 1, 2].map(|i| offset[i] + gain[i] * up[i]);
+        let mut cal = AccelCalibrator::new(5, MotionThresholds::default());
+        for (orientation, up) in up {
+            while !cal.add(raw(up)) {}
+            cal.finish_orientation(orientation).unwrap();
+        }
+        let mut calibration = ImuCalibration::default();
+        cal.finish(&mut calibration).unwrap();
+        for (_, up) in up {
+            let (_, accel) = calibration.apply([0.0; 3], raw(up), None);
+            for (accel, up) in accel.iter().zip(up) {
+                assert!((accel - up).abs() < 1e-4, "{accel} != {up}");
+            }
+        }
+    }
+
+    #[test]
+    fn accel_wrong_orientation() {
+        let mut cal = AccelCalibrator::new(1, MotionThresholds::default());
+        cal.add([0.0, 0.0, 1.0]);
+        assert_eq!(
+            cal.finish_orientation(AccelOrientation::NoseDown),
+            Err(CalibrationError::WrongOrientation)
+        );
+    }
+
+    #[test]
+    fn gyro_temperature_fit() {
+        let mut cal = GyroTemperatureCalibrator::default();
+        for i in 0..20 {
+            let t = 20.0 + i as f32;
+            cal.add([0.1 * (t - 30.0), 1.0, 0.0], t);
+        }
+        let mut calibration = ImuCalibration::default();
+        cal.finish(&mut calibration).unwrap();
+        let bias = calibration.gyro_bias_at(Some(40.0));
+        assert!((bias[0] - 1.0).abs() < 1e-3);
+        assert!((bias[1] - 1.0).abs() < 1e-3);
+    }
+}
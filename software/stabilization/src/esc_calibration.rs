@@ -0,0 +1,183 @@
+//! Throttle endpoint calibration of analog (PWM) ESCs.
+//!
+//! The ESCs learn the endpoints when they are powered up while receiving max throttle: max
+//! throttle is output until the battery has been connected and the ESCs have beeped, then min
+//! throttle is held until the ESCs have stored it.
+
+#[derive(Debug, Clone, Copy)]
+pub struct EscCalibrationConfig {
+    /// The calibration is aborted if the battery is not confirmed within this time [s].
+    pub battery_timeout: f32,
+    /// Time for which min throttle is held after the battery confirmation [s].
+    pub min_throttle_time: f32,
+    /// The calibration is only allowed (and continues) while the radio throttle is below this.
+    pub throttle_limit: f32,
+}
+
+impl Default for EscCalibrationConfig {
+    fn default() -> Self {
+        Self {
+            battery_timeout: 60.0,
+            min_throttle_time: 5.0,
+            throttle_limit: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscCalibrationPhase {
+    Idle,
+    /// Max throttle, waiting for the battery confirmation.
+    MaxThrottle,
+    /// Min throttle, waiting for the ESCs to store the endpoints.
+    MinThrottle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscCalibrationError {
+    Armed,
+    ThrottleNotLow,
+    NotActive,
+    Timeout,
+}
+
+pub struct EscCalibration {
+    config: EscCalibrationConfig,
+    phase: EscCalibrationPhase,
+    time: f32,
+}
+
+impl EscCalibration {
+    pub fn new(config: EscCalibrationConfig) -> Self {
+        Self {
+            config,
+            phase: EscCalibrationPhase::Idle,
+            time: 0.0,
+        }
+    }
+
+    pub fn phase(&self) -> EscCalibrationPhase {
+        self.phase
+    }
+
+    pub fn active(&self) -> bool {
+        self.phase != EscCalibrationPhase::Idle
+    }
+
+    /// Starts outputting max throttle. The battery must be disconnected and the props removed.
+    pub fn start(&mut self, armed: bool, throttle: f32) -> Result<(), EscCalibrationError> {
+        self.check(armed, throttle)?;
+        self.phase = EscCalibrationPhase::MaxThrottle;
+        self.time = 0.0;
+        Ok(())
+    }
+
+    /// Switches to min throttle after the battery has been connected.
+    pub fn battery_connected(&mut self) -> Result<(), EscCalibrationError> {
+        if self.phase != EscCalibrationPhase::MaxThrottle {
+            return Err(EscCalibrationError::NotActive);
+        }
+        self.phase = EscCalibrationPhase::MinThrottle;
+        self.time = 0.0;
+        Ok(())
+    }
+
+    pub fn abort(&mut self) {
+        self.phase = EscCalibrationPhase::Idle;
+    }
+
+    /// Returns the output `[0.0 .. 1.0]` for all motors while the calibration is active. Aborts
+    /// the calibration and returns the reason if the vehicle is armed, the radio throttle is
+    /// raised or the battery is not confirmed in time.
+    pub fn update(
+        &mut self,
+        armed: bool,
+        throttle: f32,
+        dt: f32,
+    ) -> Result<Option<f32>, EscCalibrationError> {
+        if self.phase == EscCalibrationPhase::Idle {
+            return Ok(None);
+        }
+        self.time += dt;
+        let result = if self.phase == EscCalibrationPhase::MaxThrottle
+            && self.time > self.config.battery_timeout
+        {
+            Err(EscCalibrationError::Timeout)
+        } else {
+            self.check(armed, throttle)
+        };
+        if let Err(e) = result {
+            self.abort();
+            return Err(e);
+        }
+        Ok(match self.phase {
+            EscCalibrationPhase::MaxThrottle => Some(1.0),
+            _ if self.time >= self.config.min_throttle_time => {
+                self.phase = EscCalibrationPhase::Idle;
+                None
+            }
+            _ => Some(0.0),
+        })
+    }
+
+    fn check(&self, armed: bool, throttle: f32) -> Result<(), EscCalibrationError> {
+        if armed {
+            Err(EscCalibrationError::Armed)
+        } else if throttle > self.config.throttle_limit {
+            Err(EscCalibrationError::ThrottleNotLow)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    #[test]
+    fn max_then_min_throttle() {
+        let mut calibration = EscCalibration::new(EscCalibrationConfig::default());
+        assert_eq!(
+            calibration.battery_connected(),
+            Err(EscCalibrationError::NotActive)
+        );
+        assert_eq!(
+            calibration.start(true, 0.0),
+            Err(EscCalibrationError::Armed)
+        );
+        assert_eq!(
+            calibration.start(false, 0.5),
+            Err(EscCalibrationError::ThrottleNotLow)
+        );
+
+        calibration.start(false, 0.0).unwrap();
+        assert_eq!(calibration.update(false, 0.0, DT), Ok(Some(1.0)));
+        calibration.battery_connected().unwrap();
+        assert_eq!(calibration.phase(), EscCalibrationPhase::MinThrottle);
+        for _ in 0..240 {
+            assert_eq!(calibration.update(false, 0.0, DT), Ok(Some(0.0)));
+        }
+        let finished = (0..20).find(|_| calibration.update(false, 0.0, DT) == Ok(None));
+        assert!(finished.is_some());
+        assert!(!calibration.active());
+    }
+
+    #[test]
+    fn aborts_when_armed_or_unconfirmed() {
+        let mut calibration = EscCalibration::new(EscCalibrationConfig::default());
+        calibration.start(false, 0.0).unwrap();
+        assert_eq!(
+            calibration.update(true, 0.0, DT),
+            Err(EscCalibrationError::Armed)
+        );
+        assert_eq!(calibration.update(false, 0.0, DT), Ok(None));
+
+        calibration.start(false, 0.0).unwrap();
+        let timeout = (0..3100).find_map(|_| calibration.update(false, 0.0, DT).err());
+        assert_eq!(timeout, Some(EscCalibrationError::Timeout));
+        assert!(!calibration.active());
+    }
+}
@@ -0,0 +1,429 @@
+//! Error-state extended Kalman filter for the attitude and the gyro bias.
+//!
+//! The nominal state (attitude quaternion and gyro bias) is propagated with the gyro. The filter
+//! estimates the error state, a body-frame rotation vector and the bias error, and injects it into
+//! the nominal state after each measurement update. The accelerometer observes the direction of
+//! gravity, the magnetometer only the heading.
+
+use nalgebra::Matrix3;
+use nalgebra::RowSVector;
+use nalgebra::SMatrix;
+use nalgebra::SVector;
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+
+use crate::attitude::AttitudeEstimate;
+use crate::attitude::AttitudeEstimator;
+use crate::attitude::accelerating;
+
+type Vector6 = SVector<f32, 6>;
+type Matrix6 = SMatrix<f32, 6, 6>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct EskfConfig {
+    /// Gyro noise density [rad/s/sqrt(Hz)].
+    pub gyro_noise: f32,
+    /// Gyro bias random walk [rad/s^2/sqrt(Hz)].
+    pub bias_walk: f32,
+    /// Noise of the normalized accelerometer reading at rest.
+    pub accel_noise: f32,
+    /// Additional accelerometer noise per g of deviation from gravity, so that the accelerometer
+    /// is trusted less during high dynamics. Beyond the rejection threshold of the complementary
+    /// filters it is not used at all.
+    pub accel_dynamics: f32,
+    /// Heading noise of the magnetometer [rad].
+    pub mag_noise: f32,
+    /// Standard deviation of the attitude after the coarse alignment [rad].
+    pub initial_attitude: f32,
+    /// Initial standard deviation of the gyro bias [rad/s].
+    pub initial_bias: f32,
+    /// Measurements with a squared Mahalanobis distance above this are rejected, per degree of
+    /// freedom.
+    pub gate: f32,
+    /// The estimate is considered converged below this standard deviation of roll and pitch [rad].
+    pub converged_tilt: f32,
+    /// If the gate rejected the accelerometer or the magnetometer for this long [s], the estimate
+    /// has most likely diverged and roll and pitch or the heading are aligned again.
+    pub realign_time: f32,
+}
+
+impl Default for EskfConfig {
+    fn default() -> Self {
+        Self {
+            gyro_noise: 0.005,
+            bias_walk: 0.0002,
+            accel_noise: 0.05,
+            accel_dynamics: 5.0,
+            mag_noise: 0.1,
+            initial_attitude: 0.1,
+            initial_bias: 0.05,
+            gate: 9.0,
+            converged_tilt: 0.02,
+            realign_time: 5.0,
+        }
+    }
+}
+
+pub struct Eskf {
+    config: EskfConfig,
+    quaternion: UnitQuaternion<f32>,
+    /// Gyro bias [rad/s].
+    bias: Vector3<f32>,
+    /// Covariance of the error state (rotation vector, bias).
+    covariance: Matrix6,
+    /// The attitude was initialized from the first measurements.
+    aligned: bool,
+    /// A measurement was rejected by the gate in the current update.
+    gated: bool,
+    /// Time since the gate accepted the accelerometer and the magnetometer [s].
+    accel_rejected: f32,
+    mag_rejected: f32,
+    /// Sum and number of the rejected accelerometer readings, averaged for the realignment of
+    /// roll and pitch, so that vibration and short accelerations do not tilt it.
+    accel_sum: Vector3<f32>,
+    accel_samples: u32,
+}
+
+impl Eskf {
+    pub fn new(config: EskfConfig) -> Self {
+        let mut covariance = Matrix6::zeros();
+        for i in 0..3 {
+            covariance[(i, i)] = config.initial_attitude * config.initial_attitude;
+            covariance[(i + 3, i + 3)] = config.initial_bias * config.initial_bias;
+        }
+        Self {
+            config,
+            quaternion: UnitQuaternion::identity(),
+            bias: Vector3::zeros(),
+            covariance,
+            aligned: false,
+            gated: false,
+            accel_rejected: 0.0,
+            mag_rejected: 0.0,
+            accel_sum: Vector3::zeros(),
+            accel_samples: 0,
+        }
+    }
+
+    /// Coarse alignment: roll and pitch from gravity, the heading from the magnetometer (or
+    /// zero without).
+    fn align(&mut self, accel: Vector3<f32>, mag: Option<Vector3<f32>>) -> bool {
+        let Some(tilt) = UnitQuaternion::rotation_between(&accel, &Vector3::z()) else {
+            return false;
+        };
+        let heading = mag.map_or(0.0, |mag| {
+            let earth = tilt * mag;
+            libm::atan2f(earth.y, earth.x)
+        });
+        self.quaternion = UnitQuaternion::from_euler_angles(0.0, 0.0, -heading) * tilt;
+        self.aligned = true;
+        true
+    }
+
+    /// Aligns roll and pitch again with the mean direction of gravity, the heading is kept.
+    fn realign_tilt(&mut self, accel: Vector3<f32>) {
+        let Some(tilt) = UnitQuaternion::rotation_between(&accel, &Vector3::z()) else {
+            return;
+        };
+        let (_, _, heading) = self.quaternion.euler_angles();
+        let (_, _, tilt_heading) = tilt.euler_angles();
+        self.quaternion =
+            UnitQuaternion::from_euler_angles(0.0, 0.0, heading - tilt_heading) * tilt;
+        // The bias estimate is kept, but no longer correlated with the attitude.
+        let variance = self.config.initial_attitude * self.config.initial_attitude;
+        self.covariance
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&(Matrix3::identity() * variance));
+        self.covariance.fixed_view_mut::<3, 3>(0, 3).fill(0.0);
+        self.covariance.fixed_view_mut::<3, 3>(3, 0).fill(0.0);
+    }
+
+    /// Aligns the heading again with the magnetometer, roll and pitch are kept.
+    fn realign_heading(&mut self, mag: Vector3<f32>) {
+        let earth = self.quaternion * mag;
+        let heading = libm::atan2f(earth.y, earth.x);
+        self.quaternion = UnitQuaternion::from_euler_angles(0.0, 0.0, -heading) * self.quaternion;
+        // Only the uncertainty about the earth vertical grows.
+        let up = self.quaternion.inverse_transform_vector(&Vector3::z());
+        let variance = self.config.initial_attitude * self.config.initial_attitude;
+        let mut attitude = self.covariance.fixed_view_mut::<3, 3>(0, 0);
+        attitude += up * up.transpose() * variance;
+    }
+
+    /// Standard deviation of roll and pitch [rad], the larger of both.
+    pub fn tilt_std(&self) -> f32 {
+        let rotation = self.quaternion.to_rotation_matrix();
+        let attitude = self.covariance.fixed_view::<3, 3>(0, 0);
+        let earth = rotation.matrix() * attitude * rotation.matrix().transpose();
+        libm::sqrtf(earth[(0, 0)].max(earth[(1, 1)]))
+    }
+
+    fn predict(&mut self, rates: &Vector3<f32>, dt: f32) {
+        self.quaternion *= UnitQuaternion::from_scaled_axis(rates * dt);
+        self.quaternion.renormalize();
+
+        let mut transition = Matrix6::identity();
+        transition
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&(Matrix3::identity() - (rates * dt).cross_matrix()));
+        transition
+            .fixed_view_mut::<3, 3>(0, 3)
+            .copy_from(&(Matrix3::identity() * -dt));
+        let mut noise = Matrix6::zeros();
+        for i in 0..3 {
+            noise[(i, i)] = self.config.gyro_noise * self.config.gyro_noise * dt;
+            noise[(i + 3, i + 3)] = self.config.bias_walk * self.config.bias_walk * dt;
+        }
+        self.covariance = transition * self.covariance * transition.transpose() + noise;
+    }
+
+    /// Gravity update, returns false if the measurement was rejected.
+    fn update_accel(&mut self, accel: Vector3<f32>) -> bool {
+        if accelerating(accel.into()) {
+            return false;
+        }
+        let Some(measured) = accel.try_normalize(f32::EPSILON) else {
+            return false;
+        };
+        let predicted = self.quaternion.inverse_transform_vector(&Vector3::z());
+        let mut jacobian = SMatrix::<f32, 3, 6>::zeros();
+        jacobian
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&predicted.cross_matrix());
+        let std = self.config.accel_noise + self.config.accel_dynamics * (accel.norm() - 1.0).abs();
+        self.correct(
+            measured - predicted,
+            &jacobian,
+            Matrix3::identity() * (std * std),
+        )
+    }
+
+    /// Heading update, returns false if the measurement was rejected.
+    fn update_mag(&mut self, mag: Vector3<f32>) -> bool {
+        let earth = self.quaternion * mag;
+        if libm::hypotf(earth.x, earth.y) < 0.1 * earth.norm() {
+            return false;
+        }
+        // The field points north, a heading error rotates it about the earth vertical.
+        let innovation = -libm::atan2f(earth.y, earth.x);
+        let up = self.quaternion.inverse_transform_vector(&Vector3::z());
+        let mut jacobian = RowSVector::<f32, 6>::zeros();
+        jacobian
+            .fixed_view_mut::<1, 3>(0, 0)
+            .copy_from(&up.transpose());
+        let noise = SMatrix::<f32, 1, 1>::new(self.config.mag_noise * self.config.mag_noise);
+        self.correct(SVector::<f32, 1>::new(innovation), &jacobian, noise)
+    }
+
+    fn correct<const M: usize>(
+        &mut self,
+        innovation: SVector<f32, M>,
+        jacobian: &SMatrix<f32, M, 6>,
+        noise: SMatrix<f32, M, M>,
+    ) -> bool {
+        let covariance = self.covariance;
+        let innovation_covariance = jacobian * covariance * jacobian.transpose() + noise;
+        let Some(inverse) = innovation_covariance.try_inverse() else {
+            return false;
+        };
+        let distance = (innovation.transpose() * inverse * innovation)[(0, 0)];
+        if distance > self.config.gate * M as f32 {
+            self.gated = true;
+            return false;
+        }
+        let gain = covariance * jacobian.transpose() * inverse;
+        let error: Vector6 = gain * innovation;
+
+        // Joseph form, keeps the covariance symmetric and positive definite in f32.
+        let factor = Matrix6::identity() - gain * jacobian;
+        self.covariance =
+            factor * covariance * factor.transpose() + gain * noise * gain.transpose();
+
+        self.quaternion *= UnitQuaternion::from_scaled_axis(error.fixed_rows::<3>(0).into_owned());
+        self.quaternion.renormalize();
+        self.bias += error.fixed_rows::<3>(3);
+        true
+    }
+}
+
+impl AttitudeEstimator for Eskf {
+    fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        dt: f32,
+    ) -> AttitudeEstimate {
+        if !self.aligned {
+            self.align(Vector3::from(accel), mag.map(Vector3::from));
+        }
+        let rates = Vector3::from(gyro).map(f32::to_radians) - self.bias;
+        self.predict(&rates, dt);
+        self.gated = false;
+        let accel_used = self.update_accel(Vector3::from(accel));
+        // Readings beyond the acceleration threshold are not gated, but ignored.
+        if self.gated {
+            self.accel_rejected += dt;
+            self.accel_sum += Vector3::from(accel);
+            self.accel_samples += 1;
+        } else {
+            self.accel_rejected = 0.0;
+            self.accel_sum = Vector3::zeros();
+            self.accel_samples = 0;
+        }
+        self.gated = false;
+        let mag_used = mag.filter(|mag| self.update_mag(Vector3::from(*mag)));
+        self.mag_rejected = if self.gated {
+            self.mag_rejected + dt
+        } else {
+            0.0
+        };
+
+        let rates = Vector3::from(gyro).map(f32::to_radians) - self.bias;
+        let estimate = AttitudeEstimate::new(
+            self.quaternion,
+            rates.map(f32::to_degrees).into(),
+            accel,
+            mag_used,
+            0.0,
+        );
+        let estimate = AttitudeEstimate {
+            converged: self.tilt_std() < self.config.converged_tilt,
+            accelerating: !accel_used,
+            ..estimate
+        };
+        // The covariance became too small to accept the measurements again. Only the rejected
+        // part of the attitude is aligned again, the gyro bias estimate is kept.
+        if self.accel_rejected > self.config.realign_time && self.accel_samples > 0 {
+            self.realign_tilt(self.accel_sum / self.accel_samples as f32);
+            self.accel_rejected = 0.0;
+            self.accel_sum = Vector3::zeros();
+            self.accel_samples = 0;
+        }
+        if self.mag_rejected > self.config.realign_time
+            && let Some(mag) = mag
+        {
+            self.realign_heading(Vector3::from(mag));
+            self.mag_rejected = 0.0;
+        }
+        estimate
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attitude::tests::DT;
+    use crate::attitude::tests::readings;
+    use crate::attitude::tests::run;
+    use crate::test_util::Noise;
+
+    #[test]
+    fn aligns_and_converges() {
+        let mut eskf = Eskf::new(EskfConfig::default());
+        let attitude = UnitQuaternion::from_euler_angles(0.5, -0.4, 2.5);
+        let estimate = eskf.update(
+            [0.0; 3],
+            readings(&attitude).0,
+            Some(readings(&attitude).1),
+            DT,
+        );
+        assert!(!estimate.converged);
+
+        let estimate = run(&mut eskf, [0.0; 3], &attitude, 2.0);
+        assert!(estimate.converged);
+        assert!(estimate.magnetometer && !estimate.accelerating);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.01);
+    }
+
+    #[test]
+    fn tracks_rotation_with_bias_and_noise() {
+        let mut eskf = Eskf::new(EskfConfig::default());
+        let mut noise = Noise(1);
+        let bias = Vector3::new(0.02, -0.03, 0.01); // [rad/s]
+        let mut attitude = UnitQuaternion::identity();
+        let mut estimate = AttitudeEstimate::default();
+        for i in 0..(120.0 / DT) as u32 {
+            // Slow tumbling, so that all bias components become observable.
+            let t = i as f32 * DT;
+            let rates = Vector3::new(
+                0.5 * libm::sinf(0.5 * t),
+                0.4 * libm::cosf(0.3 * t),
+                0.3 * libm::sinf(0.2 * t + 1.0),
+            );
+            attitude *= UnitQuaternion::from_scaled_axis(rates * DT);
+            let (accel, mag) = readings(&attitude);
+            let gyro = (rates + bias + noise.vector(0.005)).map(f32::to_degrees);
+            let accel = Vector3::from(accel) + noise.vector(0.01);
+            let mag = Vector3::from(mag) + noise.vector(0.01);
+            estimate = eskf.update(gyro.into(), accel.into(), Some(mag.into()), DT);
+        }
+        assert!(estimate.converged);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.02);
+        assert!((eskf.bias - bias).norm() < 0.005);
+    }
+
+    #[test]
+    fn rejects_acceleration() {
+        let mut eskf = Eskf::new(EskfConfig::default());
+        let attitude = UnitQuaternion::identity();
+        run(&mut eskf, [0.0; 3], &attitude, 5.0);
+
+        // Sustained sideways acceleration of 0.5 g while level.
+        let (_, mag) = readings(&attitude);
+        let mut estimate = AttitudeEstimate::default();
+        for _ in 0..(2.0 / DT) as u32 {
+            estimate = eskf.update([0.0; 3], [0.0, 0.5, 1.0], Some(mag), DT);
+        }
+        assert!(estimate.accelerating);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.02);
+
+        // Centripetal acceleration does not change the magnitude, the gate rejects it.
+        let turn = [0.0, 0.5, 0.866];
+        let estimate = eskf.update([0.0; 3], turn, Some(mag), DT);
+        assert!(estimate.accelerating);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.02);
+    }
+
+    #[test]
+    fn realigns_after_divergence() {
+        let mut eskf = Eskf::new(EskfConfig::default());
+        let estimate = run(&mut eskf, [0.0; 3], &UnitQuaternion::identity(), 5.0);
+        assert!(estimate.converged);
+
+        // The estimate is far off, e.g. after a gyro saturation, so the gate rejects the
+        // accelerometer.
+        let attitude = UnitQuaternion::from_euler_angles(0.7, -0.3, 0.0);
+        let estimate = run(&mut eskf, [0.0; 3], &attitude, 1.0);
+        assert!(estimate.accelerating);
+        assert!(estimate.quaternion.angle_to(&attitude) > 0.5);
+
+        let estimate = run(&mut eskf, [0.0; 3], &attitude, 8.0);
+        assert!(estimate.converged && !estimate.accelerating);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.02);
+    }
+
+    #[test]
+    fn realigns_heading_only() {
+        let mut eskf = Eskf::new(EskfConfig::default());
+        let attitude = UnitQuaternion::from_euler_angles(0.3, -0.2, 0.5);
+        run(&mut eskf, [0.0; 3], &attitude, 5.0);
+
+        // The magnetometer disagrees by far more than the heading uncertainty, e.g. after a long
+        // magnetic disturbance, the accelerometer keeps being accepted.
+        let turned = UnitQuaternion::from_euler_angles(0.3, -0.2, 2.0);
+        let (accel, mag) = readings(&turned);
+        let mut estimate = AttitudeEstimate::default();
+        for _ in 0..(8.0 / DT) as u32 {
+            estimate = eskf.update([0.0; 3], accel, Some(mag), DT);
+            assert!((estimate.euler[0] - 0.3).abs() < 0.01);
+            assert!((estimate.euler[1] + 0.2).abs() < 0.01);
+        }
+        assert!(estimate.magnetometer);
+        assert!(estimate.quaternion.angle_to(&turned) < 0.02);
+    }
+}
@@ -0,0 +1,330 @@
+//! Fixed-wing control laws for horizontal flight with the rotors tilted forward.
+//!
+//! Roll and pitch are controlled by cascaded angle and rate loops driving the ailerons and the
+//! elevator, the rudder coordinates turns and damps the yaw. Airspeed and altitude are controlled
+//! by a TECS-style energy controller: the throttle controls the total energy, the pitch the
+//! balance between kinetic and potential energy.
+
+use crate::STANDARD_GRAVITY;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FixedWingConfig {
+    /// Angle error to rate setpoint [1/s].
+    pub roll_gain: f32,
+    pub pitch_gain: f32,
+    /// Rate error to surface deflection [s/rad].
+    pub roll_rate_gain: f32,
+    pub pitch_rate_gain: f32,
+    /// Yaw rate error to rudder deflection [s/rad].
+    pub yaw_damper_gain: f32,
+    pub max_bank: f32,    // [rad]
+    pub max_pitch: f32,   // [rad]
+    pub min_pitch: f32,   // [rad]
+    pub stall_speed: f32, // [m/s]
+    /// Below this airspeed, the speed has priority over the altitude and turns are flattened
+    /// [m/s].
+    pub min_airspeed: f32,
+    pub max_airspeed: f32,   // [m/s]
+    pub max_climb_rate: f32, // [m/s]
+    pub max_sink_rate: f32,  // [m/s]
+    /// Time constants of the altitude and airspeed control [s].
+    pub height_time_constant: f32,
+    pub speed_time_constant: f32,
+    /// Throttle for level flight at cruise speed `[0.0 .. 1.0]`.
+    pub trim_throttle: f32,
+    /// Throttle change per total energy rate (flight path angle plus acceleration / g) [1/rad].
+    pub throttle_gain: f32,
+    pub throttle_integral_gain: f32,
+    /// Pitch change per energy balance rate error.
+    pub pitch_energy_gain: f32,
+    pub pitch_integral_gain: f32,
+}
+
+impl Default for FixedWingConfig {
+    fn default() -> Self {
+        Self {
+            roll_gain: 4.0,
+            pitch_gain: 4.0,
+            roll_rate_gain: 0.3,
+            pitch_rate_gain: 0.4,
+            yaw_damper_gain: 0.5,
+            max_bank: 35f32.to_radians(),
+            max_pitch: 20f32.to_radians(),
+            min_pitch: -15f32.to_radians(),
+            stall_speed: 10.0,
+            min_airspeed: 13.0,
+            max_airspeed: 25.0,
+            max_climb_rate: 3.0,
+            max_sink_rate: 2.0,
+            height_time_constant: 4.0,
+            speed_time_constant: 3.0,
+            trim_throttle: 0.5,
+            throttle_gain: 2.0,
+            throttle_integral_gain: 0.3,
+            pitch_energy_gain: 1.0,
+            pitch_integral_gain: 0.1,
+        }
+    }
+}
+
+/// Attitude, rates and air data of the vehicle.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FixedWingState {
+    /// Roll (right wing down) and pitch (nose up) [rad].
+    pub roll: f32,
+    pub pitch: f32,
+    /// Roll, pitch and yaw rate in the same directions [rad/s].
+    pub rates: [f32; 3],
+    pub airspeed: f32,       // [m/s]
+    pub height: f32,         // [m]
+    pub vertical_speed: f32, // [m/s]
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FixedWingOutput {
+    /// Deflections `[-1.0 .. 1.0]`, positive to roll right, pitch up and yaw right.
+    pub aileron: f32,
+    pub elevator: f32,
+    pub rudder: f32,
+    /// `[0.0 .. 1.0]`
+    pub throttle: f32,
+}
+
+/// Roll and pitch angle loops with turn coordination.
+pub struct AttitudeController {
+    config: FixedWingConfig,
+}
+
+impl AttitudeController {
+    pub fn new(config: FixedWingConfig) -> Self {
+        Self { config }
+    }
+
+    /// Maximum bank angle at the given airspeed. Turns need more lift, so they are flattened
+    /// when approaching the stall speed [rad].
+    pub fn bank_limit(&self, airspeed: f32) -> f32 {
+        let config = &self.config;
+        let margin = (airspeed - config.stall_speed) / (config.min_airspeed - config.stall_speed);
+        config.max_bank * margin.clamp(0.0, 1.0)
+    }
+
+    /// Returns aileron, elevator and rudder for the roll and pitch setpoints [rad], which are
+    /// limited to the flight envelope.
+    pub fn update(&self, roll: f32, pitch: f32, state: &FixedWingState) -> (f32, f32, f32) {
+        let config = &self.config;
+        let bank_limit = self.bank_limit(state.airspeed);
+        let roll = roll.clamp(-bank_limit, bank_limit);
+        let pitch = pitch.clamp(config.min_pitch, config.max_pitch);
+
+        let roll_rate = config.roll_gain * (roll - state.roll);
+        let pitch_rate = config.pitch_gain * (pitch - state.pitch);
+        // A coordinated turn yaws at g * tan(roll) / airspeed.
+        let airspeed = state.airspeed.max(config.stall_speed);
+        let yaw_rate = STANDARD_GRAVITY * libm::tanf(state.roll) / airspeed;
+
+        let aileron = config.roll_rate_gain * (roll_rate - state.rates[0]);
+        let elevator = config.pitch_rate_gain * (pitch_rate - state.rates[1]);
+        let rudder = config.yaw_damper_gain * (yaw_rate - state.rates[2]);
+        (
+            aileron.clamp(-1.0, 1.0),
+            elevator.clamp(-1.0, 1.0),
+            rudder.clamp(-1.0, 1.0),
+        )
+    }
+}
+
+/// Total energy control: throttle for the total energy, pitch for the energy balance.
+pub struct EnergyController {
+    config: FixedWingConfig,
+    throttle_integral: f32,
+    pitch_integral: f32,
+    last_airspeed: Option<f32>,
+    acceleration: f32,
+}
+
+impl EnergyController {
+    pub fn new(config: FixedWingConfig) -> Self {
+        Self {
+            config,
+            throttle_integral: 0.0,
+            pitch_integral: 0.0,
+            last_airspeed: None,
+            acceleration: 0.0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.throttle_integral = 0.0;
+        self.pitch_integral = 0.0;
+        self.last_airspeed = None;
+        self.acceleration = 0.0;
+    }
+
+    /// Returns throttle and pitch setpoint [rad] for the airspeed [m/s] and height [m]
+    /// setpoints.
+    pub fn update(
+        &mut self,
+        airspeed: f32,
+        height: f32,
+        state: &FixedWingState,
+        dt: f32,
+    ) -> (f32, f32) {
+        let config = &self.config;
+        let airspeed_setpoint = airspeed.clamp(config.min_airspeed, config.max_airspeed);
+        let speed = state.airspeed.max(config.stall_speed);
+
+        // Low-pass filtered derivative of the airspeed.
+        if let Some(last_airspeed) = self.last_airspeed {
+            let acceleration = (state.airspeed - last_airspeed) / dt;
+            self.acceleration += (acceleration - self.acceleration) * (dt / 0.5).min(1.0);
+        }
+        self.last_airspeed = Some(state.airspeed);
+
+        let climb_rate = ((height - state.height) / config.height_time_constant)
+            .clamp(-config.max_sink_rate, config.max_climb_rate);
+        let acceleration_setpoint =
+            (airspeed_setpoint - state.airspeed) / config.speed_time_constant;
+
+        // Specific energy rates, normalized to flight path angles [rad].
+        let potential_setpoint = climb_rate / speed;
+        let potential = state.vertical_speed / speed;
+        let kinetic_setpoint = acceleration_setpoint / STANDARD_GRAVITY;
+        let kinetic = self.acceleration / STANDARD_GRAVITY;
+
+        // Underspeed: only the speed counts, so that the pitch is lowered instead of stalling.
+        let underspeed = state.airspeed < config.min_airspeed;
+        let (potential_weight, kinetic_weight) = if underspeed { (0.0, 2.0) } else { (1.0, 1.0) };
+
+        let total_error = (potential_setpoint - potential) + (kinetic_setpoint - kinetic);
+        let mut throttle = config.trim_throttle
+            + config.throttle_gain * (potential_setpoint + kinetic_setpoint)
+            + self.throttle_integral;
+        if underspeed {
+            throttle = 1.0;
+        } else if (0.0..1.0).contains(&throttle) {
+            self.throttle_integral += config.throttle_integral_gain * total_error * dt;
+        }
+
+        let balance_error = potential_weight * (potential_setpoint - potential)
+            - kinetic_weight * (kinetic_setpoint - kinetic);
+        let pitch = potential_weight * potential_setpoint
+            + config.pitch_energy_gain * balance_error
+            + self.pitch_integral;
+        if (config.min_pitch..config.max_pitch).contains(&pitch) {
+            self.pitch_integral += config.pitch_integral_gain * balance_error * dt;
+        }
+
+        (
+            throttle.clamp(0.0, 1.0),
+            pitch.clamp(config.min_pitch, config.max_pitch),
+        )
+    }
+}
+
+/// Attitude and energy control for a commanded bank angle, airspeed and height.
+pub struct FixedWingController {
+    attitude: AttitudeController,
+    energy: EnergyController,
+}
+
+impl FixedWingController {
+    pub fn new(config: FixedWingConfig) -> Self {
+        Self {
+            attitude: AttitudeController::new(config),
+            energy: EnergyController::new(config),
+        }
+    }
+
+    /// Resets the integrators, e.g. when entering forward flight.
+    pub fn reset(&mut self) {
+        self.energy.reset();
+    }
+
+    /// `roll` is the bank angle setpoint [rad].
+    pub fn update(
+        &mut self,
+        roll: f32,
+        airspeed: f32,
+        height: f32,
+        state: &FixedWingState,
+        dt: f32,
+    ) -> FixedWingOutput {
+        let (throttle, pitch) = self.energy.update(airspeed, height, state, dt);
+        let (aileron, elevator, rudder) = self.attitude.update(roll, pitch, state);
+        FixedWingOutput {
+            aileron,
+            elevator,
+            rudder,
+            throttle,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    #[test]
+    fn attitude_loops() {
+        let controller = AttitudeController::new(FixedWingConfig::default());
+        let level = FixedWingState {
+            airspeed: 16.0,
+            ..Default::default()
+        };
+        assert_eq!(controller.update(0.0, 0.0, &level), (0.0, 0.0, 0.0));
+        let (aileron, elevator, _) = controller.update(0.3, 0.1, &level);
+        assert!(aileron > 0.0 && elevator > 0.0);
+
+        // Turn coordination: yaw right while banked right.
+        let banked = FixedWingState { roll: 0.3, ..level };
+        let (aileron, _, rudder) = controller.update(0.3, 0.0, &banked);
+        assert_eq!(aileron, 0.0);
+        assert!(rudder > 0.0);
+    }
+
+    #[test]
+    fn envelope_protection() {
+        let controller = AttitudeController::new(FixedWingConfig::default());
+        assert!((controller.bank_limit(16.0) - 35f32.to_radians()).abs() < 1e-6);
+        assert_eq!(controller.bank_limit(9.0), 0.0);
+        // Near the stall speed, a steep turn is flattened to level flight.
+        let slow = FixedWingState {
+            airspeed: 10.0,
+            roll: 0.0,
+            ..Default::default()
+        };
+        let (aileron, _, _) = controller.update(1.0, 0.0, &slow);
+        assert_eq!(aileron, 0.0);
+
+        let mut energy = EnergyController::new(FixedWingConfig::default());
+        let (throttle, pitch) = energy.update(16.0, 100.0, &slow, DT);
+        assert_eq!(throttle, 1.0);
+        assert!(pitch <= 0.0);
+    }
+
+    /// Point mass flying along its pitch angle, with drag. The elevator commands the pitch
+    /// rate.
+    #[test]
+    fn climbs_and_accelerates() {
+        let mut controller = FixedWingController::new(FixedWingConfig::default());
+        let mut state = FixedWingState {
+            airspeed: 16.0,
+            ..Default::default()
+        };
+        for _ in 0..(60.0 / DT) as u32 {
+            let output = controller.update(0.0, 18.0, 20.0, &state, DT);
+            state.rates[1] = 2.0 * output.elevator;
+            state.pitch += state.rates[1] * DT;
+            let thrust = 10.0 * output.throttle;
+            let drag = 0.0195 * state.airspeed * state.airspeed;
+            let acceleration = thrust - drag - STANDARD_GRAVITY * libm::sinf(state.pitch);
+            state.airspeed += acceleration * DT;
+            state.vertical_speed = state.airspeed * libm::sinf(state.pitch);
+            state.height += state.vertical_speed * DT;
+        }
+        assert!((state.height - 20.0).abs() < 1.0, "{}", state.height);
+        assert!((state.airspeed - 18.0).abs() < 0.5, "{}", state.airspeed);
+    }
+}
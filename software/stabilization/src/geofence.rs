@@ -0,0 +1,182 @@
+//! Geofence: limits the height above and the distance from home, and optionally keeps the
+//! vehicle inside a polygon.
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::navigation::LocalFrame;
+
+pub const MAX_VERTICES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breach {
+    Altitude,
+    Distance,
+    Polygon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenceError {
+    /// Vertices need to be added in order.
+    OutOfSequence,
+    TooManyVertices,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Geofence {
+    /// Maximum height above home [m].
+    pub max_altitude: Option<f32>,
+    /// Maximum horizontal distance from home [m].
+    pub max_distance: Option<f32>,
+    /// Latitude and longitude [1e-7 deg] of the inclusion polygon, used with 3 or more vertices.
+    polygon: [Option<[i32; 2]>; MAX_VERTICES],
+}
+
+impl Default for Geofence {
+    fn default() -> Self {
+        Self::new(None, None)
+    }
+}
+
+impl Geofence {
+    pub fn new(max_altitude: Option<f32>, max_distance: Option<f32>) -> Self {
+        Self {
+            max_altitude,
+            max_distance,
+            polygon: [None; MAX_VERTICES],
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_altitude.is_some() || self.max_distance.is_some() || self.vertices() >= 3
+    }
+
+    pub fn vertices(&self) -> usize {
+        self.polygon
+            .iter()
+            .take_while(|vertex| vertex.is_some())
+            .count()
+    }
+
+    /// Appends the vertex at `index`, index 0 starts a new polygon.
+    pub fn set_vertex(&mut self, index: usize, vertex: [i32; 2]) -> Result<(), FenceError> {
+        if index >= MAX_VERTICES {
+            return Err(FenceError::TooManyVertices);
+        }
+        if index == 0 {
+            self.polygon = [None; MAX_VERTICES];
+        } else if index != self.vertices() {
+            return Err(FenceError::OutOfSequence);
+        }
+        self.polygon[index] = Some(vertex);
+        Ok(())
+    }
+
+    /// Checks the north, east, down `position` [m] against the fence around `home`, both in the
+    /// local `frame`.
+    pub fn check(&self, frame: &LocalFrame, home: [f32; 3], position: [f32; 3]) -> Option<Breach> {
+        if let Some(max_altitude) = self.max_altitude
+            && home[2] - position[2] > max_altitude
+        {
+            return Some(Breach::Altitude);
+        }
+        let offset = [position[0] - home[0], position[1] - home[1]];
+        if let Some(max_distance) = self.max_distance
+            && offset[0] * offset[0] + offset[1] * offset[1] > max_distance * max_distance
+        {
+            return Some(Breach::Distance);
+        }
+        if self.vertices() >= 3 && !self.inside_polygon(frame, [position[0], position[1]]) {
+            return Some(Breach::Polygon);
+        }
+        None
+    }
+
+    /// Even-odd rule: counts the crossings of the edges with a ray pointing north.
+    fn inside_polygon(&self, frame: &LocalFrame, point: [f32; 2]) -> bool {
+        let vertices = self.vertices();
+        let local = |index: usize| {
+            let [latitude, longitude] = self.polygon[index].unwrap();
+            frame.to_local(latitude, longitude, frame.altitude)
+        };
+        let mut inside = false;
+        let mut previous = local(vertices - 1);
+        for index in 0..vertices {
+            let vertex = local(index);
+            // Edges crossing the east coordinate of the point, north of it.
+            if (vertex[1] > point[1]) != (previous[1] > point[1]) {
+                let north = vertex[0]
+                    + (point[1] - vertex[1]) * (previous[0] - vertex[0])
+                        / (previous[1] - vertex[1]);
+                if north > point[0] {
+                    inside = !inside;
+                }
+            }
+            previous = vertex;
+        }
+        inside
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: LocalFrame = LocalFrame {
+        latitude: 472_852_395,
+        longitude: 85_652_537,
+        altitude: 500.0,
+    };
+
+    fn vertex(north: f32, east: f32) -> [i32; 2] {
+        let (latitude, longitude, _) = FRAME.to_global([north, east, 0.0]);
+        [latitude, longitude]
+    }
+
+    #[test]
+    fn altitude_and_distance() {
+        let fence = Geofence::new(Some(50.0), Some(100.0));
+        let home = [10.0, 0.0, 2.0];
+        assert_eq!(fence.check(&FRAME, home, [50.0, 60.0, -40.0]), None);
+        assert_eq!(
+            fence.check(&FRAME, home, [50.0, 60.0, -49.0]),
+            Some(Breach::Altitude)
+        );
+        assert_eq!(
+            fence.check(&FRAME, home, [90.0, 70.0, -40.0]),
+            Some(Breach::Distance)
+        );
+        assert!(!Geofence::default().enabled());
+    }
+
+    #[test]
+    fn polygon_inclusion() {
+        let mut fence = Geofence::default();
+        // L-shaped field.
+        let corners = [
+            (0.0, 0.0),
+            (200.0, 0.0),
+            (200.0, 100.0),
+            (100.0, 100.0),
+            (100.0, 300.0),
+            (0.0, 300.0),
+        ];
+        for (index, (north, east)) in corners.into_iter().enumerate() {
+            assert_eq!(fence.enabled(), index >= 3);
+            fence.set_vertex(index, vertex(north, east)).unwrap();
+        }
+        assert!(fence.enabled());
+        assert_eq!(
+            fence.set_vertex(7, vertex(0.0, 0.0)),
+            Err(FenceError::OutOfSequence)
+        );
+
+        let home = [50.0, 50.0, 0.0];
+        let check = |north, east| fence.check(&FRAME, home, [north, east, -10.0]);
+        assert_eq!(check(150.0, 50.0), None);
+        assert_eq!(check(50.0, 250.0), None);
+        assert_eq!(check(150.0, 150.0), Some(Breach::Polygon));
+        assert_eq!(check(-5.0, 50.0), Some(Breach::Polygon));
+        assert_eq!(check(50.0, 310.0), Some(Breach::Polygon));
+    }
+}
@@ -0,0 +1,121 @@
+//! Attitude control in hover, a PD law on roll and pitch.
+//!
+//! Plus layout: motor 0 right, 1 left, 2 front and 3 rear.
+
+use crate::attitude::AttitudeEstimate;
+
+#[derive(Debug, Clone, Copy)]
+pub struct HoverConfig {
+    /// Thrust difference per angle error [1/rad].
+    pub kp: f32,
+    /// Thrust difference per rate [s/rad].
+    pub kd: f32,
+    /// Share of the stick thrust passed to the motors.
+    pub thrust_scale: f32,
+}
+
+// Tuning values of PD controller:
+// (1) Start with low values (kp = 1..3, kd = 0.1..0.3).
+// (2) Increase kp until the quad responds fast enough but does not oscillate.
+// (3) Increase kd to damp oscillations / smooth response.
+// (4) Adjust hover base thrust to maintain altitude.
+impl Default for HoverConfig {
+    fn default() -> Self {
+        Self {
+            kp: 3.0,
+            kd: 0.2,
+            thrust_scale: 0.6,
+        }
+    }
+}
+
+pub struct HoverController {
+    config: HoverConfig,
+}
+
+impl HoverController {
+    pub fn new(config: HoverConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns the roll and pitch rates [dps] and the motor thrust for the stick thrust.
+    /// `setpoint` is the roll and pitch [rad] to hold, level for zero. A positive roll lowers
+    /// the right side, a positive pitch the nose.
+    pub fn update(
+        &self,
+        estimate: &AttitudeEstimate,
+        setpoint: [f32; 2],
+        thrust: [f32; 4],
+    ) -> ([f32; 2], [f32; 4]) {
+        let HoverConfig {
+            kp,
+            kd,
+            thrust_scale,
+        } = self.config;
+        let [roll, pitch, _yaw] = estimate.euler;
+        let (roll, pitch) = (roll - setpoint[0], pitch - setpoint[1]);
+        let [roll_rate, pitch_rate, _yaw_rate] = estimate.rates;
+        // Lifts the lower side and damps the rotation towards it.
+        let roll_correction = kp * roll + kd * roll_rate.to_radians();
+        let pitch_correction = kp * pitch + kd * pitch_rate.to_radians();
+
+        (
+            [roll_rate, pitch_rate],
+            [
+                thrust[0] * thrust_scale + roll_correction,
+                thrust[1] * thrust_scale - roll_correction,
+                thrust[2] * thrust_scale + pitch_correction,
+                thrust[3] * thrust_scale - pitch_correction,
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        let controller = HoverController::new(HoverConfig::default());
+        let level = controller
+            .update(&AttitudeEstimate::default(), [0.0; 2], [0.5; 4])
+            .1;
+        assert_eq!(level, [0.3; 4]);
+
+        let rolled = AttitudeEstimate {
+            euler: [0.1, 0.0, 0.0],
+            ..Default::default()
+        };
+        let (rates, thrust) = controller.update(&rolled, [0.0; 2], [0.5; 4]);
+        assert_eq!(rates, [0.0; 2]);
+        assert!(thrust[0] > thrust[1]);
+        assert_eq!(thrust[2], thrust[3]);
+
+        let held = controller.update(&rolled, [0.1, 0.0], [0.5; 4]).1;
+        assert_eq!(held, [0.3; 4]);
+    }
+
+    #[test]
+    fn damps_rotation() {
+        let controller = HoverController::new(HoverConfig::default());
+        // Level, but rolling right and pitching the nose down.
+        let rotating = AttitudeEstimate {
+            rates: [20.0, 10.0, 0.0],
+            ..Default::default()
+        };
+        let thrust = controller.update(&rotating, [0.0; 2], [0.5; 4]).1;
+        assert!(thrust[0] > thrust[1]);
+        assert!(thrust[2] > thrust[3]);
+        assert!((thrust[0] - thrust[1] - 2.0 * 0.2 * 20f32.to_radians()).abs() < 1e-6);
+
+        // Nose down: the front motor lifts it.
+        let pitched = AttitudeEstimate {
+            euler: [0.0, 0.1, 0.0],
+            ..Default::default()
+        };
+        let thrust = controller.update(&pitched, [0.0; 2], [0.5; 4]).1;
+        assert!(thrust[2] > thrust[3]);
+        assert_eq!(thrust[0], thrust[1]);
+    }
+}
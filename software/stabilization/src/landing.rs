@@ -0,0 +1,259 @@
+//! Automatic takeoff and landing. The takeoff ramps the thrust up to the hover thrust until the
+//! vehicle lifts off, the landing descends at a controlled rate and slows down near the ground.
+//! The touchdown is detected from low thrust, low vertical speed and low rotation.
+
+#[derive(Debug, Clone, Copy)]
+pub struct DescentConfig {
+    pub descent_rate: f32, // [m/s]
+    /// Below this height, the descent slows down to the landing rate [m].
+    pub slow_height: f32,
+    pub land_rate: f32, // [m/s]
+}
+
+impl Default for DescentConfig {
+    fn default() -> Self {
+        Self {
+            descent_rate: 1.0,
+            slow_height: 5.0,
+            land_rate: 0.5,
+        }
+    }
+}
+
+impl DescentConfig {
+    /// Climb rate [m/s, up] at the `height` [m] above the landing spot, slow if it is unknown.
+    pub fn climb_rate(&self, height: Option<f32>) -> f32 {
+        match height {
+            Some(height) if height > self.slow_height => -self.descent_rate,
+            _ => -self.land_rate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TakeoffConfig {
+    /// Time to ramp the thrust up to the hover thrust [s].
+    pub ramp_time: f32,
+    /// The ramp ends early above this vertical speed [m/s].
+    pub liftoff_speed: f32,
+}
+
+impl Default for TakeoffConfig {
+    fn default() -> Self {
+        Self {
+            ramp_time: 3.0,
+            liftoff_speed: 0.3,
+        }
+    }
+}
+
+/// Ramps the thrust up, the climb to the takeoff height is left to the throttle controller.
+pub struct TakeoffRamp {
+    config: TakeoffConfig,
+    /// Time since the start of the ramp [s], `None` if inactive.
+    elapsed: Option<f32>,
+}
+
+impl TakeoffRamp {
+    pub fn new(config: TakeoffConfig) -> Self {
+        Self {
+            config,
+            elapsed: None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        self.elapsed = Some(0.0);
+    }
+
+    pub fn stop(&mut self) {
+        self.elapsed = None;
+    }
+
+    pub fn active(&self) -> bool {
+        self.elapsed.is_some()
+    }
+
+    /// Returns the collective thrust during the ramp, `None` after the liftoff.
+    pub fn update(&mut self, hover_thrust: f32, vertical_speed: f32, dt: f32) -> Option<f32> {
+        let elapsed = self.elapsed.as_mut()?;
+        if *elapsed >= self.config.ramp_time || vertical_speed > self.config.liftoff_speed {
+            self.elapsed = None;
+            return None;
+        }
+        *elapsed += dt;
+        Some(hover_thrust * (*elapsed / self.config.ramp_time).min(1.0))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LandingDetectorConfig {
+    /// Collective thrust limit, as fraction of the hover thrust.
+    pub max_thrust: f32,
+    pub max_vertical_speed: f32, // [m/s]
+    pub max_rotation: f32,       // [deg/s]
+    /// Time all conditions need to hold [s].
+    pub time: f32,
+}
+
+impl Default for LandingDetectorConfig {
+    fn default() -> Self {
+        Self {
+            max_thrust: 0.7,
+            max_vertical_speed: 0.3,
+            max_rotation: 20.0,
+            time: 1.0,
+        }
+    }
+}
+
+pub struct LandingDetector {
+    config: LandingDetectorConfig,
+    /// Time the conditions held [s].
+    duration: f32,
+}
+
+impl LandingDetector {
+    /// Starts as landed.
+    pub fn new(config: LandingDetectorConfig) -> Self {
+        Self {
+            config,
+            duration: config.time,
+        }
+    }
+
+    /// Assumes the vehicle is on the ground, e.g. at arming.
+    pub fn reset(&mut self) {
+        self.duration = self.config.time;
+    }
+
+    pub fn landed(&self) -> bool {
+        self.duration >= self.config.time
+    }
+
+    /// `thrust` is the collective thrust, `gyro` the rotation rates [deg/s]. Without a vertical
+    /// speed estimate, no landing is detected.
+    pub fn update(
+        &mut self,
+        thrust: f32,
+        hover_thrust: f32,
+        vertical_speed: Option<f32>,
+        gyro: [f32; 3],
+        dt: f32,
+    ) -> bool {
+        let config = &self.config;
+        let rotation = libm::sqrtf(gyro.iter().map(|rate| rate * rate).sum());
+        let quiet = thrust < config.max_thrust * hover_thrust
+            && vertical_speed.is_some_and(|speed| libm::fabsf(speed) < config.max_vertical_speed)
+            && rotation < config.max_rotation;
+        self.duration = if quiet {
+            (self.duration + dt).min(config.time)
+        } else {
+            0.0
+        };
+        self.landed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::STANDARD_GRAVITY;
+    use crate::altitude::AltitudeEstimate;
+    use crate::altitude_hold::ThrottleController;
+    use crate::altitude_hold::ThrottleMode;
+
+    const DT: f32 = 0.02;
+    const HOVER: f32 = 0.45;
+
+    fn estimate(height: f32, vertical_speed: f32) -> AltitudeEstimate {
+        AltitudeEstimate {
+            altitude: height,
+            height,
+            vertical_speed,
+            altitude_std: 0.1,
+            vertical_speed_std: 0.1,
+        }
+    }
+
+    /// Vertical motion at the `thrust`, the ground stops any descent.
+    fn simulate(thrust: f32, height: &mut f32, speed: &mut f32) {
+        *speed += (thrust / HOVER - 1.0) * STANDARD_GRAVITY * DT;
+        *height += *speed * DT;
+        if *height <= 0.0 {
+            *height = 0.0;
+            *speed = speed.max(0.0);
+        }
+    }
+
+    #[test]
+    fn descent_profile() {
+        let descent = DescentConfig::default();
+        assert_eq!(descent.climb_rate(Some(20.0)), -1.0);
+        assert_eq!(descent.climb_rate(Some(3.0)), -0.5);
+        assert_eq!(descent.climb_rate(None), -0.5);
+    }
+
+    #[test]
+    fn takes_off_climbs_and_lands() {
+        let mut throttle = ThrottleController::new(Default::default());
+        let mut ramp = TakeoffRamp::new(TakeoffConfig::default());
+        let mut detector = LandingDetector::new(LandingDetectorConfig::default());
+        let descent = DescentConfig::default();
+        let (mut height, mut speed) = (0.0, 0.0);
+
+        // Lifts off during the ramp, then the throttle controller climbs to 10 m.
+        ramp.start();
+        let mut ramp_end = 0.0;
+        for step in 0..(20.0 / DT) as u32 {
+            let e = estimate(height, speed);
+            let thrust = match ramp.update(throttle.hover_thrust(), speed, DT) {
+                Some(thrust) => throttle.update(ThrottleMode::Manual, thrust, Some(&e), DT),
+                None => {
+                    if ramp_end == 0.0 {
+                        ramp_end = step as f32 * DT;
+                    }
+                    throttle.set_climb_rate(0.5 * (10.0 - height));
+                    throttle.update(ThrottleMode::Auto, 0.0, Some(&e), DT)
+                }
+            };
+            simulate(thrust, &mut height, &mut speed);
+            detector.update(thrust, throttle.hover_thrust(), Some(speed), [0.0; 3], DT);
+        }
+        assert!(ramp_end > 1.0 && ramp_end <= 3.1);
+        assert!((height - 10.0).abs() < 0.5);
+        assert!(!detector.landed());
+
+        let mut landed_at = None;
+        for step in 0..(40.0 / DT) as u32 {
+            let e = estimate(height, speed);
+            throttle.set_climb_rate(descent.climb_rate(Some(height)));
+            let thrust = throttle.update(ThrottleMode::Auto, 0.0, Some(&e), DT);
+            simulate(thrust, &mut height, &mut speed);
+            let gyro = [3.0, -2.0, 1.0];
+            if detector.update(thrust, throttle.hover_thrust(), Some(speed), gyro, DT) {
+                landed_at.get_or_insert(step as f32 * DT);
+                assert_eq!(height, 0.0);
+            }
+        }
+        // 5 m at 1 m/s and 5 m at 0.5 m/s, then up to a few seconds to detect the touchdown.
+        let landed_at = landed_at.unwrap();
+        assert!(landed_at > 15.0 && landed_at < 20.0);
+    }
+
+    #[test]
+    fn no_landing_while_rotating_or_without_estimate() {
+        let mut detector = LandingDetector::new(LandingDetectorConfig::default());
+        assert!(detector.landed());
+        for _ in 0..(2.0 / DT) as u32 {
+            detector.update(0.1, HOVER, Some(0.0), [0.0, 30.0, 0.0], DT);
+        }
+        assert!(!detector.landed());
+        for _ in 0..(2.0 / DT) as u32 {
+            detector.update(0.1, HOVER, None, [0.0; 3], DT);
+        }
+        assert!(!detector.landed());
+        detector.reset();
+        assert!(detector.landed());
+    }
+}
@@ -1,51 +1,199 @@
 #![no_std]
 
-use fusion_ahrs::Ahrs;
-use nalgebra::UnitQuaternion;
-use nalgebra::Vector3;
+pub mod allocation;
+pub mod altitude;
+pub mod altitude_hold;
+pub mod attitude;
+pub mod calibration;
+pub mod esc_calibration;
+pub mod eskf;
+pub mod fixed_wing;
+pub mod geofence;
+pub mod hover;
+pub mod landing;
+pub mod madgwick;
+pub mod magnetometer;
+pub mod mahony;
+pub mod mission;
+pub mod motor_test;
+pub mod navigation;
+pub mod output;
+pub mod position_hold;
+pub mod return_home;
+pub mod servo;
+pub mod transition;
+pub mod yaw;
+
+#[cfg(test)]
+mod test_util;
+
+use core::f32::consts::PI;
+
+use attitude::AttitudeBackend;
+use attitude::AttitudeEstimate;
+use attitude::AttitudeEstimator;
+use attitude::Estimator;
+use magnetometer::InterferenceDetector;
+
+pub const STANDARD_GRAVITY: f32 = 9.80665; // [m/s^2]
+/// Without magnetometer corrections, the gyro-only heading is trusted for this long [s].
+const HEADING_TIMEOUT: f32 = 30.0;
 
 pub struct Kf {
-    ahrs: Ahrs,
+    estimator: Estimator,
+    estimate: AttitudeEstimate,
     dt: f32,
+    declination: f32,
+    interference: InterferenceDetector,
+    /// Time since the magnetometer last corrected the converged heading [s], `None` if it never
+    /// did.
+    magnetometer_age: Option<f32>,
 }
 
 impl Kf {
-    pub fn new(dt: f32) -> Self {
+    pub fn new(dt: f32, backend: AttitudeBackend) -> Self {
         Self {
-            ahrs: Ahrs::new(),
+            estimator: Estimator::new(backend),
+            estimate: AttitudeEstimate::default(),
             dt,
+            declination: 0.0,
+            interference: InterferenceDetector::default(),
+            magnetometer_age: None,
+        }
+    }
+
+    pub fn backend(&self) -> AttitudeBackend {
+        self.estimator.backend()
+    }
+
+    /// Switches the estimation backend, which restarts the estimation.
+    pub fn set_backend(&mut self, backend: AttitudeBackend) {
+        if backend != self.backend() {
+            self.estimator = Estimator::new(backend);
+            self.estimate = AttitudeEstimate::default();
+            self.magnetometer_age = None;
         }
     }
 
+    /// Sets the magnetic declination [rad], positive east.
+    pub fn set_declination(&mut self, declination: f32) {
+        self.declination = declination;
+    }
+
+    /// The estimate of the last `update`.
+    pub fn estimate(&self) -> &AttitudeEstimate {
+        &self.estimate
+    }
+
+    /// True heading [rad] in `-PI..PI`, counter-clockwise like the yaw. `None` without a recent
+    /// magnetometer reference, e.g. without a magnetometer, the yaw is then only relative to the
+    /// start.
+    pub fn heading(&self) -> Option<f32> {
+        if !self
+            .magnetometer_age
+            .is_some_and(|age| age < HEADING_TIMEOUT)
+        {
+            return None;
+        }
+        // The yaw is counter-clockwise, the declination clockwise from true north.
+        let heading = self.estimate.euler[2] - self.declination;
+        Some(if heading > PI {
+            heading - 2.0 * PI
+        } else if heading < -PI {
+            heading + 2.0 * PI
+        } else {
+            heading
+        })
+    }
+
+    /// Acceleration without gravity [m/s^2, north, east, down] relative to true north, of the last
+    /// `update`.
+    pub fn navigation_acceleration(&self) -> [f32; 3] {
+        let [north, west, up] = self.estimate.earth_acceleration;
+        let (sin, cos) = libm::sincosf(self.declination);
+        [north * cos + west * sin, north * sin - west * cos, -up]
+    }
+
+    /// Earth-frame vertical acceleration without gravity [m/s^2, up] of the last `update`.
+    pub fn vertical_acceleration(&self) -> f32 {
+        self.estimate.earth_acceleration[2]
+    }
+
+    /// Whether the heading is currently gyro-only because of magnetic interference.
+    pub fn magnetometer_disturbed(&self) -> bool {
+        self.interference.disturbed()
+    }
+
+    /// `mag` is the calibrated magnetometer reading, if there is a magnetometer.
     pub fn update(
         &mut self,
         gyro: [f32; 3],
         accel: [f32; 3],
-        thrust: [f32; 4],
-    ) -> ([f32; 2], [f32; 4]) {
-        self.ahrs
-            .update_no_magnetometer(Vector3::from(gyro), Vector3::from(accel), self.dt);
-        let quat: UnitQuaternion<f32> = self.ahrs.quaternion();
-        let (roll, pitch, _yaw) = quat.euler_angles();
-
-        // Tuning values of PD controller:
-        // (1) Start with low values (kp = 1..3, kd = 0.1..0.3).
-        // (2) Increase kp until the quad responds fast enough but does not oscillate.
-        // (3) Increase kd to damp oscillations / smooth response.
-        // (4) Adjust hover base thrust to maintain altitude.
-        let kp = 3.0;
-        let kd = 0.2;
-        let roll_rate = gyro[0];
-        let pitch_rate = gyro[1];
-
-        (
-            [roll_rate, pitch_rate],
-            [
-                thrust[0] * 0.6 + kp * roll - kd * roll_rate,
-                thrust[1] * 0.6 - kp * roll - kd * roll_rate,
-                thrust[2] * 0.6 - kp * pitch - kd * pitch_rate,
-                thrust[3] * 0.6 + kp * pitch - kd * pitch_rate,
-            ],
-        )
+        mag: Option<[f32; 3]>,
+    ) -> AttitudeEstimate {
+        let mag = mag.filter(|mag| self.interference.update(*mag, accel));
+        self.estimate = self.estimator.update(gyro, accel, mag, self.dt);
+        self.magnetometer_age = if self.estimate.magnetometer && self.estimate.converged {
+            Some(0.0)
+        } else {
+            self.magnetometer_age.map(|age| age + self.dt)
+        };
+        self.estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::attitude::tests::EARTH_FIELD;
+
+    const DT: f32 = 0.02;
+
+    fn run(kf: &mut Kf, mag: Option<[f32; 3]>, seconds: f32) {
+        for _ in 0..(seconds / DT) as u32 {
+            kf.update([0.0; 3], [0.0, 0.0, 1.0], mag);
+        }
+    }
+
+    #[test]
+    fn heading_needs_magnetometer() {
+        // The position control falls back to altitude hold without a heading.
+        let mut kf = Kf::new(DT, AttitudeBackend::Mahony);
+        run(&mut kf, None, 10.0);
+        assert!(kf.estimate().converged);
+        assert_eq!(kf.heading(), None);
+
+        run(&mut kf, Some(EARTH_FIELD), 5.0);
+        assert!(kf.heading().unwrap().abs() < 0.01);
+
+        // Gyro-only for a while, e.g. because of magnetic interference.
+        run(&mut kf, None, HEADING_TIMEOUT - 1.0);
+        assert!(kf.heading().is_some());
+        run(&mut kf, None, 2.0);
+        assert_eq!(kf.heading(), None);
+
+        run(&mut kf, Some(EARTH_FIELD), 1.0);
+        assert!(kf.heading().is_some());
+        kf.set_backend(AttitudeBackend::Eskf);
+        assert_eq!(kf.heading(), None);
+    }
+
+    #[test]
+    fn declination() {
+        let mut kf = Kf::new(DT, AttitudeBackend::Mahony);
+        run(&mut kf, Some(EARTH_FIELD), 5.0);
+        kf.set_declination(0.2);
+        kf.estimate.euler[2] = 0.5;
+        assert!((kf.heading().unwrap() - 0.3).abs() < 1e-6);
+        kf.estimate.euler[2] = -3.0;
+        assert!((kf.heading().unwrap() - (2.0 * PI - 3.2)).abs() < 1e-5);
+
+        // Magnetic north is 0.2 rad east of true north.
+        kf.estimate.earth_acceleration = [1.0, 0.0, -0.5];
+        let [north, east, down] = kf.navigation_acceleration();
+        assert!((north - libm::cosf(0.2)).abs() < 1e-6);
+        assert!((east - libm::sinf(0.2)).abs() < 1e-6);
+        assert_eq!(down, 0.5);
     }
 }
@@ -0,0 +1,109 @@
+//! Madgwick filter: the gyro integration is corrected by a normalized gradient descent step
+//! towards the measured references, with a fixed rate `beta`.
+
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+
+use crate::attitude::AttitudeEstimate;
+use crate::attitude::AttitudeEstimator;
+use crate::attitude::CONVERGENCE_TIME;
+use crate::attitude::reference_error;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MadgwickConfig {
+    /// Correction rate, about the gyro measurement error [rad/s].
+    pub beta: f32,
+    /// Gyro bias drift rate [rad/s^2].
+    pub zeta: f32,
+    /// Correction rate until converged, for fast initial alignment [rad/s].
+    pub initial_beta: f32,
+}
+
+impl Default for MadgwickConfig {
+    fn default() -> Self {
+        Self {
+            beta: 0.05,
+            zeta: 0.01,
+            initial_beta: 2.5,
+        }
+    }
+}
+
+pub struct Madgwick {
+    config: MadgwickConfig,
+    quaternion: UnitQuaternion<f32>,
+    /// Gyro bias [rad/s].
+    bias: Vector3<f32>,
+    elapsed: f32,
+}
+
+impl Madgwick {
+    pub fn new(config: MadgwickConfig) -> Self {
+        Self {
+            config,
+            quaternion: UnitQuaternion::identity(),
+            bias: Vector3::zeros(),
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl AttitudeEstimator for Madgwick {
+    fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        dt: f32,
+    ) -> AttitudeEstimate {
+        // The negative gradient of the reference error, as body-frame rotation.
+        let step = reference_error(&self.quaternion, accel, mag)
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros);
+        let beta = if self.elapsed < CONVERGENCE_TIME {
+            self.config.initial_beta
+        } else {
+            self.bias -= step * self.config.zeta * dt;
+            self.config.beta
+        };
+        let rates = Vector3::from(gyro).map(f32::to_radians) - self.bias;
+        self.quaternion *= UnitQuaternion::from_scaled_axis((rates + step * beta) * dt);
+        self.quaternion.renormalize();
+        self.elapsed += dt;
+        AttitudeEstimate::new(
+            self.quaternion,
+            rates.map(f32::to_degrees).into(),
+            accel,
+            mag,
+            self.elapsed,
+        )
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attitude::tests::run;
+
+    #[test]
+    fn aligns() {
+        let mut madgwick = Madgwick::new(MadgwickConfig::default());
+        let attitude = UnitQuaternion::from_euler_angles(-0.5, 0.3, -2.5);
+        let estimate = run(&mut madgwick, [0.0; 3], &attitude, 4.0);
+        assert!(estimate.converged);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.01);
+    }
+
+    #[test]
+    fn tracks_with_gyro_bias() {
+        let mut madgwick = Madgwick::new(MadgwickConfig::default());
+        let attitude = UnitQuaternion::from_euler_angles(0.2, 0.1, 0.5);
+        let estimate = run(&mut madgwick, [0.5, -0.5, 1.0], &attitude, 120.0);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.02);
+        assert!(estimate.rates.iter().all(|rate| rate.abs() < 0.2));
+    }
+}
@@ -0,0 +1,273 @@
+//! Magnetometer calibration (hard and soft iron) and interference detection.
+
+use nalgebra::Matrix3;
+use nalgebra::SMatrix;
+use nalgebra::SVector;
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::calibration::CalibrationError;
+
+/// Hard-iron offset and soft-iron correction matrix.
+///
+/// Calibrated readings have unit length in an undisturbed environment.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MagCalibration {
+    pub offset: [f32; 3],
+    pub soft_iron: [[f32; 3]; 3], // row-major
+}
+
+impl Default for MagCalibration {
+    fn default() -> Self {
+        Self {
+            offset: [0.0; 3],
+            soft_iron: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+}
+
+impl MagCalibration {
+    pub fn apply(&self, mag: [f32; 3]) -> [f32; 3] {
+        let centered = [
+            mag[0] - self.offset[0],
+            mag[1] - self.offset[1],
+            mag[2] - self.offset[2],
+        ];
+        let mut out = [0.0; 3];
+        for (row, value) in self.soft_iron.iter().zip(out.iter_mut()) {
+            *value = row[0] * centered[0] + row[1] * centered[1] + row[2] * centered[2];
+        }
+        out
+    }
+}
+
+/// Collects magnetometer samples while the vehicle is rotated in all directions and fits an
+/// ellipsoid to them.
+pub struct MagCalibrator {
+    samples: [[f32; 3]; Self::MAX_SAMPLES],
+    count: usize,
+    min_distance: f32,
+}
+
+impl MagCalibrator {
+    pub const MAX_SAMPLES: usize = 200;
+    const MIN_SAMPLES: usize = 50;
+
+    /// Samples closer than `min_distance` to the last accepted one are dropped, so that
+    /// holding the vehicle still does not fill the buffer.
+    pub fn new(min_distance: f32) -> Self {
+        Self {
+            samples: [[0.0; 3]; Self::MAX_SAMPLES],
+            count: 0,
+            min_distance,
+        }
+    }
+
+    /// Adds a raw sample, returns true once the buffer is full.
+    pub fn add(&mut self, mag: [f32; 3]) -> bool {
+        if self.count >= Self::MAX_SAMPLES {
+            return true;
+        }
+        if self.count > 0 {
+            let last = Vector3::from(self.samples[self.count - 1]);
+            if (Vector3::from(mag) - last).norm() < self.min_distance {
+                return false;
+            }
+        }
+        self.samples[self.count] = mag;
+        self.count += 1;
+        self.count >= Self::MAX_SAMPLES
+    }
+
+    /// Least-squares fit of `x'Ax + 2g'x = 1` to the collected samples.
+    pub fn finish(&self) -> Result<MagCalibration, CalibrationError> {
+        if self.count < Self::MIN_SAMPLES {
+            return Err(CalibrationError::Incomplete);
+        }
+        let samples = &self.samples[..self.count];
+
+        // Normalize the samples to keep the normal equations well conditioned in f32.
+        let mut mean = Vector3::zeros();
+        for sample in samples {
+            mean += Vector3::from(*sample);
+        }
+        mean /= self.count as f32;
+        let scale = samples
+            .iter()
+            .map(|s| (Vector3::from(*s) - mean).amax())
+            .fold(0.0f32, f32::max);
+        if scale <= 0.0 {
+            return Err(CalibrationError::Fit);
+        }
+
+        let mut normal = SMatrix::<f32, 9, 9>::zeros();
+        let mut rhs = SVector::<f32, 9>::zeros();
+        for sample in samples {
+            let v = (Vector3::from(*sample) - mean) / scale;
+            let row = SVector::<f32, 9>::from([
+                v.x * v.x,
+                v.y * v.y,
+                v.z * v.z,
+                2.0 * v.x * v.y,
+                2.0 * v.x * v.z,
+                2.0 * v.y * v.z,
+                2.0 * v.x,
+                2.0 * v.y,
+                2.0 * v.z,
+            ]);
+            normal += row * row.transpose();
+            rhs += row;
+        }
+        let p = normal.cholesky().ok_or(CalibrationError::Fit)?.solve(&rhs);
+
+        let a = Matrix3::new(p[0], p[3], p[4], p[3], p[1], p[5], p[4], p[5], p[2]);
+        let g = Vector3::new(p[6], p[7], p[8]);
+        let center = -(a.try_inverse().ok_or(CalibrationError::Fit)? * g);
+        let k = 1.0 + center.dot(&(a * center));
+        let q = a / k;
+
+        let eigen = q.symmetric_eigen();
+        if eigen.eigenvalues.iter().any(|l| *l <= 0.0) {
+            return Err(CalibrationError::Fit);
+        }
+        let sqrt = Matrix3::from_diagonal(&eigen.eigenvalues.map(libm::sqrtf));
+        let soft_iron = eigen.eigenvectors * sqrt * eigen.eigenvectors.transpose() / scale;
+        let offset = mean + center * scale;
+
+        Ok(MagCalibration {
+            offset: offset.into(),
+            soft_iron: [
+                [soft_iron[(0, 0)], soft_iron[(0, 1)], soft_iron[(0, 2)]],
+                [soft_iron[(1, 0)], soft_iron[(1, 1)], soft_iron[(1, 2)]],
+                [soft_iron[(2, 0)], soft_iron[(2, 1)], soft_iron[(2, 2)]],
+            ],
+        })
+    }
+}
+
+/// Detects magnetic disturbances from the field strength and the inclination (dip) angle.
+///
+/// Once disturbed, the magnetometer stays unused until `recovery_samples` consecutive samples
+/// look undisturbed again.
+pub struct InterferenceDetector {
+    /// Maximum deviation of the calibrated field strength from 1.0.
+    pub magnitude_tolerance: f32,
+    /// Maximum deviation of the dip angle from the learned reference [rad].
+    pub dip_tolerance: f32,
+    pub recovery_samples: u32,
+    reference_dip: Option<f32>,
+    good_samples: u32,
+}
+
+impl Default for InterferenceDetector {
+    fn default() -> Self {
+        Self {
+            magnitude_tolerance: 0.2,
+            dip_tolerance: 0.17,
+            recovery_samples: 50,
+            reference_dip: None,
+            good_samples: 0,
+        }
+    }
+}
+
+impl InterferenceDetector {
+    /// Checks a calibrated magnetometer sample, returns true if it can be used for heading.
+    pub fn update(&mut self, mag: [f32; 3], accel: [f32; 3]) -> bool {
+        let mag = Vector3::from(mag);
+        let accel = Vector3::from(accel);
+        let magnitude = mag.norm();
+        let accel_norm = accel.norm();
+        if accel_norm <= 0.0 || magnitude <= 0.0 {
+            self.good_samples = 0;
+            return false;
+        }
+        let dip = libm::asinf((mag.dot(&accel) / (magnitude * accel_norm)).clamp(-1.0, 1.0));
+
+        let magnitude_ok = libm::fabsf(magnitude - 1.0) <= self.magnitude_tolerance;
+        let dip_ok = match self.reference_dip {
+            Some(reference) => libm::fabsf(dip - reference) <= self.dip_tolerance,
+            None => true,
+        };
+        if !(magnitude_ok && dip_ok) {
+            self.good_samples = 0;
+            return false;
+        }
+
+        self.good_samples = self.good_samples.saturating_add(1);
+        if self.good_samples < self.recovery_samples {
+            return false;
+        }
+        // Slowly track the local dip angle while undisturbed.
+        self.reference_dip = Some(match self.reference_dip {
+            Some(reference) => reference + (dip - reference) * 0.01,
+            None => dip,
+        });
+        true
+    }
+
+    pub fn disturbed(&self) -> bool {
+        self.good_samples < self.recovery_samples
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere_points(mut f: impl FnMut([f32; 3])) {
+        for i in 0..12 {
+            for j in 0..12 {
+                let theta = core::f32::consts::PI * (i as f32 + 0.5) / 12.0;
+                let phi = 2.0 * core::f32::consts::PI * j as f32 / 12.0;
+                f([
+                    libm::sinf(theta) * libm::cosf(phi),
+                    libm::sinf(theta) * libm::sinf(phi),
+                    libm::cosf(theta),
+                ]);
+            }
+        }
+    }
+
+    #[test]
+    fn ellipsoid_fit() {
+        let offset = Vector3::new(12.0, -30.0, 5.0);
+        let distortion = Matrix3::new(45.0, 3.0, 0.0, 3.0, 40.0, -2.0, 0.0, -2.0, 50.0);
+        let mut calibrator = MagCalibrator::new(0.0);
+        sphere_points(|p| {
+            calibrator.add((distortion * Vector3::from(p) + offset).into());
+        });
+        let calibration = calibrator.finish().unwrap();
+        for i in 0..3 {
+            assert!((calibration.offset[i] - offset[i]).abs() < 0.05);
+        }
+        sphere_points(|p| {
+            let raw: [f32; 3] = (distortion * Vector3::from(p) + offset).into();
+            let norm = Vector3::from(calibration.apply(raw)).norm();
+            assert!((norm - 1.0).abs() < 1e-3);
+        });
+    }
+
+    #[test]
+    fn ellipsoid_fit_needs_coverage() {
+        let mut calibrator = MagCalibrator::new(0.0);
+        for _ in 0..100 {
+            calibrator.add([1.0, 2.0, 3.0]);
+        }
+        assert!(calibrator.finish().is_err());
+    }
+
+    #[test]
+    fn interference_detected() {
+        let mut detector = InterferenceDetector::default();
+        let accel = [0.0, 0.0, 1.0];
+        let mag = [0.6, 0.0, 0.8];
+        for _ in 0..detector.recovery_samples {
+            detector.update(mag, accel);
+        }
+        assert!(detector.update(mag, accel));
+        assert!(!detector.update([1.2, 0.0, 1.6], accel));
+        assert!(detector.disturbed());
+        assert!(!detector.update([0.8, 0.0, 0.6], accel));
+    }
+}
@@ -0,0 +1,114 @@
+//! Mahony complementary filter: the gyro rates are corrected by a PI controller on the difference
+//! between the measured and the estimated references.
+
+use nalgebra::UnitQuaternion;
+use nalgebra::Vector3;
+
+use crate::attitude::AttitudeEstimate;
+use crate::attitude::AttitudeEstimator;
+use crate::attitude::CONVERGENCE_TIME;
+use crate::attitude::reference_error;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MahonyConfig {
+    /// Proportional gain [rad/s].
+    pub kp: f32,
+    /// Integral gain for the gyro bias [rad/s^2].
+    pub ki: f32,
+    /// Proportional gain until converged, for fast initial alignment [rad/s].
+    pub initial_kp: f32,
+}
+
+impl Default for MahonyConfig {
+    fn default() -> Self {
+        Self {
+            kp: 1.0,
+            ki: 0.05,
+            initial_kp: 10.0,
+        }
+    }
+}
+
+pub struct Mahony {
+    config: MahonyConfig,
+    quaternion: UnitQuaternion<f32>,
+    /// Gyro bias [rad/s].
+    bias: Vector3<f32>,
+    elapsed: f32,
+}
+
+impl Mahony {
+    pub fn new(config: MahonyConfig) -> Self {
+        Self {
+            config,
+            quaternion: UnitQuaternion::identity(),
+            bias: Vector3::zeros(),
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl AttitudeEstimator for Mahony {
+    fn update(
+        &mut self,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+        mag: Option<[f32; 3]>,
+        dt: f32,
+    ) -> AttitudeEstimate {
+        let error = reference_error(&self.quaternion, accel, mag);
+        let kp = if self.elapsed < CONVERGENCE_TIME {
+            self.config.initial_kp
+        } else {
+            // The bias is only learnt once aligned, the initial error would wind it up.
+            self.bias -= error * self.config.ki * dt;
+            self.config.kp
+        };
+        let rates = Vector3::from(gyro).map(f32::to_radians) - self.bias;
+        self.quaternion *= UnitQuaternion::from_scaled_axis((rates + error * kp) * dt);
+        self.quaternion.renormalize();
+        self.elapsed += dt;
+        AttitudeEstimate::new(
+            self.quaternion,
+            rates.map(f32::to_degrees).into(),
+            accel,
+            mag,
+            self.elapsed,
+        )
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attitude::tests::readings;
+    use crate::attitude::tests::run;
+
+    #[test]
+    fn aligns() {
+        let mut mahony = Mahony::new(MahonyConfig::default());
+        let attitude = UnitQuaternion::from_euler_angles(0.4, -0.3, 2.0);
+        let estimate = run(&mut mahony, [0.0; 3], &attitude, 4.0);
+        assert!(estimate.converged);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.01);
+    }
+
+    #[test]
+    fn learns_gyro_bias() {
+        let mut mahony = Mahony::new(MahonyConfig::default());
+        let attitude = UnitQuaternion::from_euler_angles(0.1, 0.2, -1.0);
+        let bias = [0.5, -1.0, 2.0];
+        let estimate = run(&mut mahony, bias, &attitude, 120.0);
+        assert!(estimate.quaternion.angle_to(&attitude) < 0.01);
+        assert!(estimate.rates.iter().all(|rate| rate.abs() < 0.05));
+
+        let (accel, _) = readings(&attitude);
+        mahony.reset();
+        let estimate = mahony.update(bias, accel, None, 0.01);
+        assert!(!estimate.converged && !estimate.magnetometer);
+    }
+}
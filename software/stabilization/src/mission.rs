@@ -0,0 +1,606 @@
+//! Waypoint missions: the mission items and the executor sequencing them.
+//!
+//! In hover, the executor commands positions for the position controller. In fixed-wing flight,
+//! legs and loiter circles are followed with L1 guidance, which steers towards a reference point
+//! at the distance L1 ahead on the path and commands a bank angle.
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::STANDARD_GRAVITY;
+use crate::landing::DescentConfig;
+use crate::navigation::LocalFrame;
+use crate::navigation::NavigationEstimate;
+use crate::transition::TransitionState;
+
+pub const MAX_ITEMS: usize = 32;
+
+/// Altitudes are above the home position.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum MissionItem {
+    /// Climbs vertically to the altitude [m].
+    Takeoff { altitude: f32 },
+    Waypoint {
+        latitude: i32,  // [1e-7 deg]
+        longitude: i32, // [1e-7 deg]
+        altitude: f32,  // [m]
+    },
+    /// Holds the position (in hover) or circles clockwise (in fixed-wing flight) for some time.
+    Loiter {
+        latitude: i32,  // [1e-7 deg]
+        longitude: i32, // [1e-7 deg]
+        altitude: f32,  // [m]
+        radius: f32,    // [m]
+        time: f32,      // [s]
+    },
+    /// Transition to forward flight or back to hover.
+    Transition { forward: bool },
+    /// Lands at the position (latitude and longitude [1e-7 deg]), or at the current one.
+    Land { position: Option<[i32; 2]> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissionError {
+    /// Items need to be added in order.
+    OutOfSequence,
+    TooManyItems,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mission {
+    items: [Option<MissionItem>; MAX_ITEMS],
+}
+
+impl Default for Mission {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mission {
+    pub fn new() -> Self {
+        Self {
+            items: [None; MAX_ITEMS],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.iter().take_while(|item| item.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items[0].is_none()
+    }
+
+    pub fn get(&self, index: usize) -> Option<MissionItem> {
+        self.items.get(index).copied().flatten()
+    }
+
+    /// Appends the item at `index`, index 0 starts a new mission.
+    pub fn set(&mut self, index: usize, item: MissionItem) -> Result<(), MissionError> {
+        if index >= MAX_ITEMS {
+            return Err(MissionError::TooManyItems);
+        }
+        if index == 0 {
+            *self = Self::new();
+        } else if index != self.len() {
+            return Err(MissionError::OutOfSequence);
+        }
+        self.items[index] = Some(item);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MissionConfig {
+    /// Horizontal speed in hover [m/s].
+    pub speed: f32,
+    /// Distance at which a waypoint is reached in hover [m].
+    pub acceptance_radius: f32,
+    /// Distance at which a waypoint is reached in fixed-wing flight [m].
+    pub fixed_wing_acceptance_radius: f32,
+    /// Altitude error at which a waypoint is reached [m].
+    pub altitude_tolerance: f32,
+    /// Climb rate per meter of altitude error [1/s].
+    pub altitude_gain: f32,
+    pub climb_rate: f32, // [m/s]
+    /// Descent above home, also limits the descent between waypoints.
+    pub descent: DescentConfig,
+    /// Period [s] and damping of the L1 guidance.
+    pub l1_period: f32,
+    pub l1_damping: f32,
+    /// Lower limit of the L1 distance [m].
+    pub min_l1_distance: f32,
+    pub max_bank: f32, // [rad]
+}
+
+impl Default for MissionConfig {
+    fn default() -> Self {
+        Self {
+            speed: 5.0,
+            acceptance_radius: 2.0,
+            fixed_wing_acceptance_radius: 30.0,
+            altitude_tolerance: 1.0,
+            altitude_gain: 0.5,
+            climb_rate: 2.0,
+            descent: Default::default(),
+            l1_period: 20.0,
+            l1_damping: 0.75,
+            min_l1_distance: 10.0,
+            max_bank: 35f32.to_radians(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MissionOutput {
+    /// North and east position [m] for the position controller in hover.
+    pub target: [f32; 2],
+    /// Horizontal speed limit in hover [m/s].
+    pub speed: f32,
+    /// Climb rate [m/s, up].
+    pub climb_rate: f32,
+    /// Bank angle [rad, positive right] in fixed-wing flight.
+    pub bank: f32,
+    /// Requests forward flight.
+    pub forward: bool,
+    /// Descending to land.
+    pub land: bool,
+}
+
+pub struct MissionExecutor {
+    config: MissionConfig,
+    index: usize,
+    /// Start of the current leg [m, north and east], for the path following.
+    leg_start: [f32; 2],
+    /// Position held during takeoff, transitions and after the mission [m].
+    hold: [f32; 2],
+    forward: bool,
+    /// Time spent at the loiter position [s].
+    loiter_time: f32,
+}
+
+impl MissionExecutor {
+    pub fn new(config: MissionConfig) -> Self {
+        Self {
+            config,
+            index: 0,
+            leg_start: [0.0; 2],
+            hold: [0.0; 2],
+            forward: false,
+            loiter_time: 0.0,
+        }
+    }
+
+    /// Index of the active item.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn finished(&self, mission: &Mission) -> bool {
+        self.index >= mission.len()
+    }
+
+    /// Starts the mission from the first item, `forward` if in forward flight.
+    pub fn start(&mut self, estimate: &NavigationEstimate, forward: bool) {
+        self.index = 0;
+        self.forward = forward;
+        self.enter(estimate);
+    }
+
+    /// `home` is the north, east, down home position [m] in the local `frame`. After the last
+    /// item, the position is held.
+    pub fn update(
+        &mut self,
+        mission: &Mission,
+        frame: &LocalFrame,
+        home: [f32; 3],
+        transition: TransitionState,
+        estimate: &NavigationEstimate,
+        dt: f32,
+    ) -> MissionOutput {
+        let config = self.config;
+        let position = [estimate.position[0], estimate.position[1]];
+        let height = home[2] - estimate.position[2];
+        let fixed_wing = transition == TransitionState::FixedWing;
+        let local = |latitude, longitude| {
+            let [north, east, _] = frame.to_local(latitude, longitude, frame.altitude);
+            [north, east]
+        };
+        let mut output = MissionOutput {
+            target: self.hold,
+            speed: config.speed,
+            climb_rate: 0.0,
+            bank: 0.0,
+            forward: self.forward,
+            land: false,
+        };
+
+        let done = match mission.get(self.index) {
+            None => {
+                output.forward = false;
+                false
+            }
+            Some(MissionItem::Takeoff { altitude }) => {
+                output.climb_rate = self.climb_rate(altitude, height);
+                height >= altitude - config.altitude_tolerance
+            }
+            Some(MissionItem::Waypoint {
+                latitude,
+                longitude,
+                altitude,
+            }) => {
+                let target = local(latitude, longitude);
+                output.target = target;
+                output.climb_rate = self.climb_rate(altitude, height);
+                let distance = distance(position, target);
+                if fixed_wing {
+                    output.bank = self.follow_leg(self.leg_start, target, estimate);
+                    // Also reached if passed.
+                    let leg = sub(target, self.leg_start);
+                    distance < config.fixed_wing_acceptance_radius
+                        || dot(leg, sub(position, target)) > 0.0
+                } else {
+                    distance < config.acceptance_radius
+                        && libm::fabsf(altitude - height) < config.altitude_tolerance
+                }
+            }
+            Some(MissionItem::Loiter {
+                latitude,
+                longitude,
+                altitude,
+                radius,
+                time,
+            }) => {
+                let centre = local(latitude, longitude);
+                output.target = centre;
+                output.climb_rate = self.climb_rate(altitude, height);
+                let distance = distance(position, centre);
+                let arrived = if fixed_wing {
+                    output.bank = self.follow_circle(centre, radius, estimate);
+                    libm::fabsf(distance - radius) < config.fixed_wing_acceptance_radius
+                } else {
+                    distance < config.acceptance_radius
+                };
+                if arrived {
+                    self.loiter_time += dt;
+                }
+                self.loiter_time >= time
+            }
+            Some(MissionItem::Transition { forward }) => {
+                self.forward = forward;
+                output.forward = forward;
+                // No horizontal correction in hover while accelerating or decelerating.
+                output.target = position;
+                let state = if forward {
+                    TransitionState::FixedWing
+                } else {
+                    TransitionState::Hover
+                };
+                transition == state
+            }
+            Some(MissionItem::Land { position: target }) => {
+                self.forward = false;
+                output.forward = false;
+                let target = target.map_or(self.hold, |[latitude, longitude]| {
+                    local(latitude, longitude)
+                });
+                output.target = target;
+                if fixed_wing {
+                    output.bank = self.follow_leg(self.leg_start, target, estimate);
+                } else if distance(position, target) < config.acceptance_radius {
+                    output.climb_rate = config.descent.climb_rate(Some(height));
+                    output.land = true;
+                }
+                false
+            }
+        };
+        if done {
+            self.index += 1;
+            self.enter(estimate);
+        }
+        output
+    }
+
+    fn enter(&mut self, estimate: &NavigationEstimate) {
+        let position = [estimate.position[0], estimate.position[1]];
+        self.leg_start = position;
+        self.hold = position;
+        self.loiter_time = 0.0;
+    }
+
+    fn climb_rate(&self, altitude: f32, height: f32) -> f32 {
+        let config = &self.config;
+        (config.altitude_gain * (altitude - height))
+            .clamp(-config.descent.descent_rate, config.climb_rate)
+    }
+
+    /// Distance of the L1 reference point [m] at the ground speed.
+    fn l1_distance(&self, speed: f32) -> f32 {
+        let config = &self.config;
+        (config.l1_damping * config.l1_period * speed / core::f32::consts::PI)
+            .max(config.min_l1_distance)
+    }
+
+    /// Bank angle to fly towards `target` [m, north and east] in fixed-wing flight outside of
+    /// the mission, e.g. on the way home.
+    pub fn bank_to(&self, target: [f32; 2], estimate: &NavigationEstimate) -> f32 {
+        self.pursue(target, estimate)
+    }
+
+    /// Follows the leg from `start` to `end` [m, north and east], returns the bank angle.
+    fn follow_leg(&self, start: [f32; 2], end: [f32; 2], estimate: &NavigationEstimate) -> f32 {
+        let position = [estimate.position[0], estimate.position[1]];
+        let leg = sub(end, start);
+        let length = norm(leg);
+        if length < 1e-3 {
+            return self.pursue(end, estimate);
+        }
+        let direction = leg.map(|x| x / length);
+        let along = dot(sub(position, start), direction);
+        let cross = cross(direction, sub(position, start));
+        let l1 = self.l1_distance(speed(estimate));
+        // The reference point is on the path, L1 away from the vehicle if possible.
+        let ahead = libm::sqrtf((l1 * l1 - cross * cross).max(0.0));
+        let reference = [0, 1].map(|i| start[i] + direction[i] * (along + ahead));
+        self.pursue(reference, estimate)
+    }
+
+    /// Circles clockwise around `centre` [m, north and east], returns the bank angle.
+    fn follow_circle(&self, centre: [f32; 2], radius: f32, estimate: &NavigationEstimate) -> f32 {
+        let position = [estimate.position[0], estimate.position[1]];
+        let offset = sub(position, centre);
+        let l1 = self.l1_distance(speed(estimate));
+        // The reference point is on the circle, about L1 ahead.
+        let bearing = libm::atan2f(offset[1], offset[0])
+            + (l1 / radius.max(1.0)).min(core::f32::consts::FRAC_PI_2);
+        let (sin, cos) = libm::sincosf(bearing);
+        self.pursue(
+            [centre[0] + radius * cos, centre[1] + radius * sin],
+            estimate,
+        )
+    }
+
+    /// Steers towards `reference`, returns the bank angle for the lateral acceleration.
+    fn pursue(&self, reference: [f32; 2], estimate: &NavigationEstimate) -> f32 {
+        let config = &self.config;
+        let position = [estimate.position[0], estimate.position[1]];
+        let velocity = [estimate.velocity[0], estimate.velocity[1]];
+        let line_of_sight = sub(reference, position);
+        let distance = norm(line_of_sight).max(1e-3);
+        // Angle between the velocity and the line of sight, positive to the right.
+        let angle = libm::atan2f(cross(velocity, line_of_sight), dot(velocity, line_of_sight))
+            .clamp(-core::f32::consts::FRAC_PI_2, core::f32::consts::FRAC_PI_2);
+        let gain = 4.0 * config.l1_damping * config.l1_damping;
+        let speed = speed(estimate);
+        let acceleration = gain * speed * speed * libm::sinf(angle) / distance;
+        libm::atanf(acceleration / STANDARD_GRAVITY).clamp(-config.max_bank, config.max_bank)
+    }
+}
+
+fn sub(a: [f32; 2], b: [f32; 2]) -> [f32; 2] {
+    [a[0] - b[0], a[1] - b[1]]
+}
+
+fn dot(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+/// Positive if `b` points to the right of `a` (north-east coordinates).
+fn cross(a: [f32; 2], b: [f32; 2]) -> f32 {
+    a[0] * b[1] - a[1] * b[0]
+}
+
+fn norm(a: [f32; 2]) -> f32 {
+    libm::sqrtf(dot(a, a))
+}
+
+fn distance(a: [f32; 2], b: [f32; 2]) -> f32 {
+    norm(sub(a, b))
+}
+
+fn speed(estimate: &NavigationEstimate) -> f32 {
+    norm([estimate.velocity[0], estimate.velocity[1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.1;
+    const FRAME: LocalFrame = LocalFrame {
+        latitude: 472_852_395,
+        longitude: 85_652_537,
+        altitude: 500.0,
+    };
+
+    fn estimate(position: [f32; 3], velocity: [f32; 2]) -> NavigationEstimate {
+        NavigationEstimate {
+            position,
+            velocity: [velocity[0], velocity[1], 0.0],
+            position_std: [1.0; 3],
+            velocity_std: [0.1; 3],
+        }
+    }
+
+    fn global(north: f32, east: f32) -> (i32, i32) {
+        let (latitude, longitude, _) = FRAME.to_global([north, east, 0.0]);
+        (latitude, longitude)
+    }
+
+    /// Flies a fixed-wing at constant speed, turning at the commanded bank angle.
+    fn fly_fixed_wing(
+        executor: &mut MissionExecutor,
+        mission: &Mission,
+        position: &mut [f32; 3],
+        course: &mut f32,
+        seconds: f32,
+    ) {
+        let speed = 15.0;
+        for _ in 0..(seconds / DT) as u32 {
+            let velocity = [speed * libm::cosf(*course), speed * libm::sinf(*course)];
+            let output = executor.update(
+                mission,
+                &FRAME,
+                [0.0; 3],
+                TransitionState::FixedWing,
+                &estimate(*position, velocity),
+                DT,
+            );
+            *course += libm::tanf(output.bank) * STANDARD_GRAVITY / speed * DT;
+            position[0] += velocity[0] * DT;
+            position[1] += velocity[1] * DT;
+        }
+    }
+
+    #[test]
+    fn upload_in_sequence() {
+        let mut mission = Mission::new();
+        let takeoff = MissionItem::Takeoff { altitude: 10.0 };
+        assert_eq!(mission.set(1, takeoff), Err(MissionError::OutOfSequence));
+        for index in 0..MAX_ITEMS {
+            assert_eq!(mission.set(index, takeoff), Ok(()));
+        }
+        assert_eq!(
+            mission.set(MAX_ITEMS, takeoff),
+            Err(MissionError::TooManyItems)
+        );
+        assert_eq!(mission.len(), MAX_ITEMS);
+
+        // Restarting replaces the mission.
+        let land = MissionItem::Land { position: None };
+        assert_eq!(mission.set(0, land), Ok(()));
+        assert_eq!(mission.len(), 1);
+        assert_eq!(mission.get(0), Some(land));
+        assert_eq!(mission.get(1), None);
+    }
+
+    #[test]
+    fn sequences_hover_mission() {
+        let (latitude, longitude) = global(30.0, -40.0);
+        let items = [
+            MissionItem::Takeoff { altitude: 10.0 },
+            MissionItem::Waypoint {
+                latitude,
+                longitude,
+                altitude: 15.0,
+            },
+            MissionItem::Loiter {
+                latitude,
+                longitude,
+                altitude: 15.0,
+                radius: 20.0,
+                time: 3.0,
+            },
+            MissionItem::Land { position: None },
+        ];
+        let mut mission = Mission::new();
+        for (index, item) in items.into_iter().enumerate() {
+            mission.set(index, item).unwrap();
+        }
+
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        let mut position = [0.0; 3];
+        executor.start(&estimate(position, [0.0; 2]), false);
+        let mut indices = [0.0; 4];
+        for step in 0..1200 {
+            let output = executor.update(
+                &mission,
+                &FRAME,
+                [0.0; 3],
+                TransitionState::Hover,
+                &estimate(position, [0.0; 2]),
+                DT,
+            );
+            assert!(!output.forward);
+            if indices[executor.index()] == 0.0 {
+                indices[executor.index()] = step as f32 * DT;
+            }
+            // Moves towards the target at the speed limit.
+            let offset = sub(output.target, [position[0], position[1]]);
+            let step = (output.speed * DT).min(norm(offset));
+            if step > 0.0 {
+                for i in 0..2 {
+                    position[i] += offset[i] / norm(offset) * step;
+                }
+            }
+            position[2] = (position[2] - output.climb_rate * DT).min(0.0);
+        }
+        // Takeoff, 50 m at 5 m/s and loitering for 3 s.
+        assert!(indices[1] > 4.0 && indices[1] < 10.0);
+        assert!(indices[2] - indices[1] > 9.0 && indices[2] - indices[1] < 11.0);
+        assert!((indices[3] - indices[2] - 3.0).abs() < 0.2);
+        assert_eq!(executor.index(), 3);
+        assert!(!executor.finished(&mission));
+        // Landed at the loiter position.
+        assert!((position[0] - 30.0).abs() < 0.1 && (position[1] + 40.0).abs() < 0.1);
+        assert_eq!(position[2], 0.0);
+    }
+
+    #[test]
+    fn follows_leg_in_fixed_wing_flight() {
+        let (latitude, longitude) = global(1000.0, 0.0);
+        let mut mission = Mission::new();
+        mission
+            .set(
+                0,
+                MissionItem::Waypoint {
+                    latitude,
+                    longitude,
+                    altitude: 50.0,
+                },
+            )
+            .unwrap();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        executor.start(&estimate([0.0, 0.0, -50.0], [15.0, 0.0]), true);
+
+        // Starts 60 m east of the leg, flying east.
+        let mut position = [0.0, 60.0, -50.0];
+        let mut course = core::f32::consts::FRAC_PI_2;
+        fly_fixed_wing(&mut executor, &mission, &mut position, &mut course, 40.0);
+        assert!(position[1].abs() < 1.0);
+        assert!(libm::fabsf(course) < 0.05);
+        assert_eq!(executor.index(), 0);
+
+        fly_fixed_wing(&mut executor, &mission, &mut position, &mut course, 40.0);
+        assert!(executor.finished(&mission));
+    }
+
+    #[test]
+    fn circles_in_fixed_wing_flight() {
+        let (latitude, longitude) = global(200.0, 100.0);
+        let mut mission = Mission::new();
+        let loiter = MissionItem::Loiter {
+            latitude,
+            longitude,
+            altitude: 50.0,
+            radius: 80.0,
+            time: 1000.0,
+        };
+        mission.set(0, loiter).unwrap();
+        let mut executor = MissionExecutor::new(MissionConfig::default());
+        let mut position = [0.0, 0.0, -50.0];
+        let mut course = 0.0;
+        executor.start(&estimate(position, [15.0, 0.0]), true);
+        fly_fixed_wing(&mut executor, &mission, &mut position, &mut course, 120.0);
+        let mut max_error: f32 = 0.0;
+        for _ in 0..100 {
+            fly_fixed_wing(&mut executor, &mission, &mut position, &mut course, 1.0);
+            let radius = distance([position[0], position[1]], [200.0, 100.0]);
+            max_error = max_error.max(libm::fabsf(radius - 80.0));
+        }
+        assert!(max_error < 5.0);
+    }
+
+    #[test]
+    fn banks_towards_target() {
+        let executor = MissionExecutor::new(MissionConfig::default());
+        let flying_north = estimate([0.0, 0.0, -50.0], [15.0, 0.0]);
+        assert!(executor.bank_to([100.0, 100.0], &flying_north) > 0.1);
+        assert!(executor.bank_to([100.0, -100.0], &flying_north) < -0.1);
+        assert!(executor.bank_to([100.0, 0.0], &flying_north).abs() < 1e-6);
+        // Behind and slightly right: turns right, at most at the bank limit.
+        let bank = executor.bank_to([-100.0, 1.0], &flying_north);
+        assert!(bank > 0.1 && bank <= MissionConfig::default().max_bank);
+    }
+}
@@ -0,0 +1,163 @@
+//! Bench test of the motors while disarmed, e.g. to check the motor order and direction.
+
+#[derive(Debug, Clone, Copy)]
+pub struct MotorTestConfig {
+    /// Thrust limit during the test `[0.0 .. 1.0]`.
+    pub max_thrust: f32,
+    /// The test stops if no keep-alive arrives for this long [s].
+    pub timeout: f32,
+    /// The test is only allowed (and continues) while the radio throttle is below this.
+    pub throttle_limit: f32,
+}
+
+impl Default for MotorTestConfig {
+    fn default() -> Self {
+        Self {
+            max_thrust: 0.3,
+            timeout: 1.0,
+            throttle_limit: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotorTestError {
+    Armed,
+    ThrottleNotLow,
+    NotActive,
+    Timeout,
+}
+
+pub struct MotorTest {
+    config: MotorTestConfig,
+    active: bool,
+    thrust: [f32; 4],
+    since_keep_alive: f32,
+}
+
+impl MotorTest {
+    pub fn new(config: MotorTestConfig) -> Self {
+        Self {
+            config,
+            active: false,
+            thrust: [0.0; 4],
+            since_keep_alive: 0.0,
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    /// Enters the test mode, with all motors stopped.
+    pub fn start(&mut self, armed: bool, throttle: f32) -> Result<(), MotorTestError> {
+        self.check(armed, throttle)?;
+        self.active = true;
+        self.thrust = [0.0; 4];
+        self.since_keep_alive = 0.0;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.active = false;
+        self.thrust = [0.0; 4];
+    }
+
+    pub fn keep_alive(&mut self) -> Result<(), MotorTestError> {
+        if !self.active {
+            return Err(MotorTestError::NotActive);
+        }
+        self.since_keep_alive = 0.0;
+        Ok(())
+    }
+
+    /// Sets the thrust `[0.0 .. 1.0]` per motor, limited to the configured maximum. Also
+    /// counts as keep-alive.
+    pub fn set_thrust(&mut self, thrust: [f32; 4]) -> Result<(), MotorTestError> {
+        self.keep_alive()?;
+        self.thrust = thrust.map(|t| t.clamp(0.0, self.config.max_thrust));
+        Ok(())
+    }
+
+    /// Returns the motor thrust while the test is active. Stops the test and returns the reason
+    /// if the vehicle is armed, the radio throttle is raised or the keep-alive times out.
+    pub fn update(
+        &mut self,
+        armed: bool,
+        throttle: f32,
+        dt: f32,
+    ) -> Result<Option<[f32; 4]>, MotorTestError> {
+        if !self.active {
+            return Ok(None);
+        }
+        self.since_keep_alive += dt;
+        let result = if self.since_keep_alive > self.config.timeout {
+            Err(MotorTestError::Timeout)
+        } else {
+            self.check(armed, throttle)
+        };
+        match result {
+            Ok(()) => Ok(Some(self.thrust)),
+            Err(e) => {
+                self.stop();
+                Err(e)
+            }
+        }
+    }
+
+    fn check(&self, armed: bool, throttle: f32) -> Result<(), MotorTestError> {
+        if armed {
+            Err(MotorTestError::Armed)
+        } else if throttle > self.config.throttle_limit {
+            Err(MotorTestError::ThrottleNotLow)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    #[test]
+    fn only_while_disarmed_with_throttle_low() {
+        let mut test = MotorTest::new(MotorTestConfig::default());
+        assert_eq!(test.start(true, 0.0), Err(MotorTestError::Armed));
+        assert_eq!(test.start(false, 0.5), Err(MotorTestError::ThrottleNotLow));
+        assert_eq!(test.set_thrust([0.1; 4]), Err(MotorTestError::NotActive));
+        assert_eq!(test.update(false, 0.0, DT), Ok(None));
+
+        assert_eq!(test.start(false, 0.0), Ok(()));
+        assert_eq!(test.update(false, 0.0, DT), Ok(Some([0.0; 4])));
+        assert_eq!(test.update(true, 0.0, DT), Err(MotorTestError::Armed));
+        assert!(!test.active());
+    }
+
+    #[test]
+    fn thrust_is_capped() {
+        let mut test = MotorTest::new(MotorTestConfig::default());
+        test.start(false, 0.0).unwrap();
+        test.set_thrust([0.1, 1.0, -1.0, 0.0]).unwrap();
+        assert_eq!(test.update(false, 0.0, DT), Ok(Some([0.1, 0.3, 0.0, 0.0])));
+    }
+
+    #[test]
+    fn stops_without_keep_alive() {
+        let mut test = MotorTest::new(MotorTestConfig::default());
+        test.start(false, 0.0).unwrap();
+        test.set_thrust([0.2; 4]).unwrap();
+        for _ in 0..40 {
+            assert!(test.update(false, 0.0, DT).is_ok());
+        }
+        test.keep_alive().unwrap();
+        for _ in 0..45 {
+            assert!(test.update(false, 0.0, DT).is_ok());
+        }
+        let timeout = (0..10).find_map(|_| test.update(false, 0.0, DT).err());
+        assert_eq!(timeout, Some(MotorTestError::Timeout));
+        assert_eq!(test.update(false, 0.0, DT), Ok(None));
+    }
+}
@@ -0,0 +1,545 @@
+//! Position and velocity estimation, a Kalman filter propagated with the earth-frame acceleration
+//! and corrected with GPS position and velocity and the barometric altitude.
+//!
+//! Estimates are in a local north-east-down frame, with the origin at the first GPS fix. Without
+//! GPS, the integrated acceleration drifts off within seconds, so there is no estimate then.
+
+use nalgebra::SMatrix;
+use nalgebra::SVector;
+use nalgebra::Vector3;
+
+/// Mean earth radius [m].
+const EARTH_RADIUS: f32 = 6_371_000.0;
+/// Past states kept for the GPS latency compensation.
+const HISTORY: usize = 32;
+
+type Vector9 = SVector<f32, 9>;
+type Matrix9 = SMatrix<f32, 9, 9>;
+
+/// Origin of the local frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalFrame {
+    pub latitude: i32,  // [1e-7 deg]
+    pub longitude: i32, // [1e-7 deg]
+    pub altitude: f32,  // [m], above mean sea level
+}
+
+impl LocalFrame {
+    /// Meters per 1e-7 degree of latitude.
+    const SCALE: f32 = EARTH_RADIUS * core::f32::consts::PI / 180.0 * 1e-7;
+
+    /// North, east and down position [m], flat earth approximation for short distances.
+    pub fn to_local(&self, latitude: i32, longitude: i32, altitude: f32) -> [f32; 3] {
+        let north = latitude.wrapping_sub(self.latitude) as f32 * Self::SCALE;
+        let east =
+            longitude.wrapping_sub(self.longitude) as f32 * Self::SCALE * self.cos_latitude();
+        [north, east, self.altitude - altitude]
+    }
+
+    /// Latitude, longitude [1e-7 deg] and altitude [m] of a local position.
+    pub fn to_global(&self, position: [f32; 3]) -> (i32, i32, f32) {
+        let [north, east, down] = position;
+        let latitude = self.latitude + libm::roundf(north / Self::SCALE) as i32;
+        let longitude =
+            self.longitude + libm::roundf(east / (Self::SCALE * self.cos_latitude())) as i32;
+        (latitude, longitude, self.altitude - down)
+    }
+
+    fn cos_latitude(&self) -> f32 {
+        libm::cosf((self.latitude as f32 * 1e-7).to_radians())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NavigationConfig {
+    /// Noise of the earth-frame acceleration [m/s^2/sqrt(Hz)].
+    pub accel_noise: f32,
+    /// Random walk of the acceleration bias [m/s^3/sqrt(Hz)].
+    pub accel_bias_walk: f32,
+    /// Initial standard deviation of the acceleration bias [m/s^2].
+    pub initial_accel_bias: f32,
+    /// Time between the measurement and the reception of a GPS solution [s].
+    pub gps_delay: f32,
+    /// Lower limits of the reported GPS accuracies [m, m/s].
+    pub min_gps_position_std: f32,
+    pub min_gps_velocity_std: f32,
+    /// Velocity accuracy if the receiver does not report it [m/s].
+    pub gps_velocity_std: f32,
+    /// Noise of the barometric altitude [m].
+    pub baro_std: f32,
+    /// Measurements with a squared Mahalanobis distance above this are rejected, per degree of
+    /// freedom.
+    pub gate: f32,
+    /// The filter is reset to the GPS position if it is rejected for longer than this [s].
+    pub glitch_timeout: f32,
+    /// There is no estimate if no GPS position was accepted for longer than this [s].
+    pub gps_timeout: f32,
+    /// There is no estimate if the horizontal standard deviations exceed these [m, m/s].
+    pub max_position_std: f32,
+    pub max_velocity_std: f32,
+}
+
+impl Default for NavigationConfig {
+    fn default() -> Self {
+        Self {
+            accel_noise: 0.35,
+            accel_bias_walk: 0.01,
+            initial_accel_bias: 0.2,
+            gps_delay: 0.2,
+            min_gps_position_std: 0.5,
+            min_gps_velocity_std: 0.1,
+            gps_velocity_std: 0.5,
+            baro_std: 0.5,
+            gate: 9.0,
+            glitch_timeout: 5.0,
+            gps_timeout: 2.0,
+            max_position_std: 5.0,
+            max_velocity_std: 2.0,
+        }
+    }
+}
+
+/// GPS solution with a 3D fix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsMeasurement {
+    pub latitude: i32,  // [1e-7 deg]
+    pub longitude: i32, // [1e-7 deg]
+    pub altitude: f32,  // [m], above mean sea level
+    /// North and east velocity [m/s].
+    pub velocity: [f32; 2],
+    /// Vertical velocity [m/s, up], if available.
+    pub vertical_velocity: Option<f32>,
+    pub horizontal_accuracy: f32,    // [m]
+    pub vertical_accuracy: f32,      // [m]
+    pub speed_accuracy: Option<f32>, // [m/s]
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NavigationEstimate {
+    /// North, east, down [m].
+    pub position: [f32; 3],
+    /// North, east, down [m/s].
+    pub velocity: [f32; 3],
+    /// Standard deviations of the position [m] and the velocity [m/s].
+    pub position_std: [f32; 3],
+    pub velocity_std: [f32; 3],
+}
+
+pub struct NavigationFilter {
+    config: NavigationConfig,
+    origin: Option<LocalFrame>,
+    /// Barometric altitude at the origin [m].
+    baro_reference: Option<f32>,
+    /// Position, velocity and acceleration bias.
+    state: Vector9,
+    covariance: Matrix9,
+    /// Position and velocity after the last `HISTORY` predictions, for the GPS latency
+    /// compensation.
+    history: [SVector<f32, 6>; HISTORY],
+    head: usize,
+    dt: f32,
+    /// Time since the last accepted GPS position [s].
+    gps_rejected: f32,
+}
+
+impl NavigationFilter {
+    pub fn new(config: NavigationConfig) -> Self {
+        Self {
+            config,
+            origin: None,
+            baro_reference: None,
+            state: Vector9::zeros(),
+            covariance: Matrix9::zeros(),
+            history: [SVector::zeros(); HISTORY],
+            head: 0,
+            dt: 0.0,
+            gps_rejected: 0.0,
+        }
+    }
+
+    /// The local frame, set by the first GPS measurement.
+    pub fn origin(&self) -> Option<&LocalFrame> {
+        self.origin.as_ref()
+    }
+
+    /// `None` before the first GPS measurement, during a GPS outage and while too uncertain.
+    pub fn estimate(&self) -> Option<NavigationEstimate> {
+        self.origin?;
+        if self.gps_rejected > self.config.gps_timeout {
+            return None;
+        }
+        let std = |i: usize| libm::sqrtf(self.covariance[(i, i)]);
+        let estimate = NavigationEstimate {
+            position: [self.state[0], self.state[1], self.state[2]],
+            velocity: [self.state[3], self.state[4], self.state[5]],
+            position_std: [std(0), std(1), std(2)],
+            velocity_std: [std(3), std(4), std(5)],
+        };
+        let horizontal = |std: [f32; 3]| std[0].max(std[1]);
+        (horizontal(estimate.position_std) <= self.config.max_position_std
+            && horizontal(estimate.velocity_std) <= self.config.max_velocity_std)
+            .then_some(estimate)
+    }
+
+    /// Covariance of position, velocity and acceleration bias.
+    pub fn covariance(&self) -> &SMatrix<f32, 9, 9> {
+        &self.covariance
+    }
+
+    /// `acceleration` is the earth-frame acceleration without gravity [m/s^2, north, east, down].
+    pub fn predict(&mut self, acceleration: [f32; 3], dt: f32) {
+        self.dt = dt;
+        if self.origin.is_none() {
+            return;
+        }
+        let acceleration = Vector3::from(acceleration) - self.state.fixed_rows::<3>(6);
+        let velocity = self.state.fixed_rows::<3>(3).into_owned();
+        let mut position = self.state.fixed_rows_mut::<3>(0);
+        position += velocity * dt + acceleration * (0.5 * dt * dt);
+        let mut velocity = self.state.fixed_rows_mut::<3>(3);
+        velocity += acceleration * dt;
+
+        let mut transition = Matrix9::identity();
+        for i in 0..3 {
+            transition[(i, i + 3)] = dt;
+            transition[(i, i + 6)] = -0.5 * dt * dt;
+            transition[(i + 3, i + 6)] = -dt;
+        }
+        let mut noise = Matrix9::zeros();
+        for i in 0..3 {
+            noise[(i + 3, i + 3)] = self.config.accel_noise * self.config.accel_noise * dt;
+            noise[(i + 6, i + 6)] = self.config.accel_bias_walk * self.config.accel_bias_walk * dt;
+        }
+        self.covariance = transition * self.covariance * transition.transpose() + noise;
+        self.gps_rejected += dt;
+
+        self.head = (self.head + 1) % HISTORY;
+        self.history[self.head] = self.state.fixed_rows::<6>(0).into_owned();
+    }
+
+    /// Fuses a GPS solution, returns false if it was rejected as a glitch.
+    pub fn update_gps(&mut self, gps: &GpsMeasurement) -> bool {
+        let Some(origin) = self.origin else {
+            self.reset(gps);
+            return true;
+        };
+        let position = origin.to_local(gps.latitude, gps.longitude, gps.altitude);
+        let delayed = self.delayed_state();
+
+        let horizontal = gps
+            .horizontal_accuracy
+            .max(self.config.min_gps_position_std);
+        let vertical = gps.vertical_accuracy.max(self.config.min_gps_position_std);
+        let mut jacobian = SMatrix::<f32, 3, 9>::zeros();
+        for i in 0..3 {
+            jacobian[(i, i)] = 1.0;
+        }
+        let innovation = Vector3::from(position) - delayed.fixed_rows::<3>(0);
+        let noise = SMatrix::<f32, 3, 3>::from_diagonal(&Vector3::new(
+            horizontal * horizontal,
+            horizontal * horizontal,
+            vertical * vertical,
+        ));
+        if !self.correct(innovation, &jacobian, noise) {
+            if self.gps_rejected > self.config.glitch_timeout {
+                self.reset(gps);
+                return true;
+            }
+            return false;
+        }
+        self.gps_rejected = 0.0;
+
+        let speed = gps
+            .speed_accuracy
+            .unwrap_or(self.config.gps_velocity_std)
+            .max(self.config.min_gps_velocity_std);
+        let velocity = Vector3::new(
+            gps.velocity[0],
+            gps.velocity[1],
+            -gps.vertical_velocity.unwrap_or(0.0),
+        );
+        let mut jacobian = SMatrix::<f32, 3, 9>::zeros();
+        for i in 0..3 {
+            jacobian[(i, i + 3)] = 1.0;
+        }
+        // Without a vertical velocity, only the horizontal one is fused.
+        let rows = if gps.vertical_velocity.is_some() {
+            3
+        } else {
+            2
+        };
+        let mut innovation = velocity - delayed.fixed_rows::<3>(3);
+        let mut noise = SMatrix::<f32, 3, 3>::identity() * (speed * speed);
+        for i in rows..3 {
+            jacobian.row_mut(i).fill(0.0);
+            innovation[i] = 0.0;
+            noise[(i, i)] = 1.0;
+        }
+        self.correct(innovation, &jacobian, noise);
+        true
+    }
+
+    /// Fuses the barometric altitude [m], returns false if it was rejected.
+    pub fn update_baro(&mut self, altitude: f32) -> bool {
+        if self.origin.is_none() {
+            return false;
+        }
+        let reference = *self.baro_reference.get_or_insert(altitude + self.state[2]);
+        let mut jacobian = SMatrix::<f32, 1, 9>::zeros();
+        jacobian[(0, 2)] = 1.0;
+        let innovation = SVector::<f32, 1>::new(reference - altitude - self.state[2]);
+        let noise = SMatrix::<f32, 1, 1>::new(self.config.baro_std * self.config.baro_std);
+        self.correct(innovation, &jacobian, noise)
+    }
+
+    /// Position and velocity at the time the GPS solution was measured.
+    fn delayed_state(&self) -> SVector<f32, 6> {
+        let steps = if self.dt > 0.0 {
+            (libm::roundf(self.config.gps_delay / self.dt) as usize).min(HISTORY - 1)
+        } else {
+            0
+        };
+        self.history[(self.head + HISTORY - steps) % HISTORY]
+    }
+
+    /// Starts over at the GPS position, the origin is kept if already set.
+    fn reset(&mut self, gps: &GpsMeasurement) {
+        let origin = *self.origin.get_or_insert(LocalFrame {
+            latitude: gps.latitude,
+            longitude: gps.longitude,
+            altitude: gps.altitude,
+        });
+        let position = origin.to_local(gps.latitude, gps.longitude, gps.altitude);
+        let velocity = [
+            gps.velocity[0],
+            gps.velocity[1],
+            -gps.vertical_velocity.unwrap_or(0.0),
+        ];
+        let speed = gps.speed_accuracy.unwrap_or(self.config.gps_velocity_std);
+        let horizontal = gps
+            .horizontal_accuracy
+            .max(self.config.min_gps_position_std);
+        let vertical = gps.vertical_accuracy.max(self.config.min_gps_position_std);
+        let bias = self.config.initial_accel_bias;
+        self.state = Vector9::zeros();
+        self.covariance = Matrix9::zeros();
+        for i in 0..3 {
+            self.state[i] = position[i];
+            self.state[i + 3] = velocity[i];
+            self.covariance[(i + 3, i + 3)] = speed * speed;
+            self.covariance[(i + 6, i + 6)] = bias * bias;
+        }
+        self.covariance[(0, 0)] = horizontal * horizontal;
+        self.covariance[(1, 1)] = horizontal * horizontal;
+        self.covariance[(2, 2)] = vertical * vertical;
+        self.history = [self.state.fixed_rows::<6>(0).into_owned(); HISTORY];
+        // The barometer is referenced again, its drift would otherwise show up as a jump.
+        self.baro_reference = None;
+        self.gps_rejected = 0.0;
+    }
+
+    /// Applies the correction to the current state, also if the innovation was computed from a
+    /// delayed state.
+    fn correct<const M: usize>(
+        &mut self,
+        innovation: SVector<f32, M>,
+        jacobian: &SMatrix<f32, M, 9>,
+        noise: SMatrix<f32, M, M>,
+    ) -> bool {
+        let covariance = self.covariance;
+        let innovation_covariance = jacobian * covariance * jacobian.transpose() + noise;
+        let Some(inverse) = innovation_covariance.try_inverse() else {
+            return false;
+        };
+        let distance = (innovation.transpose() * inverse * innovation)[(0, 0)];
+        if distance > self.config.gate * M as f32 {
+            return false;
+        }
+        let gain = covariance * jacobian.transpose() * inverse;
+        let correction = gain * innovation;
+        self.state += correction;
+        // The stored states are corrected as well, so that the next delayed innovation does not
+        // contain this correction again.
+        let past = correction.fixed_rows::<6>(0).into_owned();
+        for state in self.history.iter_mut() {
+            *state += past;
+        }
+
+        let factor = Matrix9::identity() - gain * jacobian;
+        self.covariance =
+            factor * covariance * factor.transpose() + gain * noise * gain.transpose();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Noise;
+
+    const DT: f32 = 0.02;
+    const ORIGIN: LocalFrame = LocalFrame {
+        latitude: 472_852_395,
+        longitude: 85_652_537,
+        altitude: 500.0,
+    };
+
+    fn gps(position: [f32; 3], velocity: [f32; 3]) -> GpsMeasurement {
+        let (latitude, longitude, altitude) = ORIGIN.to_global(position);
+        GpsMeasurement {
+            latitude,
+            longitude,
+            altitude,
+            velocity: [velocity[0], velocity[1]],
+            vertical_velocity: Some(-velocity[2]),
+            horizontal_accuracy: 1.5,
+            vertical_accuracy: 2.5,
+            speed_accuracy: Some(0.3),
+        }
+    }
+
+    #[test]
+    fn local_frame_round_trip() {
+        let position = [1234.5, -678.9, -42.0];
+        let (latitude, longitude, altitude) = ORIGIN.to_global(position);
+        assert!(latitude > ORIGIN.latitude && longitude < ORIGIN.longitude);
+        assert_eq!(altitude, 542.0);
+        let local = ORIGIN.to_local(latitude, longitude, altitude);
+        for (local, position) in local.iter().zip(position) {
+            assert!((local - position).abs() < 0.02);
+        }
+    }
+
+    #[test]
+    fn tracks_circle_with_delayed_gps() {
+        let mut filter = NavigationFilter::new(NavigationConfig::default());
+        let mut noise = Noise(7);
+        let (radius, rate) = (20.0, 0.5);
+        // Position, velocity and acceleration on a climbing circle.
+        let truth = |t: f32| {
+            let (sin, cos) = libm::sincosf(rate * t);
+            (
+                [radius * sin, radius * (1.0 - cos), -0.5 * t],
+                [radius * rate * cos, radius * rate * sin, -0.5],
+                [-radius * rate * rate * sin, radius * rate * rate * cos, 0.0],
+            )
+        };
+        let bias = [0.15, -0.1, 0.2];
+        let (mut position_error, mut velocity_error, mut samples) = (0.0, 0.0, 0);
+        for step in 0..3_000 {
+            let t = step as f32 * DT;
+            let (position, velocity, acceleration) = truth(t);
+            if step % 10 == 0 && t >= 0.2 {
+                let (mut position, velocity, _) = truth(t - 0.2);
+                for p in position.iter_mut() {
+                    *p += noise.next();
+                }
+                assert!(filter.update_gps(&gps(position, velocity)));
+            }
+            if filter.origin().is_some() {
+                assert!(filter.update_baro(120.0 - position[2] + 0.5 * noise.next()));
+            }
+            if t > 30.0 {
+                let estimate = filter.estimate().unwrap();
+                // The origin is the first, noisy, solution.
+                let (latitude, longitude, altitude) = ORIGIN.to_global(position);
+                let position = filter
+                    .origin()
+                    .unwrap()
+                    .to_local(latitude, longitude, altitude);
+                for i in 0..3 {
+                    position_error += libm::fabsf(estimate.position[i] - position[i]);
+                    velocity_error += libm::fabsf(estimate.velocity[i] - velocity[i]);
+                }
+                samples += 3;
+            }
+            let mut measured = [0.0; 3];
+            for i in 0..3 {
+                measured[i] = acceleration[i] + bias[i] + 0.5 * noise.next();
+            }
+            filter.predict(measured, DT);
+        }
+        // Without the latency compensation, the lag alone would be 2 m.
+        assert!(
+            position_error / (samples as f32) < 0.5,
+            "{}",
+            position_error / samples as f32
+        );
+        assert!(velocity_error / (samples as f32) < 0.2);
+        let estimate = filter.estimate().unwrap();
+        assert!(estimate.position_std.iter().all(|std| *std < 1.0));
+        assert!(estimate.velocity_std.iter().all(|std| *std < 0.3));
+    }
+
+    #[test]
+    fn rejects_glitch_and_resets_after_timeout() {
+        let mut filter = NavigationFilter::new(NavigationConfig::default());
+        assert!(filter.estimate().is_none());
+        for step in 0..1_000 {
+            filter.predict([0.0; 3], DT);
+            if step % 10 == 0 {
+                assert!(filter.update_gps(&gps([0.0; 3], [0.0; 3])));
+            }
+        }
+
+        let jumped = gps([100.0, 0.0, 0.0], [0.0; 3]);
+        let config = NavigationConfig::default();
+        let mut accepted = 0;
+        for step in 0..400 {
+            filter.predict([0.0; 3], DT);
+            if step % 10 == 0 {
+                if !filter.update_gps(&jumped) {
+                    // Without an accepted position, the estimate is gone after the GPS timeout.
+                    match filter.estimate() {
+                        Some(estimate) => assert!(estimate.position[0].abs() < 0.5),
+                        None => assert!(step as f32 * DT >= config.gps_timeout),
+                    }
+                } else if accepted == 0 {
+                    accepted = step;
+                }
+            }
+        }
+        // Accepted again once the glitch timeout passed.
+        let timeout = config.glitch_timeout;
+        assert!(accepted as f32 * DT >= timeout && (accepted as f32 * DT) < timeout + 0.5);
+        assert!((filter.estimate().unwrap().position[0] - 100.0).abs() < 1.0);
+        assert_eq!(filter.origin(), Some(&ORIGIN));
+    }
+
+    #[test]
+    fn no_estimate_during_gps_outage() {
+        let config = NavigationConfig::default();
+        let mut filter = NavigationFilter::new(config);
+        let mut noise = Noise(3);
+        for step in 0..500 {
+            if step % 10 == 0 {
+                assert!(filter.update_gps(&gps([0.0; 3], [0.0; 3])));
+            }
+            filter.update_baro(100.0);
+            filter.predict([0.0; 3], DT);
+        }
+        assert!(filter.estimate().is_some());
+
+        // Dead reckoning with a biased, noisy acceleration, the barometer keeps working.
+        let mut lost = None;
+        for step in 0..(10.0 / DT) as u32 {
+            filter.update_baro(100.0);
+            filter.predict([0.3 + noise.next(), -0.2 + noise.next(), 0.0], DT);
+            match filter.estimate() {
+                Some(estimate) => {
+                    assert!(lost.is_none());
+                    assert!(estimate.position[0].abs() < 2.0 && estimate.position[1].abs() < 2.0);
+                }
+                None => {
+                    lost.get_or_insert(step);
+                }
+            }
+        }
+        let lost = lost.unwrap() as f32 * DT;
+        assert!(lost <= config.gps_timeout + DT, "{}", lost);
+
+        // Back with the next GPS solution.
+        assert!(filter.update_gps(&gps([0.0; 3], [0.0; 3])));
+        assert!(filter.estimate().is_some());
+    }
+}
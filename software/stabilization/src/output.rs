@@ -0,0 +1,159 @@
+//! Conditioning of the motor outputs between the controllers and the ESC driver.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OutputConfig {
+    /// Output while armed at zero thrust, keeps the motors spinning `[0.0 .. 1.0]`.
+    pub idle: f32,
+    /// Time after arming in which the output limit rises from stop to full [s].
+    pub soft_start_time: f32,
+    /// Maximum change of an output [1/s], `None` for no limit.
+    pub slew_rate: Option<f32>,
+    /// Output range `(min, max)` per motor that thrust `[0.0 .. 1.0]` is mapped to.
+    pub endpoints: [(f32, f32); 4],
+    /// Corrects for thrust being proportional to the command squared, so that the output
+    /// thrust is proportional to the input.
+    pub linearize: bool,
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            idle: 0.05,
+            soft_start_time: 1.0,
+            slew_rate: Some(8.0),
+            endpoints: [(0.0, 1.0); 4],
+            linearize: false,
+        }
+    }
+}
+
+pub struct OutputConditioner {
+    config: OutputConfig,
+    armed: bool,
+    soft_start: f32,
+    output: [f32; 4],
+}
+
+impl OutputConditioner {
+    pub fn new(config: OutputConfig) -> Self {
+        Self {
+            config,
+            armed: false,
+            soft_start: 0.0,
+            output: [0.0; 4],
+        }
+    }
+
+    pub fn config(&self) -> &OutputConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: OutputConfig) {
+        self.config = config;
+    }
+
+    pub fn armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Returns the ESC outputs `[0.0 .. 1.0]` for the thrust `[0.0 .. 1.0]` per motor. The
+    /// motors are stopped (output zero) while disarmed.
+    pub fn update(&mut self, armed: bool, thrust: [f32; 4], dt: f32) -> [f32; 4] {
+        if !armed {
+            self.armed = false;
+            self.output = [0.0; 4];
+            return self.output;
+        }
+        if !self.armed {
+            self.armed = true;
+            self.soft_start = 0.0;
+            self.output = self.config.endpoints.map(|(min, _)| min);
+        }
+        self.soft_start = if self.config.soft_start_time > 0.0 {
+            (self.soft_start + dt / self.config.soft_start_time).min(1.0)
+        } else {
+            1.0
+        };
+
+        let config = &self.config;
+        for ((output, thrust), (min, max)) in
+            self.output.iter_mut().zip(thrust).zip(config.endpoints)
+        {
+            let mut command = thrust.clamp(0.0, 1.0);
+            if config.linearize {
+                command = libm::sqrtf(command);
+            }
+            let command = (config.idle + (1.0 - config.idle) * command).min(self.soft_start);
+            let target = min + (max - min) * command;
+            *output = match config.slew_rate {
+                Some(rate) => *output + (target - *output).clamp(-rate * dt, rate * dt),
+                None => target,
+            };
+        }
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    fn run(conditioner: &mut OutputConditioner, thrust: [f32; 4], seconds: f32) -> [f32; 4] {
+        let mut output = [0.0; 4];
+        for _ in 0..(seconds / DT) as u32 {
+            output = conditioner.update(true, thrust, DT);
+        }
+        output
+    }
+
+    #[test]
+    fn stopped_while_disarmed() {
+        let mut conditioner = OutputConditioner::new(OutputConfig::default());
+        assert_eq!(conditioner.update(false, [1.0; 4], DT), [0.0; 4]);
+        assert!(!conditioner.armed());
+    }
+
+    #[test]
+    fn idle_and_soft_start() {
+        let mut conditioner = OutputConditioner::new(OutputConfig::default());
+        let first = conditioner.update(true, [1.0; 4], DT);
+        assert!(first[0] <= DT / 1.0 + 1e-6);
+        let ramping = run(&mut conditioner, [1.0; 4], 0.48);
+        assert!((ramping[0] - 0.5).abs() < 0.01);
+        assert_eq!(run(&mut conditioner, [1.0; 4], 1.0), [1.0; 4]);
+        let idle = run(&mut conditioner, [0.0; 4], 1.0);
+        assert!((idle[0] - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn slew_rate_limit() {
+        let config = OutputConfig {
+            soft_start_time: 0.0,
+            ..Default::default()
+        };
+        let mut conditioner = OutputConditioner::new(config);
+        run(&mut conditioner, [0.0; 4], 1.0);
+        let output = conditioner.update(true, [1.0; 4], DT);
+        assert!((output[0] - (0.05 + 8.0 * DT)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn endpoints_and_linearization() {
+        let mut endpoints = [(0.0, 1.0); 4];
+        endpoints[2] = (0.1, 0.9);
+        let config = OutputConfig {
+            idle: 0.0,
+            soft_start_time: 0.0,
+            slew_rate: None,
+            endpoints,
+            linearize: true,
+        };
+        let mut conditioner = OutputConditioner::new(config);
+        let output = conditioner.update(true, [0.25, 1.0, 1.0, 0.0], DT);
+        assert_eq!(output, [0.5, 1.0, 0.9, 0.0]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Horizontal position control in hover. The roll and pitch sticks command a velocity relative to
+//! the heading, with centred sticks the position is held. A velocity loop turns the velocity
+//! error into an acceleration and that into roll and pitch setpoints.
+
+use crate::STANDARD_GRAVITY;
+use crate::navigation::NavigationEstimate;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PositionHoldConfig {
+    /// Stick deadband around the centre, in which the position is held `[0.0 .. 1.0]`.
+    pub deadband: f32,
+    /// Velocity at full stick [m/s].
+    pub max_speed: f32,
+    /// Velocity per meter of position error [1/s].
+    pub position_gain: f32,
+    /// Acceleration per m/s of velocity error [1/s].
+    pub velocity_p: f32,
+    /// Acceleration per meter of integrated velocity error [1/s^2], compensates wind.
+    pub velocity_i: f32,
+    pub max_acceleration: f32, // [m/s^2]
+    pub max_tilt: f32,         // [rad]
+}
+
+impl Default for PositionHoldConfig {
+    fn default() -> Self {
+        Self {
+            deadband: 0.1,
+            max_speed: 5.0,
+            position_gain: 1.0,
+            velocity_p: 2.0,
+            velocity_i: 0.3,
+            max_acceleration: 4.0,
+            max_tilt: 25f32.to_radians(),
+        }
+    }
+}
+
+pub struct PositionController {
+    config: PositionHoldConfig,
+    /// North and east position to hold [m], `None` while the sticks command a velocity.
+    target: Option<[f32; 2]>,
+    /// North and east acceleration [m/s^2].
+    integrator: [f32; 2],
+}
+
+impl PositionController {
+    pub fn new(config: PositionHoldConfig) -> Self {
+        Self {
+            config,
+            target: None,
+            integrator: [0.0; 2],
+        }
+    }
+
+    /// Forgets the held position and the wind estimate, e.g. when the mode is engaged.
+    pub fn reset(&mut self) {
+        self.target = None;
+        self.integrator = [0.0; 2];
+    }
+
+    pub fn target(&self) -> Option<[f32; 2]> {
+        self.target
+    }
+
+    /// `sticks` are the roll (right) and pitch (forward) sticks `[-1.0 .. 1.0]`, `yaw` is the
+    /// heading [rad] of the north-west-up attitude estimate. Returns the roll and pitch
+    /// setpoints [rad] in the convention of the attitude estimate.
+    pub fn update(
+        &mut self,
+        sticks: [f32; 2],
+        yaw: f32,
+        estimate: &NavigationEstimate,
+        dt: f32,
+    ) -> [f32; 2] {
+        let [right, forward] = sticks.map(|stick| self.deflection(stick));
+        let velocity = if right == 0.0 && forward == 0.0 {
+            let target = *self
+                .target
+                .get_or_insert([estimate.position[0], estimate.position[1]]);
+            self.approach(target, self.config.max_speed, estimate)
+        } else {
+            self.target = None;
+            let (forward_axis, right_axis) = axes(yaw);
+            [0, 1].map(|i| {
+                (forward * forward_axis[i] + right * right_axis[i]) * self.config.max_speed
+            })
+        };
+        self.track(velocity, yaw, estimate, dt)
+    }
+
+    /// Flies towards `target` [m, north and east] at up to `speed` [m/s] and holds it there.
+    pub fn fly_to(
+        &mut self,
+        target: [f32; 2],
+        speed: f32,
+        yaw: f32,
+        estimate: &NavigationEstimate,
+        dt: f32,
+    ) -> [f32; 2] {
+        self.target = Some(target);
+        let velocity = self.approach(target, speed, estimate);
+        self.track(velocity, yaw, estimate, dt)
+    }
+
+    /// Velocity setpoint towards `target`, limited to `speed`.
+    fn approach(&self, target: [f32; 2], speed: f32, estimate: &NavigationEstimate) -> [f32; 2] {
+        let velocity =
+            [0, 1].map(|i| self.config.position_gain * (target[i] - estimate.position[i]));
+        limit(velocity, speed)
+    }
+
+    /// Velocity loop, returns the roll and pitch setpoints for the north and east `velocity`.
+    fn track(
+        &mut self,
+        velocity: [f32; 2],
+        yaw: f32,
+        estimate: &NavigationEstimate,
+        dt: f32,
+    ) -> [f32; 2] {
+        let config = &self.config;
+        let error = [0, 1].map(|i| velocity[i] - estimate.velocity[i]);
+        let unlimited = [0, 1].map(|i| config.velocity_p * error[i] + self.integrator[i]);
+        let acceleration = limit(unlimited, config.max_acceleration);
+        // Anti-windup: only integrate while the acceleration is not limited.
+        if acceleration == unlimited {
+            for (integrator, error) in self.integrator.iter_mut().zip(error) {
+                *integrator += config.velocity_i * error * dt;
+            }
+        }
+
+        let (forward_axis, right_axis) = axes(yaw);
+        let forward = acceleration[0] * forward_axis[0] + acceleration[1] * forward_axis[1];
+        let right = acceleration[0] * right_axis[0] + acceleration[1] * right_axis[1];
+        // Rolling right and pitching nose down are positive.
+        [right, forward]
+            .map(|a| libm::atanf(a / STANDARD_GRAVITY).clamp(-config.max_tilt, config.max_tilt))
+    }
+
+    /// Maps a stick to `[-1.0 .. 1.0]`, zero within the centre deadband.
+    fn deflection(&self, stick: f32) -> f32 {
+        let stick = stick.clamp(-1.0, 1.0);
+        let magnitude = (libm::fabsf(stick) - self.config.deadband).max(0.0);
+        magnitude / (1.0 - self.config.deadband) * stick.signum()
+    }
+}
+
+/// North and east components of the forward and the right axis for the north-west-up `yaw`.
+fn axes(yaw: f32) -> ([f32; 2], [f32; 2]) {
+    let (sin, cos) = libm::sincosf(yaw);
+    ([cos, -sin], [sin, cos])
+}
+
+/// Scales `vector` down to at most `max` length.
+fn limit(vector: [f32; 2], max: f32) -> [f32; 2] {
+    let norm = libm::sqrtf(vector[0] * vector[0] + vector[1] * vector[1]);
+    if norm > max {
+        vector.map(|v| v * max / norm)
+    } else {
+        vector
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::FRAC_PI_2;
+
+    const DT: f32 = 0.02;
+
+    fn estimate(position: [f32; 2], velocity: [f32; 2]) -> NavigationEstimate {
+        NavigationEstimate {
+            position: [position[0], position[1], -10.0],
+            velocity: [velocity[0], velocity[1], 0.0],
+            position_std: [1.0; 3],
+            velocity_std: [0.1; 3],
+        }
+    }
+
+    /// Horizontal acceleration [m/s^2, north and east] of the `tilt` setpoints.
+    fn simulated_acceleration(tilt: [f32; 2], yaw: f32) -> [f32; 2] {
+        let (forward_axis, right_axis) = axes(yaw);
+        let [right, forward] = tilt.map(|angle| libm::tanf(angle) * STANDARD_GRAVITY);
+        [0, 1].map(|i| forward * forward_axis[i] + right * right_axis[i])
+    }
+
+    #[test]
+    fn sticks_relative_to_heading() {
+        let mut controller = PositionController::new(PositionHoldConfig::default());
+        // Facing west, the forward stick accelerates west and pitches down.
+        let tilt = controller.update([0.0, 1.0], FRAC_PI_2, &estimate([0.0; 2], [0.0; 2]), DT);
+        assert!(tilt[1] > 0.0 && tilt[0].abs() < 1e-5);
+        let acceleration = simulated_acceleration(tilt, FRAC_PI_2);
+        assert!(acceleration[1] < 0.0 && acceleration[0].abs() < 1e-4);
+        assert_eq!(controller.target(), None);
+    }
+
+    #[test]
+    fn holds_position_against_wind() {
+        let mut controller = PositionController::new(PositionHoldConfig::default());
+        let yaw = 0.7;
+        let wind = [0.5, -0.3]; // [m/s^2]
+        let (mut position, mut velocity) = ([0.0; 2], [2.0, 0.0]);
+        for _ in 0..(60.0 / DT) as u32 {
+            let tilt = controller.update([0.02, -0.05], yaw, &estimate(position, velocity), DT);
+            let acceleration = simulated_acceleration(tilt, yaw);
+            for i in 0..2 {
+                velocity[i] += (acceleration[i] + wind[i]) * DT;
+                position[i] += velocity[i] * DT;
+            }
+        }
+        // Returns to where the sticks were released at 2 m/s.
+        let target = controller.target().unwrap();
+        assert_eq!(target, [0.0; 2]);
+        for i in 0..2 {
+            assert!((position[i] - target[i]).abs() < 0.05);
+            assert!(velocity[i].abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn flies_to_target_at_limited_speed() {
+        let mut controller = PositionController::new(PositionHoldConfig::default());
+        let (mut position, mut velocity) = ([0.0; 2], [0.0; 2]);
+        let mut max_speed: f32 = 0.0;
+        for _ in 0..(40.0 / DT) as u32 {
+            let tilt =
+                controller.fly_to([-60.0, 80.0], 4.0, 0.0, &estimate(position, velocity), DT);
+            let acceleration = simulated_acceleration(tilt, 0.0);
+            for i in 0..2 {
+                velocity[i] += acceleration[i] * DT;
+                position[i] += velocity[i] * DT;
+            }
+            max_speed = max_speed.max(libm::sqrtf(
+                velocity[0] * velocity[0] + velocity[1] * velocity[1],
+            ));
+        }
+        assert!(max_speed < 4.2);
+        assert!((position[0] + 60.0).abs() < 0.1 && (position[1] - 80.0).abs() < 0.1);
+    }
+}
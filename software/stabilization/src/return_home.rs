@@ -0,0 +1,226 @@
+//! Return to home: climbs to a safe height, flies back to the position recorded at arming and
+//! lands there. Far from home, forward flight is requested for the way back.
+
+use crate::landing::DescentConfig;
+use crate::navigation::NavigationEstimate;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ReturnConfig {
+    /// Minimum height above home for the way back [m].
+    pub altitude: f32,
+    pub climb_rate: f32, // [m/s]
+    /// Horizontal speed in hover [m/s].
+    pub speed: f32,
+    /// Distance from home at which the descent starts [m].
+    pub acceptance_radius: f32,
+    /// Forward flight is requested beyond this distance from home [m]...
+    pub forward_distance: f32,
+    /// ... until closer than this [m].
+    pub hover_distance: f32,
+    /// Descent above home.
+    pub descent: DescentConfig,
+}
+
+impl Default for ReturnConfig {
+    fn default() -> Self {
+        Self {
+            altitude: 30.0,
+            climb_rate: 2.0,
+            speed: 5.0,
+            acceptance_radius: 2.0,
+            forward_distance: 200.0,
+            hover_distance: 60.0,
+            descent: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReturnPhase {
+    Climb,
+    Return,
+    Land,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReturnOutput {
+    /// North and east position to fly to [m].
+    pub target: [f32; 2],
+    /// Horizontal speed limit [m/s].
+    pub speed: f32,
+    /// Climb rate [m/s, up], zero to hold the altitude.
+    pub climb_rate: f32,
+    /// Requests forward flight.
+    pub forward: bool,
+}
+
+pub struct ReturnToHome {
+    config: ReturnConfig,
+    /// North, east, down [m].
+    home: Option<[f32; 3]>,
+    phase: ReturnPhase,
+    /// Horizontal position held during the climb and the landing [m].
+    hold: [f32; 2],
+    forward: bool,
+}
+
+impl ReturnToHome {
+    pub fn new(config: ReturnConfig) -> Self {
+        Self {
+            config,
+            home: None,
+            phase: ReturnPhase::Climb,
+            hold: [0.0; 2],
+            forward: false,
+        }
+    }
+
+    /// Records the home position, e.g. at arming.
+    pub fn set_home(&mut self, position: [f32; 3]) {
+        self.home = Some(position);
+    }
+
+    pub fn home(&self) -> Option<[f32; 3]> {
+        self.home
+    }
+
+    pub fn phase(&self) -> ReturnPhase {
+        self.phase
+    }
+
+    /// Starts the return from the current position.
+    pub fn start(&mut self, estimate: &NavigationEstimate) {
+        self.enter(ReturnPhase::Climb, estimate);
+    }
+
+    /// Lands at the current position instead of returning.
+    pub fn land(&mut self, estimate: &NavigationEstimate) {
+        self.enter(ReturnPhase::Land, estimate);
+    }
+
+    /// Returns `None` without a home position.
+    pub fn update(&mut self, estimate: &NavigationEstimate) -> Option<ReturnOutput> {
+        let config = self.config;
+        let home = self.home?;
+        let height = home[2] - estimate.position[2];
+        let offset = [0, 1].map(|i| home[i] - estimate.position[i]);
+        let distance = libm::sqrtf(offset[0] * offset[0] + offset[1] * offset[1]);
+
+        match self.phase {
+            ReturnPhase::Climb if height >= config.altitude - 0.5 => {
+                self.enter(ReturnPhase::Return, estimate)
+            }
+            ReturnPhase::Return if distance < config.acceptance_radius && !self.forward => {
+                self.enter(ReturnPhase::Land, estimate);
+                self.hold = [home[0], home[1]];
+            }
+            _ => {}
+        }
+        if self.phase != ReturnPhase::Return || distance < config.hover_distance {
+            self.forward = false;
+        } else if distance > config.forward_distance {
+            self.forward = true;
+        }
+
+        Some(match self.phase {
+            ReturnPhase::Climb => ReturnOutput {
+                target: self.hold,
+                speed: config.speed,
+                climb_rate: config.climb_rate,
+                forward: false,
+            },
+            ReturnPhase::Return => ReturnOutput {
+                target: [home[0], home[1]],
+                speed: config.speed,
+                climb_rate: 0.0,
+                forward: self.forward,
+            },
+            ReturnPhase::Land => ReturnOutput {
+                target: self.hold,
+                speed: config.speed,
+                climb_rate: config.descent.climb_rate(Some(height)),
+                forward: false,
+            },
+        })
+    }
+
+    fn enter(&mut self, phase: ReturnPhase, estimate: &NavigationEstimate) {
+        self.phase = phase;
+        self.hold = [estimate.position[0], estimate.position[1]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn estimate(position: [f32; 3]) -> NavigationEstimate {
+        NavigationEstimate {
+            position,
+            velocity: [0.0; 3],
+            position_std: [1.0; 3],
+            velocity_std: [0.1; 3],
+        }
+    }
+
+    /// Moves straight towards the outputs, at the commanded speeds, returns the final position.
+    fn fly(rth: &mut ReturnToHome, mut position: [f32; 3], seconds: f32) -> [f32; 3] {
+        let dt = 0.1;
+        for _ in 0..(seconds / dt) as u32 {
+            let output = rth.update(&estimate(position)).unwrap();
+            let offset = [0, 1].map(|i| output.target[i] - position[i]);
+            let distance = libm::sqrtf(offset[0] * offset[0] + offset[1] * offset[1]);
+            let step = (output.speed * dt).min(distance);
+            if distance > 0.0 {
+                for i in 0..2 {
+                    position[i] += offset[i] / distance * step;
+                }
+            }
+            position[2] = (position[2] - output.climb_rate * dt).min(0.0);
+        }
+        position
+    }
+
+    #[test]
+    fn climbs_returns_and_lands() {
+        let mut rth = ReturnToHome::new(ReturnConfig::default());
+        assert_eq!(rth.update(&estimate([0.0; 3])), None);
+        rth.set_home([0.0; 3]);
+
+        let start = [40.0, -30.0, -10.0];
+        rth.start(&estimate(start));
+        let output = rth.update(&estimate(start)).unwrap();
+        assert_eq!(output.target, [40.0, -30.0]);
+        assert!(output.climb_rate > 0.0);
+
+        let position = fly(&mut rth, start, 12.0);
+        assert_eq!(rth.phase(), ReturnPhase::Return);
+        assert!((position[2] + 30.0).abs() < 0.5);
+
+        let position = fly(&mut rth, position, 8.0);
+        assert_eq!(rth.phase(), ReturnPhase::Land);
+        assert!((position[2] + 30.0).abs() < 1.0);
+
+        let position = fly(&mut rth, position, 40.0);
+        assert!(position[2] == 0.0);
+        assert!(position[0].abs() < 2.0 && position[1].abs() < 2.0);
+        let output = rth.update(&estimate(position)).unwrap();
+        assert_eq!(
+            output.climb_rate,
+            -ReturnConfig::default().descent.land_rate
+        );
+    }
+
+    #[test]
+    fn forward_flight_when_far() {
+        let mut rth = ReturnToHome::new(ReturnConfig::default());
+        rth.set_home([0.0; 3]);
+        let far = [0.0, 500.0, -50.0];
+        rth.start(&estimate(far));
+        assert!(rth.update(&estimate(far)).unwrap().forward);
+        // Hysteresis between the forward and the hover distance.
+        assert!(rth.update(&estimate([0.0, 100.0, -50.0])).unwrap().forward);
+        assert!(!rth.update(&estimate([0.0, 50.0, -50.0])).unwrap().forward);
+        assert!(!rth.update(&estimate([0.0, 100.0, -50.0])).unwrap().forward);
+    }
+}
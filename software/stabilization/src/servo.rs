@@ -0,0 +1,173 @@
+//! Servo outputs for the tilt mechanism and the control surfaces.
+
+use serde::{Deserialize, Serialize};
+
+/// What a servo output is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServoFunction {
+    TiltLeft,
+    TiltRight,
+    Elevator,
+    Rudder,
+    AileronLeft,
+    AileronRight,
+    ElevonLeft,
+    ElevonRight,
+}
+
+/// Commanded tilt and control surface deflections.
+///
+/// Surface deflections are `[-1.0 .. 1.0]`, positive for trailing edge up (elevator, ailerons)
+/// or right (rudder).
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FlightControls {
+    /// Rotor tilt `[-1.0 .. 1.0]`, 0 for vertical (hover) and 1 for horizontal (forward
+    /// flight), negative to tilt backwards. The servo trim sets the vertical position.
+    pub tilt_left: f32,
+    pub tilt_right: f32,
+    /// Positive to pitch up.
+    pub elevator: f32,
+    /// Positive to yaw right.
+    pub rudder: f32,
+    /// Positive to roll right.
+    pub aileron: f32,
+}
+
+impl FlightControls {
+    /// Servo command `[-1.0 .. 1.0]` for the output with the given function.
+    pub fn command(&self, function: ServoFunction) -> f32 {
+        let command = match function {
+            ServoFunction::TiltLeft => self.tilt_left,
+            ServoFunction::TiltRight => self.tilt_right,
+            ServoFunction::Elevator => self.elevator,
+            ServoFunction::Rudder => self.rudder,
+            ServoFunction::AileronLeft => -self.aileron,
+            ServoFunction::AileronRight => self.aileron,
+            ServoFunction::ElevonLeft => self.elevator - self.aileron,
+            ServoFunction::ElevonRight => self.elevator + self.aileron,
+        };
+        command.clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ServoConfig {
+    /// Position at zero command `[-1.0 .. 1.0]`.
+    pub trim: f32,
+    /// Positions `(min, max)` at full negative and positive command `[-1.0 .. 1.0]`.
+    pub endpoints: (f32, f32),
+    pub reversed: bool,
+    /// Maximum change of the position [1/s], `None` for no limit.
+    pub rate: Option<f32>,
+}
+
+impl Default for ServoConfig {
+    fn default() -> Self {
+        Self {
+            trim: 0.0,
+            endpoints: (-1.0, 1.0),
+            reversed: false,
+            rate: None,
+        }
+    }
+}
+
+pub struct Servo {
+    config: ServoConfig,
+    position: Option<f32>,
+}
+
+impl Servo {
+    pub fn new(config: ServoConfig) -> Self {
+        Self {
+            config,
+            position: None,
+        }
+    }
+
+    pub fn config(&self) -> &ServoConfig {
+        &self.config
+    }
+
+    /// Keeps the current position, so that the servo does not jump.
+    pub fn set_config(&mut self, config: ServoConfig) {
+        self.config = config;
+    }
+
+    /// Returns the servo position `[-1.0 .. 1.0]` for the command `[-1.0 .. 1.0]`. The rate
+    /// limit only applies after the first update.
+    pub fn update(&mut self, command: f32, dt: f32) -> f32 {
+        let config = &self.config;
+        let mut command = command.clamp(-1.0, 1.0);
+        if config.reversed {
+            command = -command;
+        }
+        let (min, max) = config.endpoints;
+        let target = if command >= 0.0 {
+            config.trim + command * (max - config.trim)
+        } else {
+            config.trim + command * (config.trim - min)
+        };
+        let position = match (self.position, config.rate) {
+            (Some(position), Some(rate)) => {
+                position + (target - position).clamp(-rate * dt, rate * dt)
+            }
+            _ => target,
+        };
+        self.position = Some(position);
+        position
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    #[test]
+    fn trim_endpoints_and_reversing() {
+        let mut servo = Servo::new(ServoConfig {
+            trim: 0.1,
+            endpoints: (-0.5, 0.9),
+            ..Default::default()
+        });
+        assert_eq!(servo.update(0.0, DT), 0.1);
+        assert_eq!(servo.update(1.0, DT), 0.9);
+        assert_eq!(servo.update(-1.0, DT), -0.5);
+        assert_eq!(servo.update(-2.0, DT), -0.5);
+        assert!((servo.update(0.5, DT) - 0.5).abs() < 1e-6);
+
+        servo.set_config(ServoConfig {
+            reversed: true,
+            ..*servo.config()
+        });
+        assert_eq!(servo.update(1.0, DT), -0.5);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut servo = Servo::new(ServoConfig {
+            rate: Some(2.0),
+            ..Default::default()
+        });
+        assert_eq!(servo.update(-1.0, DT), -1.0);
+        assert!((servo.update(1.0, DT) - (-1.0 + 2.0 * DT)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn mixing() {
+        let controls = FlightControls {
+            tilt_left: 1.0,
+            tilt_right: -0.1,
+            elevator: 0.5,
+            rudder: -0.2,
+            aileron: 0.75,
+        };
+        assert_eq!(controls.command(ServoFunction::TiltLeft), 1.0);
+        assert_eq!(controls.command(ServoFunction::TiltRight), -0.1);
+        assert_eq!(controls.command(ServoFunction::AileronLeft), -0.75);
+        assert_eq!(controls.command(ServoFunction::ElevonLeft), -0.25);
+        assert_eq!(controls.command(ServoFunction::ElevonRight), 1.0);
+    }
+}
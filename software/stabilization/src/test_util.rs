@@ -0,0 +1,17 @@
+//! Helpers shared by the tests.
+
+use nalgebra::Vector3;
+
+/// Deterministic uniform noise in `-1.0..1.0`.
+pub struct Noise(pub u32);
+
+impl Noise {
+    pub fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (self.0 >> 8) as f32 / (1 << 23) as f32 - 1.0
+    }
+
+    pub fn vector(&mut self, amplitude: f32) -> Vector3<f32> {
+        Vector3::new(self.next(), self.next(), self.next()) * amplitude
+    }
+}
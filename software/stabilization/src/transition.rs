@@ -0,0 +1,328 @@
+//! Transition between hover (rotors vertical) and forward flight (rotors horizontal).
+//!
+//! The forward transition tilts the rotors to an intermediate angle until the vehicle is fast
+//! enough for the wing to carry it, then tilts them fully forward. The back transition tilts them
+//! up again while decelerating. In between, the multicopter attitude control is blended with the
+//! control surfaces depending on the speed. Forward flight needs a speed estimate, without one it
+//! is refused or aborted.
+
+#[derive(Debug, Clone, Copy)]
+pub struct TransitionConfig {
+    /// Tilt `[0.0 .. 1.0]` held during the forward transition until the transition speed.
+    pub transition_tilt: f32,
+    /// Time for tilting from vertical to horizontal [s].
+    pub tilt_time: f32,
+    /// Speed above which the control surfaces start taking over [m/s].
+    pub blend_speed: f32,
+    /// Speed at which the forward transition completes [m/s].
+    pub transition_speed: f32,
+    /// Without a speed estimate, the back transition completes after this time [s].
+    pub open_loop_time: f32,
+    /// The forward transition is aborted if not complete within this time [s].
+    pub forward_timeout: f32,
+    /// Speed below which the back transition completes [m/s].
+    pub hover_speed: f32,
+    /// Deceleration during the back transition [m/s^2].
+    pub deceleration: f32,
+    /// The back transition completes after this time, even if still moving [s].
+    pub back_timeout: f32,
+}
+
+impl Default for TransitionConfig {
+    fn default() -> Self {
+        Self {
+            transition_tilt: 0.5,
+            tilt_time: 3.0,
+            blend_speed: 8.0,
+            transition_speed: 14.0,
+            open_loop_time: 5.0,
+            forward_timeout: 10.0,
+            hover_speed: 3.0,
+            deceleration: 2.0,
+            back_timeout: 15.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransitionState {
+    Hover,
+    Forward,
+    FixedWing,
+    Back,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransitionOutput {
+    /// Rotor tilt `[0.0 .. 1.0]`, 0 for vertical and 1 for horizontal.
+    pub tilt: f32,
+    /// Weight of the fixed-wing control `[0.0 .. 1.0]`, the multicopter control gets the rest.
+    pub fixed_wing: f32,
+    /// Deceleration to be commanded by the multicopter control [m/s^2].
+    pub deceleration: f32,
+}
+
+impl TransitionOutput {
+    /// Blends the multicopter and the fixed-wing command.
+    pub fn blend(&self, multicopter: f32, fixed_wing: f32) -> f32 {
+        multicopter * (1.0 - self.fixed_wing) + fixed_wing * self.fixed_wing
+    }
+}
+
+pub struct TransitionController {
+    config: TransitionConfig,
+    state: TransitionState,
+    tilt: f32,
+    time: f32,
+    /// Set on an abort, forward flight is only requested again after releasing the request.
+    aborted: bool,
+}
+
+impl TransitionController {
+    pub fn new(config: TransitionConfig) -> Self {
+        Self {
+            config,
+            state: TransitionState::Hover,
+            tilt: 0.0,
+            time: 0.0,
+            aborted: false,
+        }
+    }
+
+    pub fn state(&self) -> TransitionState {
+        self.state
+    }
+
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    /// Returns to hover immediately, e.g. while disarmed.
+    pub fn reset(&mut self) {
+        self.state = TransitionState::Hover;
+        self.tilt = 0.0;
+        self.time = 0.0;
+    }
+
+    /// Starts the back transition, e.g. if the attitude cannot be held.
+    pub fn abort(&mut self) {
+        if self.state != TransitionState::Hover {
+            self.aborted = true;
+            self.enter(TransitionState::Back);
+        }
+    }
+
+    /// `forward` requests forward flight, `speed` is the airspeed (or the groundspeed) [m/s],
+    /// if available.
+    pub fn update(&mut self, forward: bool, speed: Option<f32>, dt: f32) -> TransitionOutput {
+        let config = self.config;
+        if !forward {
+            self.aborted = false;
+        }
+        if forward && speed.is_none() {
+            // The wing might not carry the vehicle yet, and nothing would tell.
+            match self.state {
+                TransitionState::Hover => self.aborted = true,
+                TransitionState::Forward | TransitionState::FixedWing => self.abort(),
+                TransitionState::Back => {}
+            }
+        }
+        let forward = forward && !self.aborted;
+        self.time += dt;
+
+        match self.state {
+            TransitionState::Hover if forward => self.enter(TransitionState::Forward),
+            TransitionState::Forward | TransitionState::FixedWing if !forward => {
+                self.enter(TransitionState::Back)
+            }
+            TransitionState::Back if forward => self.enter(TransitionState::Forward),
+            TransitionState::Forward => {
+                let fast = speed.is_some_and(|speed| speed >= config.transition_speed);
+                if fast && self.tilt >= config.transition_tilt {
+                    self.enter(TransitionState::FixedWing);
+                } else if self.time > config.forward_timeout {
+                    self.abort();
+                }
+            }
+            TransitionState::Back => {
+                let slow = match speed {
+                    Some(speed) => speed <= config.hover_speed,
+                    None => self.time >= config.open_loop_time,
+                };
+                if (slow && self.tilt <= 0.0) || self.time > config.back_timeout {
+                    self.enter(TransitionState::Hover);
+                }
+            }
+            _ => {}
+        }
+
+        let target_tilt = match self.state {
+            TransitionState::Hover | TransitionState::Back => 0.0,
+            TransitionState::Forward => config.transition_tilt,
+            TransitionState::FixedWing => 1.0,
+        };
+        let max_step = if config.tilt_time > 0.0 {
+            dt / config.tilt_time
+        } else {
+            1.0
+        };
+        self.tilt += (target_tilt - self.tilt).clamp(-max_step, max_step);
+
+        let open_loop = (self.time / config.open_loop_time).clamp(0.0, 1.0);
+        let fixed_wing = match (self.state, speed) {
+            (TransitionState::Hover, _) => 0.0,
+            (TransitionState::FixedWing, _) => 1.0,
+            (_, Some(speed)) => ((speed - config.blend_speed)
+                / (config.transition_speed - config.blend_speed))
+                .clamp(0.0, 1.0),
+            // Only the back transition runs without a speed estimate.
+            (_, None) => 1.0 - open_loop,
+        };
+        let decelerating = match speed {
+            Some(speed) => speed > config.hover_speed,
+            None => open_loop < 1.0,
+        };
+        TransitionOutput {
+            tilt: self.tilt,
+            fixed_wing,
+            deceleration: if self.state == TransitionState::Back && decelerating {
+                config.deceleration
+            } else {
+                0.0
+            },
+        }
+    }
+
+    fn enter(&mut self, state: TransitionState) {
+        self.state = state;
+        self.time = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.02;
+
+    /// Point mass moving forward, pushed by the tilted rotors and slowed by drag.
+    struct Simulation {
+        speed: f32,
+        /// Horizontal acceleration with the rotors fully tilted [m/s^2].
+        acceleration: f32,
+    }
+
+    impl Simulation {
+        fn step(&mut self, output: &TransitionOutput) {
+            let thrust = self.acceleration * libm::sinf(output.tilt * core::f32::consts::FRAC_PI_2);
+            let drag = 0.02 * self.speed * self.speed;
+            self.speed = (self.speed + (thrust - drag - output.deceleration) * DT).max(0.0);
+        }
+    }
+
+    fn fly(
+        controller: &mut TransitionController,
+        sim: &mut Simulation,
+        forward: bool,
+        seconds: f32,
+    ) -> TransitionOutput {
+        let mut output = controller.update(forward, Some(sim.speed), DT);
+        for _ in 0..(seconds / DT) as u32 {
+            sim.step(&output);
+            output = controller.update(forward, Some(sim.speed), DT);
+        }
+        output
+    }
+
+    #[test]
+    fn forward_and_back() {
+        let mut controller = TransitionController::new(TransitionConfig::default());
+        let mut sim = Simulation {
+            speed: 0.0,
+            acceleration: 8.0,
+        };
+        let output = fly(&mut controller, &mut sim, false, 1.0);
+        assert_eq!(controller.state(), TransitionState::Hover);
+        assert_eq!(output.tilt, 0.0);
+
+        let output = fly(&mut controller, &mut sim, true, 1.0);
+        assert_eq!(controller.state(), TransitionState::Forward);
+        assert!(output.tilt > 0.0 && output.tilt <= 0.5);
+        assert!(output.fixed_wing < 0.5);
+
+        let output = fly(&mut controller, &mut sim, true, 10.0);
+        assert_eq!(controller.state(), TransitionState::FixedWing);
+        assert_eq!(output.tilt, 1.0);
+        assert_eq!(output.fixed_wing, 1.0);
+        assert!(sim.speed >= 14.0);
+
+        let output = fly(&mut controller, &mut sim, false, 0.5);
+        assert_eq!(controller.state(), TransitionState::Back);
+        assert!(output.tilt < 1.0);
+        assert_eq!(output.deceleration, 2.0);
+
+        let output = fly(&mut controller, &mut sim, false, 15.0);
+        assert_eq!(controller.state(), TransitionState::Hover);
+        assert_eq!(output.tilt, 0.0);
+        assert!(sim.speed <= 3.0);
+    }
+
+    #[test]
+    fn aborts_if_too_slow() {
+        let mut controller = TransitionController::new(TransitionConfig::default());
+        let mut sim = Simulation {
+            speed: 0.0,
+            acceleration: 1.0,
+        };
+        fly(&mut controller, &mut sim, true, 10.5);
+        assert_eq!(controller.state(), TransitionState::Back);
+        assert!(controller.aborted());
+
+        // No new attempt until the request is released.
+        let output = fly(&mut controller, &mut sim, true, 20.0);
+        assert_eq!(controller.state(), TransitionState::Hover);
+        assert_eq!(output.tilt, 0.0);
+        controller.update(false, Some(0.0), DT);
+        controller.update(true, Some(0.0), DT);
+        assert_eq!(controller.state(), TransitionState::Forward);
+    }
+
+    #[test]
+    fn needs_speed_estimate() {
+        let mut controller = TransitionController::new(TransitionConfig::default());
+        let output = controller.update(true, None, DT);
+        assert_eq!(controller.state(), TransitionState::Hover);
+        assert!(controller.aborted());
+        assert_eq!(output.tilt, 0.0);
+
+        controller.update(false, Some(0.0), DT);
+        let mut sim = Simulation {
+            speed: 0.0,
+            acceleration: 8.0,
+        };
+        fly(&mut controller, &mut sim, true, 10.0);
+        assert_eq!(controller.state(), TransitionState::FixedWing);
+
+        // Losing the estimate aborts, the back transition completes open loop.
+        let output = controller.update(true, None, DT);
+        assert_eq!(controller.state(), TransitionState::Back);
+        assert!(controller.aborted());
+        assert!(output.fixed_wing > 0.99);
+        let mut output = output;
+        for _ in 0..(5.1 / DT) as u32 {
+            output = controller.update(true, None, DT);
+        }
+        assert_eq!(controller.state(), TransitionState::Hover);
+        assert_eq!(output.fixed_wing, 0.0);
+    }
+
+    #[test]
+    fn blending() {
+        let output = TransitionOutput {
+            tilt: 0.5,
+            fixed_wing: 0.25,
+            deceleration: 0.0,
+        };
+        assert_eq!(output.blend(1.0, -1.0), 0.5);
+    }
+}
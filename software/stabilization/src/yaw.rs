@@ -0,0 +1,111 @@
+//! Yaw control in hover, by motor torque differences and by tilting the rotors differentially.
+//!
+//! The reaction torque of the motors only gives weak yaw authority. Tilting the left rotors
+//! forward and the right rotors backward (or vice versa) turns part of their thrust into a much
+//! stronger yaw torque.
+
+#[derive(Debug, Clone, Copy)]
+pub struct YawConfig {
+    /// Share of the yaw demand that is routed to the differential tilt `[0.0 .. 1.0]`, the rest
+    /// goes to the motor torque.
+    pub tilt_share: f32,
+    /// Differential tilt per side at full yaw demand, in the units of the tilt command.
+    pub max_differential_tilt: f32,
+    /// Motor thrust difference at full yaw demand `[0.0 .. 1.0]`.
+    pub max_motor_yaw: f32,
+    /// Direction of the reaction torque per motor, 1.0 for a propeller turning counter-clockwise
+    /// seen from above (yaws the vehicle right), -1.0 for clockwise.
+    pub motor_directions: [f32; 4],
+}
+
+impl Default for YawConfig {
+    fn default() -> Self {
+        Self {
+            tilt_share: 0.7,
+            max_differential_tilt: 0.15,
+            max_motor_yaw: 0.1,
+            // M1 (right) and M2 (left) counter-clockwise, M3 (front) and M4 (rear) clockwise,
+            // same layout as the hover PD law.
+            motor_directions: [1.0, 1.0, -1.0, -1.0],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct YawOutput {
+    /// Offsets to add to the motor thrust.
+    pub motors: [f32; 4],
+    /// Offsets to add to the tilt commands.
+    pub tilt_left: f32,
+    pub tilt_right: f32,
+}
+
+pub struct YawMixer {
+    config: YawConfig,
+}
+
+impl YawMixer {
+    pub fn new(config: YawConfig) -> Self {
+        Self { config }
+    }
+
+    /// Splits the yaw demand `[-1.0 .. 1.0]` (positive to yaw right) at the given rotor tilt
+    /// (0 for vertical, 1 for horizontal). The differential tilt fades out towards forward
+    /// flight, where it would roll instead of yaw.
+    pub fn update(&self, yaw: f32, tilt: f32) -> YawOutput {
+        let config = &self.config;
+        let yaw = yaw.clamp(-1.0, 1.0);
+        let tilt_share = config.tilt_share.clamp(0.0, 1.0);
+        let effectiveness = libm::cosf(tilt.clamp(0.0, 1.0) * core::f32::consts::FRAC_PI_2);
+
+        // The left rotors tilt forward and the right rotors backward to yaw right.
+        let differential_tilt = yaw * tilt_share * config.max_differential_tilt * effectiveness;
+        let motor_yaw = yaw * (1.0 - tilt_share) * config.max_motor_yaw;
+        YawOutput {
+            motors: config
+                .motor_directions
+                .map(|direction| direction * motor_yaw),
+            tilt_left: differential_tilt,
+            tilt_right: -differential_tilt,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend() {
+        let torque_only = YawMixer::new(YawConfig {
+            tilt_share: 0.0,
+            ..Default::default()
+        });
+        let output = torque_only.update(1.0, 0.0);
+        assert_eq!(output.motors, [0.1, 0.1, -0.1, -0.1]);
+        assert_eq!((output.tilt_left, output.tilt_right), (0.0, 0.0));
+
+        let tilt_only = YawMixer::new(YawConfig {
+            tilt_share: 1.0,
+            ..Default::default()
+        });
+        let output = tilt_only.update(-1.0, 0.0);
+        assert_eq!(output.motors, [0.0; 4]);
+        assert_eq!((output.tilt_left, output.tilt_right), (-0.15, 0.15));
+
+        let mixed = YawMixer::new(YawConfig::default()).update(0.5, 0.0);
+        assert!((mixed.motors[0] - 0.015).abs() < 1e-6);
+        assert!((mixed.tilt_left - 0.0525).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fades_out_in_forward_flight() {
+        let mixer = YawMixer::new(YawConfig::default());
+        let hover = mixer.update(1.0, 0.0);
+        let halfway = mixer.update(1.0, 0.5);
+        let forward = mixer.update(1.0, 1.0);
+        assert!(halfway.tilt_left < hover.tilt_left);
+        assert!(forward.tilt_left.abs() < 1e-6);
+        assert_eq!(forward.motors, hover.motors);
+    }
+}